@@ -0,0 +1,67 @@
+//! Time `stack_layers` decoding a few large layers one at a time and in parallel,
+//! to see when `--jobs-per-image` pays off. Run with `cargo bench --bench stack_layers`.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use pack_creator::helper::{self, LayerModifiers, OnMissingLayer, StackOptions};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Layers of each run and their size, few and large being where parallel decoding helps
+const LAYERS: usize = 4;
+const SIZE: u32 = 2048;
+const RUNS: u32 = 5;
+
+fn stack(folder: &[PathBuf], layers: &[String], jobs: usize) -> Duration {
+    let options = StackOptions {
+        jobs,
+        max_dimension: helper::DEFAULT_MAX_DIMENSION,
+        on_missing: OnMissingLayer::Skip,
+        lint_order: None,
+        keep_images: false,
+    };
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let mut canvas = DynamicImage::new_rgba8(SIZE, SIZE);
+        helper::stack_layers(
+            &mut canvas,
+            folder,
+            layers,
+            &LayerModifiers::default(),
+            &options,
+        );
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let dir = tempfile::tempdir().expect("a temporary folder");
+    let layers: Vec<String> = (0..LAYERS)
+        .map(|i| {
+            let name = format!("layer{i}");
+            RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+                Rgba([(x + i as u32) as u8, y as u8, (x ^ y) as u8, 128])
+            })
+            .save(dir.path().join(format!("{name}.png")))
+            .expect("the layer is written");
+            name
+        })
+        .collect();
+    let folder = [dir.path().to_path_buf()];
+
+    let threads = rayon::current_num_threads();
+    println!(
+        "{} layers of {}x{}, {} thread(s)",
+        LAYERS, SIZE, SIZE, threads
+    );
+    let serial = stack(&folder, &layers, 1);
+    println!("jobs 1: {:?}", serial);
+    for jobs in [2, LAYERS, threads].into_iter().filter(|&jobs| jobs > 1) {
+        let parallel = stack(&folder, &layers, jobs);
+        println!(
+            "jobs {}: {:?} ({:.2}x)",
+            jobs,
+            parallel,
+            serial.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+}
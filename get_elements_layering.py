@@ -50,6 +50,12 @@ def strip_png_recursive(obj):
     else:
         return obj
 
+def is_entry(obj):
+    """An entry is either a list of layers or an object with layer lists."""
+    return isinstance(obj, list) or (
+        isinstance(obj, dict) and any(k in obj for k in ("layers", "below", "above", "offset", "inner"))
+    )
+
 def flatten(obj, prefix=""):
     """Flatten dicts into 'A/B/C': value form."""
     flat = {}
@@ -57,7 +63,7 @@ def flatten(obj, prefix=""):
         for k, v in obj.items():
             k2 = strip_png(k)
             new_key = f"{prefix}/{k2}" if prefix else k2
-            if isinstance(v, dict):
+            if isinstance(v, dict) and not is_entry(v):
                 flat.update(flatten(v, new_key))
             else:
                 flat[new_key] = strip_png_recursive(v)
@@ -92,6 +98,8 @@ for folder in PACK_ROOT.iterdir():
             new_flat[full_key] = [""]
 
 # --- Flatten old JSON for comparison ----------------------------------------
+# Layer aliases are not an element type, keep them as they are
+aliases = old_json.pop("aliases", None)
 old_json = strip_png_recursive(old_json)
 old_flat = flatten(old_json)
 
@@ -110,6 +118,8 @@ for k in old_flat.keys():
 
 # Turn flat back into nested by top-level category
 merged_json = unflatten(merged_flat)
+if aliases is not None:
+    merged_json["aliases"] = aliases
 
 # --- Custom dumper with grouping --------------------------------------------
 def dump_json_grouped(obj, indent=2, level=0, top_level=False):
@@ -120,7 +130,10 @@ def dump_json_grouped(obj, indent=2, level=0, top_level=False):
     - Sorts keys case-insensitively (based on lowercase).
     """
     spaces = ' ' * (indent * level)
-    if isinstance(obj, dict):
+    if isinstance(obj, dict) and not top_level and is_entry(obj):
+        # Object-form entries stay on a single line like the list form
+        return json.dumps(obj)
+    elif isinstance(obj, dict):
         # Sort keys alphabetically ignoring case
         keys = sorted(obj.keys(), key=lambda x: x.lower())
         lines = []
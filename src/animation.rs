@@ -0,0 +1,159 @@
+use crate::helper;
+use image::DynamicImage;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// A numbered layer sequence such as `glow_####`, the run of `#` standing for
+/// the zero-padded frame number. A tint follows after one more `#`: `glow_#####ff0000`,
+/// or after the `~` of a partial tint. Transforms come last and apply to every frame.
+pub struct LayerSequence<'a> {
+    prefix: &'a str,
+    width: usize,
+    tint: Option<&'a str>,
+    transforms: &'a str,
+}
+
+impl<'a> LayerSequence<'a> {
+    /// Parse a layer descriptor, `None` when it isn't a sequence
+    pub fn parse(descriptor: &'a str) -> Option<Self> {
+        let (rest, _) = helper::split_transforms(descriptor);
+        let transforms = &descriptor[rest.len()..];
+        let start = rest.find('#')?;
+        let run = rest[start..].chars().take_while(|&c| c == '#').count();
+        let tint = &rest[start + run..];
+        let sequence = |width, tint| {
+            Some(LayerSequence {
+                prefix: &rest[..start],
+                width,
+                tint,
+                transforms,
+            })
+        };
+
+        // A single `#` followed by a color is a plain tint
+        if tint.is_empty() {
+            sequence(run, None)
+        } else if tint.starts_with('~') {
+            sequence(run, Some(tint))
+        } else if run > 1 {
+            sequence(run - 1, Some(tint))
+        } else {
+            None
+        }
+    }
+
+    /// Descriptor of a single frame, tint and transforms included
+    pub fn frame(&self, number: usize) -> String {
+        let name = format!("{}{:0width$}", self.prefix, number, width = self.width);
+        match self.tint {
+            Some(tint) if tint.starts_with('~') => format!("{name}{tint}{}", self.transforms),
+            Some(tint) => format!("{name}#{tint}{}", self.transforms),
+            None => format!("{name}{}", self.transforms),
+        }
+    }
+
+    /// Descriptors of every existing frame, numbered from 0 or 1 without gaps
+    pub fn frames(&self, layer_folders: &[PathBuf]) -> Vec<String> {
+        let exists = |number: usize| {
+            let name = format!("{}{:0width$}", self.prefix, number, width = self.width);
+            helper::find_layer_path(layer_folders, &name).is_some()
+        };
+        let Some(first) = (0..=1).find(|&n| exists(n)) else {
+            return Vec::new();
+        };
+        (first..)
+            .take_while(|&n| exists(n))
+            .map(|n| self.frame(n))
+            .collect()
+    }
+}
+
+/// Replace every sequence of a layer list by its frame at `index`,
+/// shorter sequences loop. Sequences without frames keep their first frame name
+/// so they are reported as missing.
+pub fn resolve_frame(layers: &[String], layer_folders: &[PathBuf], index: usize) -> Vec<String> {
+    layers
+        .iter()
+        .map(|layer| match LayerSequence::parse(layer) {
+            Some(sequence) => {
+                let frames = sequence.frames(layer_folders);
+                if frames.is_empty() {
+                    sequence.frame(0)
+                } else {
+                    frames[index % frames.len()].clone()
+                }
+            }
+            None => layer.clone(),
+        })
+        .collect()
+}
+
+/// Number of frames of the longest sequence of a layer list, 1 without sequences
+pub fn frame_count(layers: &[String], layer_folders: &[PathBuf]) -> usize {
+    layers
+        .iter()
+        .filter_map(|layer| LayerSequence::parse(layer))
+        .map(|sequence| sequence.frames(layer_folders).len())
+        .max()
+        .unwrap_or(0)
+        .max(1)
+}
+
+/// Encode frames of the same size into a looping animated PNG
+pub fn save_apng(path: &Path, frames: &[DynamicImage], delay_ms: u16) -> Result<(), String> {
+    let first = frames.first().ok_or("No frames to encode")?;
+    let file = File::create(path).map_err(|e| e.to_string())?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), first.width(), first.height());
+    let has_alpha = first.color().has_alpha();
+    encoder.set_color(if has_alpha {
+        png::ColorType::Rgba
+    } else {
+        png::ColorType::Rgb
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(|e| e.to_string())?;
+    encoder
+        .set_frame_delay(delay_ms, 1000)
+        .map_err(|e| e.to_string())?;
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    for frame in frames {
+        let data = if has_alpha {
+            frame.to_rgba8().into_raw()
+        } else {
+            frame.to_rgb8().into_raw()
+        };
+        writer.write_image_data(&data).map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_keep_their_tint_and_transforms() {
+        let frame = |descriptor| LayerSequence::parse(descriptor).map(|s| s.frame(1));
+        assert_eq!(frame("glow_##").as_deref(), Some("glow_01"));
+        assert_eq!(frame("glow_###ff0000").as_deref(), Some("glow_01#ff0000"));
+        assert_eq!(
+            frame("glow_##~ff0000:50").as_deref(),
+            Some("glow_01~ff0000:50")
+        );
+        assert_eq!(
+            frame("glow_##:scale(2)").as_deref(),
+            Some("glow_01:scale(2)")
+        );
+        assert_eq!(
+            frame("glow_###ff0000:scale(2):premul").as_deref(),
+            Some("glow_01#ff0000:scale(2):premul")
+        );
+        assert_eq!(frame("frame#ff0000").as_deref(), None);
+        assert_eq!(frame("frame").as_deref(), None);
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Context;
+use image::DynamicImage;
+use pack_creator::helper;
+use pack_creator::render::{self, RenderOptions, Task};
+use rand::RngCore;
+use std::path::{Path, PathBuf};
+
+/// Path of the sidecar JSON of an atlas, `atlas.json` for `atlas.png`
+pub fn json_path(path: &Path) -> PathBuf {
+    path.with_extension("json")
+}
+
+/// Name and output path of an icon of the atlas
+pub struct Icon {
+    name: String,
+    path: PathBuf,
+}
+
+impl Icon {
+    pub fn new(task: &Task, options: &RenderOptions) -> Self {
+        Icon {
+            name: format!("{}/{}", task.element_type, task.filename),
+            path: render::output_path(task, options),
+        }
+    }
+}
+
+/// Place rectangles on shelves, tallest first, the shelves being as wide as the
+/// widest rectangle or the side of a square of their total area. Rectangles of
+/// the same size are ordered by `rng`. Returns the position of every rectangle
+/// and the exact size of what they cover.
+fn pack(sizes: &[(u32, u32)], rng: &mut impl RngCore) -> (Vec<(u32, u32)>, (u32, u32)) {
+    let area: u64 = sizes.iter().map(|&(w, h)| w as u64 * h as u64).sum();
+    let widest = sizes.iter().map(|&(w, _)| w).max().unwrap_or(0);
+    let limit = widest.max((area as f64).sqrt().ceil() as u32);
+
+    let ties: Vec<u64> = sizes.iter().map(|_| rng.next_u64()).collect();
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| {
+        (
+            std::cmp::Reverse(sizes[i].1),
+            std::cmp::Reverse(sizes[i].0),
+            ties[i],
+        )
+    });
+
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut shelf_height, mut width) = (0, 0, 0, 0);
+    for i in order {
+        let (w, h) = sizes[i];
+        if x > 0 && x + w > limit {
+            y += shelf_height;
+            (x, shelf_height) = (0, 0);
+        }
+        positions[i] = (x, y);
+        x += w;
+        shelf_height = shelf_height.max(h);
+        width = width.max(x);
+    }
+    (positions, (width, y + shelf_height))
+}
+
+/// Pack the written outputs into a single image in three passes: measure the
+/// visible pixels of every output, pack those trimmed rectangles tightly, then
+/// copy them in. Outputs that weren't written or are fully transparent are left out.
+/// The sidecar JSON gives where each icon is and where it was trimmed from.
+/// Icons of the same size are placed in an order drawn from `rng`, seeded by
+/// `--seed`. Returns the number of icons packed.
+pub fn write(icons: &[Icon], path: &Path, rng: &mut impl RngCore) -> anyhow::Result<usize> {
+    let mut icons: Vec<&Icon> = icons.iter().collect();
+    icons.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // Measure
+    let mut trimmed = Vec::new();
+    for icon in icons {
+        let Ok(image) = image::open(&icon.path) else {
+            continue;
+        };
+        if let Some(bounds) = helper::opaque_bounds(&image) {
+            trimmed.push((&icon.name, image, bounds));
+        }
+    }
+
+    // Pack
+    let sizes: Vec<(u32, u32)> = trimmed
+        .iter()
+        .map(|(_, _, (_, _, w, h))| (*w, *h))
+        .collect();
+    let (positions, (width, height)) = pack(&sizes, rng);
+
+    // Render
+    let mut atlas = DynamicImage::new_rgba8(width, height);
+    let mut placed = Vec::with_capacity(trimmed.len());
+    for ((name, image, (trim_x, trim_y, w, h)), (x, y)) in trimmed.iter().zip(positions) {
+        let cropped = image.crop_imm(*trim_x, *trim_y, *w, *h);
+        image::imageops::replace(&mut atlas, &cropped, x as i64, y as i64);
+        placed.push(serde_json::json!({
+            "name": name,
+            "x": x,
+            "y": y,
+            "width": w,
+            "height": h,
+            "trim_x": trim_x,
+            "trim_y": trim_y,
+            "source_width": image.width(),
+            "source_height": image.height(),
+        }));
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    atlas
+        .save(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    let json = serde_json::json!({
+        "width": width,
+        "height": height,
+        "icons": placed,
+    });
+    let json_path = json_path(path);
+    std::fs::write(&json_path, serde_json::to_string_pretty(&json)? + "\n")
+        .with_context(|| format!("Failed to write {}", json_path.display()))?;
+    Ok(placed.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    /// Generator of the atlas for a `--seed`
+    fn rng(seed: u64) -> rand::rngs::StdRng {
+        RenderOptions {
+            seed,
+            ..Default::default()
+        }
+        .rng("atlas")
+    }
+
+    /// 10x10 outputs, each with a visible rectangle of its own size somewhere inside
+    fn icons(dir: &Path) -> Vec<Icon> {
+        [
+            ("a", (1, 2, 4, 4)),
+            ("b", (3, 0, 6, 2)),
+            ("c", (5, 1, 3, 8)),
+        ]
+        .into_iter()
+        .map(|(name, (x, y, w, h))| {
+            let image = RgbaImage::from_fn(10, 10, |px, py| {
+                let inside = (x..x + w).contains(&px) && (y..y + h).contains(&py);
+                Rgba([255, 0, 0, if inside { 255 } else { 0 }])
+            });
+            let path = dir.join(format!("{name}.png"));
+            image.save(&path).unwrap();
+            Icon {
+                name: name.to_string(),
+                path,
+            }
+        })
+        .collect()
+    }
+
+    #[test]
+    fn trimmed_icons_pack_tighter() {
+        let dir = tempfile::tempdir().unwrap();
+        let atlas = dir.path().join("atlas.png");
+        assert_eq!(write(&icons(dir.path()), &atlas, &mut rng(0)).unwrap(), 3);
+
+        let (width, height) = image::open(&atlas).unwrap().dimensions();
+        let (_, (untrimmed_width, untrimmed_height)) = pack(&[(10, 10); 3], &mut rng(0));
+        assert!(width * height <= untrimmed_width * untrimmed_height);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(json_path(&atlas)).unwrap()).unwrap();
+        let b = &json["icons"][1];
+        assert_eq!(b["name"], "b");
+        assert_eq!((&b["width"], &b["height"]), (&6.into(), &2.into()));
+        assert_eq!((&b["trim_x"], &b["trim_y"]), (&3.into(), &0.into()));
+    }
+
+    #[test]
+    fn atlas_is_deterministic() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut icons = icons(dir.path());
+        let (first, second) = (dir.path().join("first.png"), dir.path().join("second.png"));
+        write(&icons, &first, &mut rng(0)).unwrap();
+        icons.reverse();
+        write(&icons, &second, &mut rng(0)).unwrap();
+
+        assert_eq!(
+            std::fs::read(&first).unwrap(),
+            std::fs::read(&second).unwrap()
+        );
+        assert_eq!(
+            std::fs::read(json_path(&first)).unwrap(),
+            std::fs::read(json_path(&second)).unwrap()
+        );
+    }
+
+    #[test]
+    fn seed_orders_icons_of_the_same_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let icons: Vec<Icon> = (0..6)
+            .map(|i| {
+                let path = dir.path().join(format!("{i}.png"));
+                RgbaImage::from_pixel(4, 4, Rgba([i * 40, 0, 0, 255]))
+                    .save(&path)
+                    .unwrap();
+                Icon {
+                    name: i.to_string(),
+                    path,
+                }
+            })
+            .collect();
+        let layout = |seed: u64, name: &str| {
+            let path = dir.path().join(name);
+            write(&icons, &path, &mut rng(seed)).unwrap();
+            (
+                std::fs::read(&path).unwrap(),
+                std::fs::read_to_string(json_path(&path)).unwrap(),
+            )
+        };
+
+        let first = layout(7, "first.png");
+        assert_eq!(layout(7, "again.png"), first);
+        assert_ne!(layout(8, "other.png").1, first.1);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::checksums::{self, Checksum};
+use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Name of the file mapping output names to their path in the store
+pub const INDEX: &str = "index.json";
+
+/// Copy the existing `outputs` into a content-addressed store, each named after the
+/// SHA-256 of its bytes as `ab/cdef….png`, identical outputs being stored once.
+/// `index.json` in the store maps the output paths, relative to the output folder,
+/// to their path in the store. Returns the number of distinct files and of outputs mapped.
+pub fn write(
+    store: &Path,
+    output_folder: &Path,
+    outputs: &[PathBuf],
+) -> anyhow::Result<(usize, usize)> {
+    let mut index = BTreeMap::new();
+    for path in outputs.iter().filter(|path| path.is_file()) {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let hash = Checksum::Sha256.hex_digest(&bytes);
+        let mut name = format!("{}/{}", &hash[..2], &hash[2..]);
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            name = format!("{}.{}", name, extension);
+        }
+
+        let stored_path = store.join(&name);
+        if !stored_path.exists() {
+            std::fs::create_dir_all(store.join(&hash[..2]))?;
+            std::fs::write(&stored_path, &bytes)
+                .with_context(|| format!("Failed to write {}", stored_path.display()))?;
+        }
+        index.insert(checksums::relative_name(path, output_folder), name);
+    }
+
+    let index_path = store.join(INDEX);
+    std::fs::create_dir_all(store)?;
+    std::fs::write(&index_path, serde_json::to_string_pretty(&index)? + "\n")
+        .with_context(|| format!("Failed to write {}", index_path.display()))?;
+    let stored: BTreeSet<&String> = index.values().collect();
+    Ok((stored.len(), index.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_outputs_are_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_folder = dir.path().join("output");
+        let outputs =
+            ["Perks/a.png", "Items/b.png", "Items/c.png"].map(|name| output_folder.join(name));
+        for (path, content) in outputs.iter().zip(["same", "same", "other"]) {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let store = dir.path().join("store");
+        assert_eq!(write(&store, &output_folder, &outputs).unwrap(), (2, 3));
+        let index: BTreeMap<String, String> =
+            serde_json::from_str(&std::fs::read_to_string(store.join(INDEX)).unwrap()).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index["Perks/a.png"], index["Items/b.png"]);
+        assert_ne!(index["Perks/a.png"], index["Items/c.png"]);
+        let stored = store.join(&index["Perks/a.png"]);
+        assert_eq!(std::fs::read_to_string(stored).unwrap(), "same");
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::Context;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Hash of the checksum file written after rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Checksum {
+    #[default]
+    None,
+    Sha256,
+    Md5,
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Checksum::None),
+            "sha256" => Ok(Checksum::Sha256),
+            "md5" => Ok(Checksum::Md5),
+            _ => Err(format!(
+                "Unknown checksum '{}', expected none, sha256 or md5",
+                s
+            )),
+        }
+    }
+}
+
+impl Checksum {
+    /// Name of the file the sums are written to, like coreutils names them
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Checksum::None | Checksum::Sha256 => "SHA256SUMS",
+            Checksum::Md5 => "MD5SUMS",
+        }
+    }
+
+    pub fn hex_digest(&self, bytes: &[u8]) -> String {
+        let digest = match self {
+            Checksum::None | Checksum::Sha256 => Sha256::digest(bytes).to_vec(),
+            Checksum::Md5 => Md5::digest(bytes).to_vec(),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Path of an output relative to the output folder, with forward slashes
+/// so files listing it are usable on every platform
+pub fn relative_name(path: &Path, output_folder: &Path) -> String {
+    path.strip_prefix(output_folder)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Write the sums of the existing `outputs` to the checksum file of the output folder,
+/// in the format `sha256sum -c` and `md5sum -c` read from that folder.
+/// Returns the path of the file and the number of outputs listed.
+pub fn write(
+    checksum: Checksum,
+    output_folder: &Path,
+    outputs: &[PathBuf],
+) -> anyhow::Result<(PathBuf, usize)> {
+    let mut outputs: Vec<&PathBuf> = outputs.iter().filter(|path| path.is_file()).collect();
+    outputs.sort();
+    outputs.dedup();
+
+    let mut sums = String::new();
+    for path in &outputs {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        sums.push_str(&format!(
+            "{}  {}\n",
+            checksum.hex_digest(&bytes),
+            relative_name(path, output_folder)
+        ));
+    }
+
+    let path = output_folder.join(checksum.file_name());
+    std::fs::write(&path, sums).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok((path, outputs.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_match_the_outputs() {
+        assert_eq!(
+            Checksum::Sha256.hex_digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            Checksum::Md5.hex_digest(b"abc"),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let outputs = [dir.path().join("Perks/b.png"), dir.path().join("a.png")];
+        std::fs::create_dir_all(dir.path().join("Perks")).unwrap();
+        std::fs::write(&outputs[0], b"second").unwrap();
+        std::fs::write(&outputs[1], b"first").unwrap();
+        let listed = [&outputs[..], &[dir.path().join("gone.png")]].concat();
+
+        let (path, count) = write(Checksum::Md5, dir.path(), &listed).unwrap();
+        assert_eq!(
+            (path.file_name().unwrap().to_str(), count),
+            (Some("MD5SUMS"), 2)
+        );
+        let sums = std::fs::read_to_string(path).unwrap();
+        let expected: String = [("Perks/b.png", "second"), ("a.png", "first")]
+            .iter()
+            .map(|(name, content)| {
+                let digest = Checksum::Md5.hex_digest(content.as_bytes());
+                format!("{}  {}\n", digest, name)
+            })
+            .collect();
+        assert_eq!(sums, expected);
+    }
+}
@@ -0,0 +1,213 @@
+use colored::Colorize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Whether a folder was not visited yet, recording it. Symlinks are resolved so
+/// a link back to one of its parents is seen once, a warning naming the cycle.
+fn first_visit(folder: &Path, visited: &mut HashSet<PathBuf>) -> bool {
+    let real = std::fs::canonicalize(folder).unwrap_or_else(|_| folder.to_path_buf());
+    let first = visited.insert(real.clone());
+    if !first {
+        log::warn!(
+            "Skipping '{}', a symlink to the already visited '{}'",
+            folder.display(),
+            real.display()
+        );
+    }
+    first
+}
+
+/// Every file under a folder, recursively. With `visited`, symlinked folders are
+/// walked too, each real folder once. Without, symlinks are listed themselves and
+/// never followed, so nothing outside of the folder is reached.
+fn collect_files(
+    folder: &Path,
+    files: &mut Vec<PathBuf>,
+    mut visited: Option<&mut HashSet<PathBuf>>,
+) -> std::io::Result<()> {
+    if let Some(visited) = visited.as_deref_mut()
+        && !first_visit(folder, visited)
+    {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(folder)? {
+        let entry = entry?;
+        let is_dir = match visited {
+            Some(_) => entry.path().is_dir(),
+            None => entry.file_type()?.is_dir(),
+        };
+        if is_dir {
+            collect_files(&entry.path(), files, visited.as_deref_mut())?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Every file under a folder, recursively, sorted. Symlinks are listed themselves,
+/// so removing the files never reaches what they point to.
+pub fn files_under(folder: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_files(folder, &mut files, None)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Every file under a folder, recursively, sorted, symlinked folders like shared
+/// asset folders being walked as if they were there. A link to a folder already
+/// walked, like one of its parents, is skipped with a warning so cycles end.
+pub fn files_under_following_links(folder: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_files(folder, &mut files, Some(&mut HashSet::new()))?;
+    files.sort();
+    Ok(files)
+}
+
+/// Remove the folders under `folder` holding nothing but `stale` files,
+/// keeping `folder` itself. Returns whether `folder` ended up empty.
+fn remove_empty_folders(
+    folder: &Path,
+    stale: &HashSet<PathBuf>,
+    dry_run: bool,
+    removed: &mut Vec<PathBuf>,
+) -> bool {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return false;
+    };
+    let mut empty = true;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            if remove_empty_folders(&path, stale, dry_run, removed)
+                && (dry_run || std::fs::remove_dir(&path).is_ok())
+            {
+                removed.push(path);
+                continue;
+            }
+        } else if stale.contains(&path) {
+            continue;
+        }
+        empty = false;
+    }
+    empty
+}
+
+/// Delete every file of the output folder that isn't expected,
+/// then the folders left empty. Only lists them when `dry_run` is set.
+/// Symlinks are removed themselves, what they point to is left alone.
+pub fn run(expected: &HashSet<PathBuf>, output_folder: &Path, dry_run: bool) -> anyhow::Result<()> {
+    let stale: Vec<PathBuf> = files_under(output_folder)?
+        .into_iter()
+        .filter(|file| !expected.contains(file))
+        .collect();
+
+    let action = if dry_run { "Would remove" } else { "Removed" };
+    let mut removed = 0;
+    for file in &stale {
+        if !dry_run && let Err(e) = std::fs::remove_file(file) {
+            eprintln!(
+                "{}",
+                format!("Failed to remove '{}': {}", file.display(), e).red()
+            );
+            continue;
+        }
+        println!(" - {} {}", action, file.display());
+        removed += 1;
+    }
+
+    let stale: HashSet<PathBuf> = stale.into_iter().collect();
+    let mut folders = Vec::new();
+    remove_empty_folders(output_folder, &stale, dry_run, &mut folders);
+    folders.sort();
+    for folder in &folders {
+        println!(" - {} empty folder {}", action, folder.display());
+    }
+
+    println!(
+        "\n{}",
+        format!(
+            "{} {} stale file(s) and {} empty folder(s)",
+            action,
+            removed,
+            folders.len()
+        )
+        .green()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unexpected_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path();
+        let kept = output.join("Perks/a.png");
+        let stale = [output.join("Perks/old.png"), output.join("Gone/deep/x.png")];
+        for file in stale.iter().chain([&kept]) {
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, "").unwrap();
+        }
+        let expected = HashSet::from([kept.clone()]);
+
+        run(&expected, output, true).unwrap();
+        assert_eq!(files_under(output).unwrap().len(), 3);
+
+        run(&expected, output, false).unwrap();
+        assert_eq!(files_under(output).unwrap(), [kept]);
+        assert!(!output.join("Gone").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_removed_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (output, outside) = (dir.path().join("output"), dir.path().join("outside"));
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("precious.txt"), "").unwrap();
+        std::os::unix::fs::symlink(&outside, output.join("link")).unwrap();
+
+        assert_eq!(files_under(&output).unwrap(), [output.join("link")]);
+        run(&HashSet::new(), &output, false).unwrap();
+        assert!(!output.join("link").exists());
+        assert!(outside.join("precious.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycles_end_the_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+        std::fs::create_dir_all(output.join("Perks")).unwrap();
+        std::fs::write(output.join("Perks/a.png"), "").unwrap();
+        std::os::unix::fs::symlink(&output, output.join("Perks/loop")).unwrap();
+        let shared = dir.path().join("shared");
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("b.png"), "").unwrap();
+        std::fs::write(shared.join("c.png"), "").unwrap();
+        std::os::unix::fs::symlink(&shared, output.join("Shared")).unwrap();
+
+        // Followed, the shared folder is walked and the loop back to the root skipped
+        assert_eq!(
+            files_under_following_links(&output).unwrap(),
+            [
+                output.join("Perks/a.png"),
+                output.join("Shared/b.png"),
+                output.join("Shared/c.png"),
+            ]
+        );
+        std::fs::remove_file(output.join("Shared")).unwrap();
+
+        assert_eq!(
+            files_under(&output).unwrap(),
+            [output.join("Perks/a.png"), output.join("Perks/loop")]
+        );
+        let expected = HashSet::from([output.join("Perks/a.png")]);
+        run(&expected, &output, false).unwrap();
+        assert_eq!(files_under(&output).unwrap(), [output.join("Perks/a.png")]);
+    }
+}
@@ -0,0 +1,640 @@
+use crate::checksums::Checksum;
+use clap::{CommandFactory, Parser, Subcommand};
+use pack_creator::config::{LayerFallback, OutputFormat, ResizeFilter};
+use pack_creator::helper::{self, ColorRemap, OnMissingLayer};
+use pack_creator::label::Corner;
+use pack_creator::render::{BitDepth, Dither, Flatten, Mirror, OutputColor};
+use pack_creator::watermark::WatermarkMode;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Builds a Dead by Daylight icon pack by layering the source icons
+/// as described in elements_layering.json and settings.json
+#[derive(Parser, Debug)]
+#[command(version, about, subcommand_precedence_over_arg = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Element types to render, e.g. "Perks", whatever their case [default: all of them]
+    #[arg(value_name = "ELEMENT", value_parser = parse_element_type)]
+    pub element_types: Vec<String>,
+
+    /// Render every pack of a JSON list of
+    /// `{ "settings", "layering", "source", "output" }` objects, in order
+    #[arg(long, global = true, value_name = "PATH")]
+    pub batch: Option<std::path::PathBuf>,
+
+    /// Layering file to read instead of elements_layering.json, repeatable to merge
+    /// several in order, or a folder of them read in name order. An entry given again
+    /// replaces the earlier one, or fails with --strict. Packs of --batch use their own.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub layering: Vec<std::path::PathBuf>,
+
+    /// Stop --batch at the first pack that fails instead of rendering the others,
+    /// and fail on element types given as arguments that the configuration doesn't have,
+    /// on entries given again by --layering files and on outputs over --max-output-bytes
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Element types missing from settings.json use the folder of the same name here
+    #[arg(long, global = true, value_name = "DIR")]
+    pub layer_root: Option<std::path::PathBuf>,
+
+    /// Layer folder for element types missing from settings.json and the layer root,
+    /// "" for no layers
+    #[arg(long, global = true, value_name = "DIR")]
+    pub default_layer_folder: Option<String>,
+
+    /// JSON file mapping preset names to a tint and transforms, layers written
+    /// `frame=preset:NAME` getting those of the preset, e.g. `{ "gold": "#ffd700:scale(1.1)" }`
+    #[arg(long, global = true, value_name = "PATH")]
+    pub presets: Option<std::path::PathBuf>,
+
+    /// Gitignore-style file of `element/filename` patterns to skip
+    /// [default: .deadbydaylightignore in the input folder]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub ignore_file: Option<std::path::PathBuf>,
+
+    /// Row-major 3x3 matrix applied to the RGB of every output, e.g.
+    /// "0.393,0.769,0.189,0.349,0.686,0.168,0.272,0.534,0.131" for sepia
+    #[arg(long, global = true, value_name = "M", value_parser = parse_color_matrix, allow_hyphen_values = true)]
+    pub color_matrix: Option<[f32; 9]>,
+
+    /// Replace a color by another in every output once everything else is drawn,
+    /// e.g. "#ff0000=#ff8800", repeatable
+    #[arg(long, global = true, value_name = "FROM=TO")]
+    pub remap: Vec<ColorRemap>,
+
+    /// Also replace colors of --remap whose channels are at most this far from it
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    pub remap_tolerance: u8,
+
+    /// Quantize each RGB channel of every output to this many evenly spaced levels,
+    /// from 2 to 256, once everything else is drawn
+    #[arg(long, global = true, value_name = "LEVELS")]
+    pub posterize: Option<u16>,
+
+    /// Posterize the alpha channel too
+    #[arg(long, global = true, requires = "posterize")]
+    pub posterize_alpha: bool,
+
+    /// Decode the layers of a single image with up to this many threads,
+    /// useful for few images with many large layers
+    #[arg(long, global = true, value_name = "N", default_value_t = 1)]
+    pub jobs_per_image: usize,
+
+    /// What to do with layers that can't be found: skip them, draw a magenta
+    /// checkerboard instead (placeholder), or fail the image (error)
+    #[arg(long, global = true, value_name = "MODE", default_value = "skip")]
+    pub on_missing_layer: OnMissingLayer,
+
+    /// Write every source and layer file the configuration needs as JSON,
+    /// with whether each exists, instead of rendering
+    #[arg(long, global = true, value_name = "PATH")]
+    pub list_assets: Option<std::path::PathBuf>,
+
+    /// Decode every source and layer file before rendering and report its color type
+    /// and bit depth, stopping if any can't be read or isn't 8-bit RGB(A) or grayscale
+    #[arg(long, global = true)]
+    pub verify_inputs: bool,
+
+    /// Render a single entry and print the RGBA of one pixel, e.g. "Perks/iconPerks_adrenaline:32,32"
+    #[arg(long, global = true, value_name = "ELEMENT/NAME:X,Y", value_parser = parse_pixel_sample)]
+    pub sample_pixel: Option<PixelSample>,
+
+    /// Image used in place of source images that can't be opened,
+    /// they are still reported as skipped
+    #[arg(long, global = true, value_name = "PATH")]
+    pub placeholder: Option<std::path::PathBuf>,
+
+    /// Solid color the canvas starts with instead of "transparent",
+    /// as HEX, `hsl(H,S%,L%)` or `hsv(H,S%,V%)`
+    #[arg(long, global = true, value_name = "COLOR", value_parser = parse_canvas_fill)]
+    pub canvas_fill: Option<CanvasFill>,
+
+    /// Image drawn on every canvas under all of its layers and the item, over
+    /// --canvas-fill, stretched to the canvas when their sizes differ
+    #[arg(long, global = true, value_name = "PATH")]
+    pub global_background: Option<std::path::PathBuf>,
+
+    /// Render entries with numbered layer sequences (`glow_####`) as animated PNGs
+    /// showing each frame for this many milliseconds
+    #[arg(long, global = true, value_name = "MS")]
+    pub animate: Option<u16>,
+
+    /// Crop outputs to their visible pixels then pad them with this many transparent
+    /// pixels on every side, before --size
+    #[arg(long, global = true, value_name = "PX", value_parser = parse_margin)]
+    pub normalize_margin: Option<u32>,
+
+    /// Resize outputs to fit a square of this size [default: `defaults.output_size` in settings.json]
+    #[arg(long, global = true, value_name = "PX")]
+    pub size: Option<u32>,
+
+    /// Zero-pad the number output names end with to this many digits,
+    /// e.g. `addon_1` is written as `addon_001` with 3
+    #[arg(long, global = true, value_name = "WIDTH")]
+    pub pad_numbers: Option<usize>,
+
+    /// Write each output at the path of its source image relative to the input folder,
+    /// instead of under the last folder of its element type or its `output_name`
+    #[arg(long, global = true)]
+    pub mirror_tree: bool,
+
+    /// Output format: png, tiff or jpeg [default: `defaults.format` in settings.json, else png]
+    #[arg(long, global = true, value_name = "FORMAT")]
+    pub format: Option<OutputFormat>,
+
+    /// Filter used by --size: nearest, triangle, catmullrom, gaussian or lanczos3
+    /// [default: `defaults.filter` in settings.json, else lanczos3]
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub filter: Option<ResizeFilter>,
+
+    /// Skip source images and layers wider or taller than this many pixels
+    /// instead of decoding them
+    #[arg(long, global = true, value_name = "PX", default_value_t = helper::DEFAULT_MAX_DIMENSION)]
+    pub max_dimension: u32,
+
+    /// Regular expression finding the rarity in entry names, its first group naming
+    /// the palette color of the layers tinted `#rarity`, e.g. "rare" in `perk_name_rare`
+    #[arg(long, global = true, value_name = "REGEX", default_value = helper::DEFAULT_RARITY_PATTERN, value_parser = parse_regex)]
+    pub rarity_pattern: regex::Regex,
+
+    /// Render at most this many images at once, limiting memory use on large packs
+    #[arg(long, global = true, value_name = "N")]
+    pub chunk_size: Option<usize>,
+
+    /// Render the tasks of every element type in turn rather than one element type
+    /// after the other, so small element types don't wait for the large ones
+    #[arg(long, global = true)]
+    pub interleave: bool,
+
+    /// Don't write outputs that are fully transparent, listing them in the summary instead
+    #[arg(long, global = true)]
+    pub skip_empty: bool,
+
+    /// Darken the corners of outputs by this much, from 0 (no change) to 1 (black),
+    /// fading in smoothly from the center
+    #[arg(long, global = true, value_name = "STRENGTH")]
+    pub vignette: Option<f32>,
+
+    /// Sharpen outputs after resizing with an unsharp mask of this sigma, e.g. 0.5
+    #[arg(long, global = true, value_name = "SIGMA")]
+    pub sharpen: Option<f32>,
+
+    /// Smallest brightness difference --sharpen enhances, higher values keep flat areas smooth
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    pub sharpen_threshold: i32,
+
+    /// Only render the tasks whose source image or a layer file was modified after this
+    /// RFC 3339 time, e.g. 2024-05-01T12:00:00Z, or after this file was
+    #[arg(long, global = true, value_name = "TIME|FILE", value_parser = parse_since)]
+    pub only_changed_since: Option<SystemTime>,
+
+    /// Skip the tasks this manifest lists with unchanged inputs and outputs, then record
+    /// the rendered outputs in it. Created on first use.
+    #[arg(long, global = true, value_name = "MANIFEST")]
+    pub resume: Option<std::path::PathBuf>,
+
+    /// Stop once more than N images were skipped, which usually means the input folder is wrong
+    #[arg(long, global = true, value_name = "N")]
+    pub max_skips: Option<usize>,
+
+    /// Stop once rendering has been running for this many seconds, the images being
+    /// rendered finishing and the others being dropped, and fail the run
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub timeout: Option<f64>,
+
+    /// Write every output at each of these comma-separated scales of --size, e.g. 1,2.
+    /// Scale 1 keeps the usual name, others are suffixed like `name@2x.png`.
+    #[arg(long, global = true, value_name = "SCALES", value_delimiter = ',')]
+    pub scales: Vec<f32>,
+
+    /// Also lay the outputs of each element type out left to right in `<element>.strip.png`,
+    /// in cells of this many pixels, with their offsets in `<element>.strip.json`
+    #[arg(long, global = true, value_name = "CELL", value_parser = parse_cell)]
+    pub strip: Option<u32>,
+
+    /// Also pack the outputs, trimmed to their visible pixels, into this PNG after
+    /// rendering, with where each one is in a JSON file of the same name
+    #[arg(long, global = true, value_name = "PNG")]
+    pub atlas: Option<std::path::PathBuf>,
+
+    /// Write the hashes of every output to SHA256SUMS or MD5SUMS in the output folder
+    /// after rendering, to check them with `sha256sum -c`: none, sha256 or md5
+    #[arg(long, global = true, value_name = "HASH", default_value = "none")]
+    pub checksums: Checksum,
+
+    /// Warn about outputs whose file is larger than this many bytes and list them in
+    /// the summary, failing the run with --strict
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub max_output_bytes: Option<u64>,
+
+    /// Also copy every output into this content-addressed folder after rendering,
+    /// named after its SHA-256 as `ab/cdef….png` so identical outputs are stored once,
+    /// with `index.json` mapping output names to those files
+    #[arg(long, global = true, value_name = "DIR")]
+    pub cas_output: Option<std::path::PathBuf>,
+
+    /// Also write the whole output folder to this gzip-compressed tar after rendering,
+    /// keeping its folder structure
+    #[arg(long, global = true, value_name = "PATH")]
+    pub tar_gz: Option<std::path::PathBuf>,
+
+    /// Compare every output with the one at the same place in this reference pack
+    /// after rendering, writing difference heatmaps, blue where they match and red
+    /// where they differ most, and listing the most changed outputs
+    #[arg(long, global = true, value_name = "DIR")]
+    pub heatmap_against: Option<std::path::PathBuf>,
+
+    /// Folder the heatmaps of --heatmap-against are written to
+    #[arg(long, global = true, value_name = "DIR", default_value = "Heatmaps")]
+    pub heatmap_folder: std::path::PathBuf,
+
+    /// How many of the most changed outputs --heatmap-against lists
+    #[arg(long, global = true, value_name = "N", default_value_t = 10)]
+    pub heatmap_top: usize,
+
+    /// Write PNGs with pinned encoder settings, fully transparent pixels being made
+    /// transparent black, so identical visible pixels always give byte-identical files
+    #[arg(long, global = true)]
+    pub reproducible: bool,
+
+    /// Composite each distinct stack of layers under the items once, reusing it for every
+    /// item sharing it. Uses memory for every distinct stack.
+    #[arg(long, global = true)]
+    pub cache_stacks: bool,
+
+    /// Warn about layers that fully cover more than PERCENT of the visible pixels drawn
+    /// before them, which usually means they are listed in the wrong order
+    #[arg(long, global = true, value_name = "PERCENT", num_args = 0..=1, default_missing_value = "90")]
+    pub lint_order: Option<f32>,
+
+    /// Seed of randomized choices, like the order of same-sized icons in the --atlas,
+    /// the same seed always giving the same outputs
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    pub seed: u64,
+
+    /// Channels of the outputs: rgba, or rgb to flatten them over --flatten
+    #[arg(long, global = true, value_name = "COLOR", default_value = "rgba")]
+    pub output_color: OutputColor,
+
+    /// Bits per channel of the canvas, the layers and the outputs: 8, or 16 to keep
+    /// the depth of 16-bit source art in PNG and TIFF outputs. Difference layers are
+    /// still worked out at 8 bits.
+    #[arg(long, global = true, value_name = "BITS", default_value = "8")]
+    pub bit_depth: BitDepth,
+
+    /// How 8-bit outputs are reduced: none, or floyd-steinberg to composite at 16 bits
+    /// and diffuse the rounding error over the finished image, turning the banding of
+    /// smooth gradients into fine noise. Can't be combined with --color-matrix, --vignette,
+    /// --sharpen, --remap, --watermark and --debug-labels, which work at 8 bits.
+    #[arg(long, global = true, value_name = "METHOD", default_value = "none")]
+    pub dither: Dither,
+
+    /// Backdrop of transparent pixels in RGB and JPEG outputs: white, black, color:HEX
+    /// or checker [default: --canvas-fill, or white without one]
+    #[arg(long, global = true, value_name = "BACKDROP")]
+    pub flatten: Option<Flatten>,
+
+    /// Flip every finished output: none, horizontal or vertical
+    #[arg(long, global = true, value_name = "MIRROR", default_value = "none")]
+    pub mirror: Mirror,
+
+    /// Image drawn over every output, e.g. a "PREVIEW" banner
+    #[arg(long, global = true, value_name = "PATH")]
+    pub watermark: Option<std::path::PathBuf>,
+
+    /// Layout of --watermark: centered, or tiled to cover the whole output
+    #[arg(long, global = true, value_name = "MODE", default_value = "centered")]
+    pub watermark_mode: WatermarkMode,
+
+    /// Opacity of --watermark, from 0 to 1
+    #[arg(long, global = true, value_name = "ALPHA", default_value_t = 1.0)]
+    pub watermark_opacity: f32,
+
+    /// Resize --watermark to fit each output, keeping its aspect ratio
+    #[arg(long, global = true)]
+    pub watermark_fit: bool,
+
+    /// Draw the name of each output in one of its corners, for debugging
+    #[arg(long, global = true)]
+    pub debug_labels: bool,
+
+    /// Color of --debug-labels, as HEX, `hsl(...)` or `hsv(...)`
+    #[arg(long, global = true, value_name = "COLOR", default_value = "ffffff", value_parser = helper::parse_color)]
+    pub label_color: (u8, u8, u8),
+
+    /// Corner of --debug-labels: top-left, top-right, bottom-left or bottom-right
+    #[arg(
+        long,
+        global = true,
+        value_name = "CORNER",
+        default_value = "bottom-left"
+    )]
+    pub label_corner: Corner,
+
+    /// Size in pixels of a pixel of the 3x5 font of --debug-labels, text of a
+    /// --label-font being 6 times as tall
+    #[arg(long, global = true, value_name = "N", default_value_t = 1)]
+    pub label_scale: u32,
+
+    /// TrueType or OpenType font of --debug-labels, instead of the built-in
+    /// uppercase pixel font
+    #[arg(long, global = true, value_name = "FILE")]
+    pub label_font: Option<std::path::PathBuf>,
+
+    /// Most detailed messages shown: off, error, warn, info, debug or trace
+    /// [default: RUST_LOG, or warn without it]
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<log::LevelFilter>,
+
+    /// Comma-separated warnings to keep out of the console, still counted in the summary:
+    /// skipped-image, missing-layer, empty-output, unmatched-glob or oversized-output
+    #[arg(long, global = true, value_name = "CATEGORIES", value_delimiter = ',')]
+    pub suppress: Vec<Warning>,
+
+    /// Print every element type of the configuration with its number of entries and
+    /// its layer folders, and whether each exists, instead of rendering
+    #[arg(long, global = true)]
+    pub list_element_types: bool,
+
+    /// Draw a swatch, the name and the HEX of every named color into this PNG
+    /// instead of rendering
+    #[arg(long, global = true, value_name = "PNG")]
+    pub palette_legend: Option<std::path::PathBuf>,
+
+    /// Print the options in effect as JSON instead of rendering
+    #[arg(long, global = true)]
+    pub print_config: bool,
+
+    /// Print the time spent decoding, compositing and saving, summed over every thread
+    #[arg(long, global = true)]
+    pub timings: bool,
+
+    /// Write a Chrome trace of every phase and task to this file, to open in
+    /// chrome://tracing or Perfetto
+    #[cfg(feature = "profile")]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub profile: Option<std::path::PathBuf>,
+
+    /// Try downloads of remote items and layers that fail this many more times,
+    /// waiting longer each time. Those still failing use --placeholder for items
+    /// and --on-missing-layer for layers, and are listed in the summary.
+    #[cfg(feature = "remote")]
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    pub retries: u32,
+
+    /// Print the summary as a single line of JSON after the rest of the output
+    #[arg(long, global = true)]
+    pub summary_json_stdout: bool,
+
+    /// Also write every drawn layer of each output on its own, tinted and transformed,
+    /// to `DIR/<element>/<name>/NN_<layer>.png`, for debugging
+    #[arg(long, global = true, value_name = "DIR")]
+    pub export_layers: Option<std::path::PathBuf>,
+
+    /// Write a `<name>.layers.txt` next to each output listing the applied layers
+    #[arg(long, global = true)]
+    pub trace_layers: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Check both configuration files and every referenced file without rendering,
+    /// exiting with a non-zero code if anything is wrong
+    Validate,
+    /// Delete the files of the output folder that the configuration no longer produces,
+    /// then the folders left empty
+    Clean {
+        /// Only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print how one entry is going to be rendered, its source, layers and outputs
+    /// along with whether each file is found, without rendering it
+    Explain {
+        #[arg(value_name = "ELEMENT/NAME", value_parser = parse_entry_name)]
+        entry: (String, String),
+    },
+    /// Convert a CSV with `element`, `filename` and `layers` columns, layers being
+    /// separated by `;`, into the format of elements_layering.json
+    ImportCsv {
+        csv: std::path::PathBuf,
+        /// File to write, the JSON is printed without one
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Write the per-pixel difference of two images, black where they match,
+    /// e.g. to compare two versions of a pack
+    DiffImage {
+        a: std::path::PathBuf,
+        b: std::path::PathBuf,
+        output: std::path::PathBuf,
+    },
+}
+
+/// Keep an element type given as an argument, refusing the names of commands
+/// as they would be run instead
+fn parse_element_type(s: &str) -> Result<String, String> {
+    if Cli::command().find_subcommand(s).is_some() {
+        return Err(format!("'{}' is a command, not an element type", s));
+    }
+    Ok(s.to_string())
+}
+
+/// Compile a regular expression
+fn parse_regex(s: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(s).map_err(|e| e.to_string())
+}
+
+/// Parse a margin, at most half of the largest image side
+fn parse_margin(s: &str) -> Result<u32, String> {
+    let margin: u32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid margin '{}'", s))?;
+    if margin > helper::DEFAULT_MAX_DIMENSION / 2 {
+        return Err(format!(
+            "Margin {} is larger than {} pixels",
+            margin,
+            helper::DEFAULT_MAX_DIMENSION / 2
+        ));
+    }
+    Ok(margin)
+}
+
+/// Parse the side of a strip cell, at least one pixel and at most the largest image side
+fn parse_cell(s: &str) -> Result<u32, String> {
+    let cell: u32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid cell size '{}'", s))?;
+    if cell == 0 || cell > helper::DEFAULT_MAX_DIMENSION {
+        return Err(format!(
+            "Cell size must be between 1 and {} pixels",
+            helper::DEFAULT_MAX_DIMENSION
+        ));
+    }
+    Ok(cell)
+}
+
+/// Parse 9 comma-separated floats into a 3x3 color matrix
+fn parse_color_matrix(s: &str) -> Result<[f32; 9], String> {
+    let values = s
+        .split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid matrix value '{}'", v))
+        })
+        .collect::<Result<Vec<f32>, String>>()?;
+    values
+        .try_into()
+        .map_err(|v: Vec<f32>| format!("Expected 9 values, got {}", v.len()))
+}
+
+/// Parse `element/name` into the element type and the entry name,
+/// the name may itself contain `/`
+fn parse_entry_name(s: &str) -> Result<(String, String), String> {
+    let (element_type, filename) = s.split_once('/').ok_or("Expected ELEMENT/NAME")?;
+    Ok((element_type.to_string(), filename.to_string()))
+}
+
+/// Parse an RFC 3339 time, or take the modification time of a file
+fn parse_since(s: &str) -> Result<SystemTime, String> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(time.into());
+    }
+    std::fs::metadata(s)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|_| {
+            format!(
+                "Expected an RFC 3339 time like 2024-05-01T12:00:00Z or an existing file, got '{}'",
+                s
+            )
+        })
+}
+
+/// A pixel of a rendered entry to print
+#[derive(Debug, Clone)]
+pub struct PixelSample {
+    pub element_type: String,
+    pub filename: String,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Parse `element/name:x,y`, the name may itself contain `/`
+fn parse_pixel_sample(s: &str) -> Result<PixelSample, String> {
+    let (target, coords) = s.rsplit_once(':').ok_or("Expected ELEMENT/NAME:X,Y")?;
+    let (element_type, filename) = target
+        .split_once('/')
+        .ok_or("Expected ELEMENT/NAME before ':'")?;
+    let (x, y) = coords.split_once(',').ok_or("Expected X,Y after ':'")?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid coordinate '{}'", v))
+    };
+
+    Ok(PixelSample {
+        element_type: element_type.to_string(),
+        filename: filename.to_string(),
+        x: parse(x)?,
+        y: parse(y)?,
+    })
+}
+
+/// Category of the warnings --suppress silences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// Source images that can't be opened, including those replaced by --placeholder
+    SkippedImage,
+    MissingLayer,
+    EmptyOutput,
+    /// `glob:` layers matching no file
+    UnmatchedGlob,
+    /// Outputs larger than --max-output-bytes
+    OversizedOutput,
+}
+
+impl FromStr for Warning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "skipped-image" => Ok(Warning::SkippedImage),
+            "missing-layer" => Ok(Warning::MissingLayer),
+            "empty-output" => Ok(Warning::EmptyOutput),
+            "unmatched-glob" => Ok(Warning::UnmatchedGlob),
+            "oversized-output" => Ok(Warning::OversizedOutput),
+            _ => Err(format!(
+                "Unknown warning '{}', expected skipped-image, missing-layer, empty-output, unmatched-glob or oversized-output",
+                s
+            )),
+        }
+    }
+}
+
+impl Cli {
+    /// Whether warnings of this category are shown
+    pub fn reports(&self, warning: Warning) -> bool {
+        !self.suppress.contains(&warning)
+    }
+}
+
+/// Solid color of the canvas, `None` when transparent
+#[derive(Debug, Clone, Copy)]
+pub struct CanvasFill(pub Option<(u8, u8, u8)>);
+
+/// Parse a color, "transparent" meaning no fill
+fn parse_canvas_fill(s: &str) -> Result<CanvasFill, String> {
+    if s.eq_ignore_ascii_case("transparent") {
+        return Ok(CanvasFill(None));
+    }
+    helper::parse_color(s).map(|rgb| CanvasFill(Some(rgb)))
+}
+
+impl Cli {
+    /// Layer folders of element types missing from settings.json
+    pub fn layer_fallback(&self) -> LayerFallback<'_> {
+        LayerFallback {
+            layer_root: self.layer_root.as_deref(),
+            default_folder: self.default_layer_folder.as_deref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn render_options_reach_subcommands() {
+        for args in [
+            ["", "--format", "jpeg", "--scales", "1,2", "clean"],
+            ["", "clean", "--format", "jpeg", "--scales", "1,2"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert!(matches!(cli.command, Some(Command::Clean { .. })));
+            assert!(cli.element_types.is_empty());
+            assert_eq!(cli.format, Some(OutputFormat::Jpeg));
+            assert_eq!(cli.scales, [1.0, 2.0]);
+        }
+    }
+
+    #[test]
+    fn subcommands_are_not_element_types() {
+        let cli = Cli::try_parse_from(["", "Perks", "validate"]).unwrap();
+        assert_eq!(cli.element_types, ["Perks"]);
+        assert!(matches!(cli.command, Some(Command::Validate)));
+        assert!(Cli::try_parse_from(["", "--", "clean"]).is_err());
+    }
+}
@@ -0,0 +1,851 @@
+use crate::helper::{self, LayerModifiers, Platform};
+use anyhow::Context;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use image::imageops::FilterType;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub type GameFolders = HashMap<String, HashMap<String, Entry>>;
+
+/// Content of settings.json, either the legacy form mapping element types to
+/// layer folders in `layers_location` or the structured form with a `folders`
+/// section and global `defaults`. Both sections may be given, `folders` wins.
+#[derive(Deserialize)]
+#[serde(from = "RawSettings")]
+pub struct Settings {
+    pub folders: HashMap<String, FolderConfig>,
+    pub output_path: Option<String>,
+    pub input_path: Option<String>,
+    pub defaults: GlobalDefaults,
+}
+
+#[derive(Deserialize)]
+struct RawSettings {
+    #[serde(default)]
+    layers_location: HashMap<String, LayerFolders>,
+    #[serde(default)]
+    folders: HashMap<String, FolderConfig>,
+    output_path: Option<String>,
+    input_path: Option<String>,
+    #[serde(default)]
+    defaults: GlobalDefaults,
+}
+
+impl From<RawSettings> for Settings {
+    fn from(raw: RawSettings) -> Self {
+        let mut folders: HashMap<String, FolderConfig> = raw
+            .layers_location
+            .into_iter()
+            .map(|(element_type, layers)| (element_type, FolderConfig::from(layers)))
+            .collect();
+        folders.extend(raw.folders);
+
+        Settings {
+            folders,
+            output_path: raw.output_path,
+            input_path: raw.input_path,
+            defaults: raw.defaults,
+        }
+    }
+}
+
+/// Settings of a single element type, a bare folder or list of folders
+/// being shorthand for `{ "layers": ... }`
+#[derive(Deserialize, Clone)]
+#[serde(from = "RawFolderConfig")]
+pub struct FolderConfig {
+    pub layers: LayerFolders,
+    /// Tint of every layer without one of its own
+    pub layer_tint: Option<String>,
+    /// Opacity every layer is composited with, from 0.0 to 1.0
+    pub layer_opacity: Option<f32>,
+    /// Multiplies the alpha of finished outputs, from 0.0 to 1.0
+    pub opacity: Option<f32>,
+    /// Name of the output folder, the last component of the element type otherwise
+    pub output_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawFolderConfig {
+    Layers(LayerFolders),
+    Detailed {
+        layers: LayerFolders,
+        #[serde(default)]
+        layer_tint: Option<String>,
+        #[serde(default)]
+        layer_opacity: Option<f32>,
+        #[serde(default)]
+        opacity: Option<f32>,
+        #[serde(default)]
+        output_name: Option<String>,
+    },
+}
+
+impl From<RawFolderConfig> for FolderConfig {
+    fn from(raw: RawFolderConfig) -> Self {
+        match raw {
+            RawFolderConfig::Layers(layers) => FolderConfig::from(layers),
+            RawFolderConfig::Detailed {
+                layers,
+                layer_tint,
+                layer_opacity,
+                opacity,
+                output_name,
+            } => FolderConfig {
+                layers,
+                layer_tint,
+                layer_opacity,
+                opacity,
+                output_name,
+            },
+        }
+    }
+}
+
+impl From<LayerFolders> for FolderConfig {
+    fn from(layers: LayerFolders) -> Self {
+        FolderConfig {
+            layers,
+            layer_tint: None,
+            layer_opacity: None,
+            opacity: None,
+            output_name: None,
+        }
+    }
+}
+
+/// Global options of settings.json, overridden by the matching command line flags
+#[derive(Deserialize, Default, Clone)]
+pub struct GlobalDefaults {
+    /// Outputs are resized to fit a square of this size
+    pub output_size: Option<u32>,
+    pub format: Option<OutputFormat>,
+    pub filter: Option<ResizeFilter>,
+}
+
+/// Image format outputs are written in
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Tiff,
+    Jpeg,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Tiff => "tiff",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn image_format(self) -> image::ImageFormat {
+        match self {
+            OutputFormat::Png => image::ImageFormat::Png,
+            OutputFormat::Tiff => image::ImageFormat::Tiff,
+            OutputFormat::Jpeg => image::ImageFormat::Jpeg,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "tiff" | "tif" => Ok(OutputFormat::Tiff),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            _ => Err(format!(
+                "Unknown format '{}', expected png, tiff or jpeg",
+                s
+            )),
+        }
+    }
+}
+
+/// Filter used when resizing outputs
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(ResizeFilter::Nearest),
+            "triangle" => Ok(ResizeFilter::Triangle),
+            "catmullrom" => Ok(ResizeFilter::CatmullRom),
+            "gaussian" => Ok(ResizeFilter::Gaussian),
+            "lanczos3" => Ok(ResizeFilter::Lanczos3),
+            _ => Err(format!(
+                "Unknown filter '{}', expected nearest, triangle, catmullrom, gaussian or lanczos3",
+                s
+            )),
+        }
+    }
+}
+
+/// One layer folder or a list of them, searched in order so the first
+/// folder holding a layer wins (e.g. per-element overrides before shared assets)
+#[derive(Deserialize, Clone)]
+#[serde(from = "RawLayerFolders")]
+pub struct LayerFolders(pub Vec<String>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawLayerFolders {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<RawLayerFolders> for LayerFolders {
+    fn from(raw: RawLayerFolders) -> Self {
+        match raw {
+            RawLayerFolders::One(folder) => LayerFolders(vec![folder]),
+            RawLayerFolders::Many(folders) => LayerFolders(folders),
+        }
+    }
+}
+
+/// How element types missing from settings.json find their layers
+#[derive(Default, Clone, Copy)]
+pub struct LayerFallback<'a> {
+    /// Root searched for a folder named after the element type
+    pub layer_root: Option<&'a Path>,
+    /// Used when nothing else matches, an empty string meaning no layers
+    pub default_folder: Option<&'a str>,
+}
+
+/// Where the layer folders of an element type come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FolderSource {
+    Settings,
+    LayerRoot,
+    Default,
+    /// No settings entry nor fallback, the working directory is used
+    Missing,
+}
+
+impl Settings {
+    /// Resolve the layer folders of an element type, in search order.
+    /// Element types without a settings entry use the folder of the same name
+    /// under the layer root if it exists, then the default folder when given,
+    /// otherwise the working directory.
+    pub fn layer_folders(
+        &self,
+        element_type: &str,
+        fallback: LayerFallback,
+        platform: Platform,
+    ) -> (Vec<PathBuf>, FolderSource) {
+        let Some(configured) = self.folders.get(element_type).map(|f| &f.layers) else {
+            if let Some(root) = fallback.layer_root {
+                let auto = helper::resolve_full_path(&root.join(element_type));
+                if auto.is_dir() {
+                    return (vec![auto], FolderSource::LayerRoot);
+                }
+            }
+            return match fallback.default_folder {
+                Some("") => (Vec::new(), FolderSource::Default),
+                Some(default) => (
+                    vec![helper::resolve_full_path(Path::new(default))],
+                    FolderSource::Default,
+                ),
+                None => (
+                    vec![helper::resolve_full_path(&PathBuf::new())],
+                    FolderSource::Missing,
+                ),
+            };
+        };
+
+        let folders: Vec<PathBuf> = configured
+            .0
+            .iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| helper::resolve_full_path(&PathBuf::from(s)))
+            .filter(|p| helper::is_path_compatible(p, platform) && p.exists())
+            .collect();
+
+        if folders.is_empty() {
+            (
+                vec![helper::resolve_full_path(&PathBuf::new())],
+                FolderSource::Settings,
+            )
+        } else {
+            (folders, FolderSource::Settings)
+        }
+    }
+}
+
+impl Settings {
+    /// Modifiers applied to every layer of an element type,
+    /// a tint in the layer descriptor taking precedence over the element tint
+    pub fn layer_defaults(&self, element_type: &str) -> LayerModifiers {
+        match self.folders.get(element_type) {
+            Some(folder) => LayerModifiers {
+                tint: folder.layer_tint.clone(),
+                opacity: folder.layer_opacity.unwrap_or(1.0),
+            },
+            None => LayerModifiers::default(),
+        }
+    }
+
+    /// Output folder name of an element type, if renamed
+    pub fn output_name(&self, element_type: &str) -> Option<&str> {
+        self.folders
+            .get(element_type)
+            .and_then(|folder| folder.output_name.as_deref())
+    }
+
+    /// Opacity of the outputs of an element type, 1.0 unless set
+    pub fn opacity(&self, element_type: &str) -> f32 {
+        self.folders
+            .get(element_type)
+            .and_then(|folder| folder.opacity)
+            .unwrap_or(1.0)
+    }
+}
+
+/// A pack of a batch file, its files defaulting to the usual ones of the working directory
+#[derive(Deserialize)]
+pub struct BatchJob {
+    #[serde(default = "BatchJob::default_settings")]
+    pub settings: PathBuf,
+    #[serde(default = "BatchJob::default_layering")]
+    pub layering: PathBuf,
+    /// Used instead of `input_path` of the settings
+    pub source: Option<PathBuf>,
+    /// Used instead of `output_path` of the settings
+    pub output: Option<PathBuf>,
+}
+
+impl BatchJob {
+    fn default_settings() -> PathBuf {
+        PathBuf::from("settings.json")
+    }
+
+    fn default_layering() -> PathBuf {
+        PathBuf::from("elements_layering.json")
+    }
+}
+
+impl Default for BatchJob {
+    fn default() -> Self {
+        BatchJob {
+            settings: BatchJob::default_settings(),
+            layering: BatchJob::default_layering(),
+            source: None,
+            output: None,
+        }
+    }
+}
+
+/// Load a batch file, a JSON list of packs
+pub fn load_batch(path: &Path) -> anyhow::Result<Vec<BatchJob>> {
+    let file = File::open(path).with_context(|| format!("{} must exist", path.display()))?;
+    serde_json::from_reader(file).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Load settings.json
+pub fn load_settings(path: &Path) -> anyhow::Result<Settings> {
+    let file = File::open(path).with_context(|| format!("{} must exist", path.display()))?;
+    serde_json::from_reader(file).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Content of elements_layering.json, element types alongside
+/// an optional `aliases` map of short tokens to layer names
+#[derive(Deserialize)]
+struct LayeringFile {
+    #[serde(default)]
+    aliases: HashMap<String, String>,
+    #[serde(flatten)]
+    elements: GameFolders,
+}
+
+/// Load elements_layering.json, expanding `$alias` layers
+pub fn load_layering(path: &Path) -> anyhow::Result<GameFolders> {
+    let file = File::open(path).with_context(|| format!("{} must exist", path.display()))?;
+    let mut layering: LayeringFile = serde_json::from_reader(file)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    for (element_type, entries) in layering.elements.iter_mut() {
+        for (entry_name, entry) in entries.iter_mut() {
+            for layer in entry.layers.iter_mut().chain(entry.above.iter_mut()) {
+                *layer = expand_alias(layer, &layering.aliases).with_context(|| {
+                    format!("In {}/{} of {}", element_type, entry_name, path.display())
+                })?;
+            }
+        }
+    }
+    Ok(layering.elements)
+}
+
+/// Load every layering file in order, the `.json` files of a folder in name order,
+/// and merge their element types. An entry given again replaces the earlier one,
+/// or fails with `strict`.
+pub fn load_layerings(paths: &[PathBuf], strict: bool) -> anyhow::Result<GameFolders> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut jsons: Vec<PathBuf> = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|file| {
+                file.is_file()
+                    && file
+                        .extension()
+                        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+            })
+            .collect();
+        anyhow::ensure!(!jsons.is_empty(), "No layering file in {}", path.display());
+        jsons.sort();
+        files.extend(jsons);
+    }
+
+    let mut merged = GameFolders::new();
+    for file in &files {
+        for (element_type, entries) in load_layering(file)? {
+            let merged_entries = merged.entry(element_type.clone()).or_default();
+            for (entry_name, entry) in entries {
+                if merged_entries.insert(entry_name.clone(), entry).is_some() {
+                    anyhow::ensure!(
+                        !strict,
+                        "{}/{} of {} is already in an earlier layering file",
+                        element_type,
+                        entry_name,
+                        file.display()
+                    );
+                    log::info!(
+                        "{}/{} of {} replaces the one of an earlier layering file",
+                        element_type,
+                        entry_name,
+                        file.display()
+                    );
+                }
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Replace the `$alias` layer names of every alternative of a layer by the layer
+/// they stand for, keeping their tint, transforms and preset
+fn expand_alias(layer: &str, aliases: &HashMap<String, String>) -> anyhow::Result<String> {
+    let alternatives = helper::layer_alternatives(layer)
+        .into_iter()
+        .map(|alternative| {
+            let descriptor = alternative
+                .split_once("=preset:")
+                .map_or(alternative, |(descriptor, _)| descriptor);
+            let base = helper::layer_base_name(descriptor);
+            let Some(token) = base.strip_prefix('$') else {
+                return Ok(alternative.to_string());
+            };
+            let name = aliases
+                .get(token)
+                .with_context(|| format!("Unknown layer alias '${}'", token))?;
+            Ok(format!("{}{}", name, &alternative[base.len()..]))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(alternatives.join("|"))
+}
+
+/// Shared tints and transforms of a `--presets` file, by name, e.g.
+/// `{ "goldframe": "#ffd700:scale(1.1)" }`
+pub type Presets = HashMap<String, String>;
+
+/// Load the presets layers reference as `name=preset:NAME`
+pub fn load_presets(path: &Path) -> anyhow::Result<Presets> {
+    let file = File::open(path).with_context(|| format!("{} must exist", path.display()))?;
+    serde_json::from_reader(file).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Replace the `=preset:NAME` of every layer by the tint and transforms of the preset
+pub fn expand_presets(data: &mut GameFolders, presets: &Presets) -> anyhow::Result<()> {
+    for (element_type, entries) in data.iter_mut() {
+        for (entry_name, entry) in entries.iter_mut() {
+            for layer in entry.layers.iter_mut().chain(entry.above.iter_mut()) {
+                *layer = expand_preset(layer, presets)
+                    .with_context(|| format!("In {}/{}", element_type, entry_name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the `=preset:NAME` a layer ends with by what the preset stands for
+fn expand_preset(layer: &str, presets: &Presets) -> anyhow::Result<String> {
+    let Some((name, preset)) = layer.split_once("=preset:") else {
+        return Ok(layer.to_string());
+    };
+    let expansion = presets.get(preset).with_context(|| {
+        if presets.is_empty() {
+            format!("Unknown preset '{}', no --presets file was given", preset)
+        } else {
+            format!("Unknown preset '{}'", preset)
+        }
+    })?;
+    Ok(format!("{}{}", name, expansion))
+}
+
+/// Load a gitignore-style file whose patterns are matched against `element/filename`.
+/// A missing file means nothing is ignored.
+pub fn load_ignore_file(path: &Path, root: &Path) -> anyhow::Result<Option<Gitignore>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(e) = builder.add(path) {
+        return Err(e).with_context(|| format!("Failed to parse {}", path.display()));
+    }
+    let ignore = builder
+        .build()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(ignore))
+}
+
+/// A single element of elements_layering.json, either a plain list of layers
+/// or an object carrying the layers alongside free-form notes.
+/// The object form may also list layers to draw over the item in `above`,
+/// `below` being an alias of `layers`, move the item with `offset` and fit it
+/// into the `inner` rectangle of a frame.
+#[derive(Deserialize, Default, Clone)]
+#[serde(from = "RawEntry")]
+pub struct Entry {
+    /// Layers drawn under the item
+    pub layers: Vec<String>,
+    /// Layers drawn over the item
+    pub above: Vec<String>,
+    pub meta: BTreeMap<String, serde_json::Value>,
+    /// Position of the item on the canvas, which may be negative
+    pub offset: Offset,
+    /// Area the item is resized to fit and centered in, keeping its aspect ratio
+    pub inner: Option<Rect>,
+}
+
+/// Area of the canvas given as `{ "x": ..., "y": ..., "w": ..., "h": ... }`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    #[serde(default)]
+    pub x: i64,
+    #[serde(default)]
+    pub y: i64,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// Position given as `{ "x": ..., "y": ... }`, each defaulting to 0
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Offset {
+    #[serde(default)]
+    pub x: i64,
+    #[serde(default)]
+    pub y: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawEntry {
+    Legacy(Vec<LayerRef>),
+    Detailed(DetailedEntry),
+}
+
+#[derive(Deserialize)]
+struct DetailedEntry {
+    #[serde(default, alias = "below")]
+    layers: Vec<LayerRef>,
+    #[serde(default)]
+    above: Vec<LayerRef>,
+    #[serde(default)]
+    meta: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    offset: Offset,
+    inner: Option<Rect>,
+}
+
+/// A layer given either by name or as `{ "name": ..., "z": ... }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LayerRef {
+    Name(String),
+    Indexed { name: String, z: i64 },
+}
+
+/// Sort layers by their z-index, lowest first.
+/// A layer given by name uses its position in the list as z-index,
+/// and layers sharing a z-index keep their order from the list.
+fn order_layers(layers: Vec<LayerRef>) -> Vec<String> {
+    let mut indexed: Vec<(i64, String)> = layers
+        .into_iter()
+        .enumerate()
+        .map(|(position, layer)| match layer {
+            LayerRef::Name(name) => (position as i64, name),
+            LayerRef::Indexed { name, z } => (z, name),
+        })
+        .collect();
+    indexed.sort_by_key(|(z, _)| *z);
+    indexed.into_iter().map(|(_, name)| name).collect()
+}
+
+impl From<RawEntry> for Entry {
+    fn from(raw: RawEntry) -> Self {
+        match raw {
+            RawEntry::Legacy(layers) => Entry {
+                layers: order_layers(layers),
+                ..Default::default()
+            },
+            RawEntry::Detailed(detailed) => Entry {
+                layers: order_layers(detailed.layers),
+                above: order_layers(detailed.above),
+                meta: detailed.meta,
+                offset: detailed.offset,
+                inner: detailed.inner,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_layering(json: &str) -> GameFolders {
+        serde_json::from_str::<LayeringFile>(json).unwrap().elements
+    }
+
+    #[test]
+    fn entries_are_lists_or_objects() {
+        let data = parse_layering(
+            r#"{
+                "Perks": {
+                    "a": ["bg", "frame#ff0000"],
+                    "b": { "layers": ["bg"], "meta": { "author": "me", "note": 2 } }
+                }
+            }"#,
+        );
+        let perks = &data["Perks"];
+        assert_eq!(perks["a"].layers, ["bg", "frame#ff0000"]);
+        assert!(perks["a"].meta.is_empty());
+        assert_eq!(perks["b"].layers, ["bg"]);
+        assert_eq!(perks["b"].meta["author"], "me");
+        assert_eq!(perks["b"].meta["note"], 2);
+    }
+
+    #[test]
+    fn layers_are_sorted_by_z() {
+        let data = parse_layering(
+            r#"{
+                "Perks": {
+                    "a": [{ "name": "top", "z": 5 }, "bg", { "name": "under", "z": -1 }],
+                    "b": [{ "name": "first", "z": 1 }, "second", { "name": "third", "z": 1 }]
+                }
+            }"#,
+        );
+        assert_eq!(data["Perks"]["a"].layers, ["under", "bg", "top"]);
+        // Ties keep the order of the list, `second` being at position 1
+        assert_eq!(data["Perks"]["b"].layers, ["first", "second", "third"]);
+    }
+
+    fn parse_settings(json: serde_json::Value) -> Settings {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn unlisted_element_types_use_the_default_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let listed = dir.path().join("perks");
+        std::fs::create_dir(&listed).unwrap();
+        let settings =
+            parse_settings(serde_json::json!({ "layers_location": { "Perks": listed } }));
+        let default = dir.path().join("shared");
+        let fallback = LayerFallback {
+            layer_root: None,
+            default_folder: default.to_str(),
+        };
+        let platform = helper::detect_platform();
+
+        assert_eq!(
+            settings.layer_folders("Perks", fallback, platform),
+            (vec![listed], FolderSource::Settings)
+        );
+        assert_eq!(
+            settings.layer_folders("Items", fallback, platform),
+            (vec![default.clone()], FolderSource::Default)
+        );
+        let no_layers = LayerFallback {
+            default_folder: Some(""),
+            ..fallback
+        };
+        assert_eq!(
+            settings.layer_folders("Items", no_layers, platform),
+            (Vec::new(), FolderSource::Default)
+        );
+    }
+
+    #[test]
+    fn layer_root_folders_are_found() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("Perks")).unwrap();
+        let settings = parse_settings(serde_json::json!({}));
+        let fallback = LayerFallback {
+            layer_root: Some(dir.path()),
+            default_folder: None,
+        };
+        let platform = helper::detect_platform();
+
+        assert_eq!(
+            settings.layer_folders("Perks", fallback, platform),
+            (vec![dir.path().join("Perks")], FolderSource::LayerRoot)
+        );
+        // Reported as using the working directory
+        let (_, source) = settings.layer_folders("Items", fallback, platform);
+        assert_eq!(source, FolderSource::Missing);
+    }
+
+    #[test]
+    fn settings_are_legacy_or_structured() {
+        let legacy = parse_settings(serde_json::json!({
+            "layers_location": { "Perks": "perks", "Items": ["override", "items"] },
+            "output_path": "out",
+        }));
+        assert_eq!(legacy.folders["Perks"].layers.0, ["perks"]);
+        assert_eq!(legacy.folders["Items"].layers.0, ["override", "items"]);
+        assert_eq!(legacy.output_path.as_deref(), Some("out"));
+        assert_eq!(legacy.defaults.output_size, None);
+
+        let structured = parse_settings(serde_json::json!({
+            "layers_location": { "Perks": "old" },
+            "folders": {
+                "Perks": { "layers": "perks", "layer_tint": "#ff0000", "opacity": 0.5 },
+                "Items": "items",
+            },
+            "defaults": { "output_size": 256, "format": "jpeg", "filter": "nearest" },
+        }));
+        let perks = &structured.folders["Perks"];
+        assert_eq!(perks.layers.0, ["perks"]);
+        assert_eq!(perks.layer_tint.as_deref(), Some("#ff0000"));
+        assert_eq!(perks.opacity, Some(0.5));
+        assert_eq!(structured.folders["Items"].layers.0, ["items"]);
+        assert_eq!(structured.defaults.output_size, Some(256));
+        assert_eq!(structured.defaults.format, Some(OutputFormat::Jpeg));
+        assert_eq!(structured.defaults.filter, Some(ResizeFilter::Nearest));
+    }
+
+    #[test]
+    fn entries_list_layers_below_and_above() {
+        let data = parse_layering(
+            r#"{ "Perks": { "a": { "below": ["bg", "frame"], "above": ["glow"] } } }"#,
+        );
+        assert_eq!(data["Perks"]["a"].layers, ["bg", "frame"]);
+        assert_eq!(data["Perks"]["a"].above, ["glow"]);
+    }
+
+    #[test]
+    fn aliases_are_expanded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layering.json");
+        let layering = serde_json::json!({
+            "aliases": { "gold": "frames/gold_frame" },
+            "Perks": { "a": { "layers": ["$gold#ff0000", "bg"], "above": ["$gold"] } },
+        });
+        std::fs::write(&path, layering.to_string()).unwrap();
+        let data = load_layering(&path).unwrap();
+        assert_eq!(
+            data["Perks"]["a"].layers,
+            ["frames/gold_frame#ff0000", "bg"]
+        );
+        assert_eq!(data["Perks"]["a"].above, ["frames/gold_frame"]);
+
+        let aliases = HashMap::from([("gold".to_string(), "frames/gold_frame".to_string())]);
+        let expand = |layer: &str| expand_alias(layer, &aliases).unwrap();
+        assert_eq!(expand("$gold:scale(1.1)"), "frames/gold_frame:scale(1.1)");
+        assert_eq!(
+            expand("$gold#ff0000:if(coverage<0.3)"),
+            "frames/gold_frame#ff0000:if(coverage<0.3)"
+        );
+        assert_eq!(
+            expand("$gold=preset:shiny"),
+            "frames/gold_frame=preset:shiny"
+        );
+        assert_eq!(expand("$gold|bg"), "frames/gold_frame|bg");
+        assert_eq!(
+            expand("bg:auto(a|b)|$gold#00ff00"),
+            "bg:auto(a|b)|frames/gold_frame#00ff00"
+        );
+        assert!(expand_alias("bg|$silver", &aliases).is_err());
+
+        let layering = serde_json::json!({ "Perks": { "a": ["$silver"] } });
+        std::fs::write(&path, layering.to_string()).unwrap();
+        let Err(error) = load_layering(&path) else {
+            panic!("an unknown alias must fail");
+        };
+        let error = format!("{:#}", error);
+        assert!(error.contains("Unknown layer alias '$silver'"), "{}", error);
+        assert!(error.contains("Perks/a"), "{}", error);
+    }
+
+    #[test]
+    fn layerings_are_merged_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("layering");
+        std::fs::create_dir(&folder).unwrap();
+        std::fs::write(
+            folder.join("1_perks.json"),
+            r#"{ "Perks": { "a": ["bg"], "b": ["bg"] } }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            folder.join("2_items.json"),
+            r#"{ "Items": { "c": ["bg"] } }"#,
+        )
+        .unwrap();
+        let late = dir.path().join("late.json");
+        std::fs::write(&late, r#"{ "Perks": { "b": ["frame"] } }"#).unwrap();
+
+        let merged = load_layerings(std::slice::from_ref(&folder), true).unwrap();
+        let mut element_types: Vec<&String> = merged.keys().collect();
+        element_types.sort();
+        assert_eq!(element_types, ["Items", "Perks"]);
+        assert_eq!(merged["Perks"].len(), 2);
+
+        // The later file wins, unless duplicates are an error
+        let paths = [folder, late];
+        let merged = load_layerings(&paths, false).unwrap();
+        assert_eq!(merged["Perks"]["b"].layers, ["frame"]);
+        assert_eq!(merged["Perks"]["a"].layers, ["bg"]);
+        let Err(error) = load_layerings(&paths, true) else {
+            panic!("a duplicate entry is an error when strict");
+        };
+        assert!(error.to_string().contains("Perks/b"), "{}", error);
+    }
+}
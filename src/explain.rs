@@ -0,0 +1,266 @@
+use colored::Colorize;
+use pack_creator::animation::LayerSequence;
+use pack_creator::helper::{self, BlendMode, ItemVariables};
+use pack_creator::render::{self, RenderOptions, Task};
+use std::io::Write;
+
+/// How a file reference resolved
+fn status(found: bool) -> colored::ColoredString {
+    if found {
+        "found".green()
+    } else {
+        "missing".red()
+    }
+}
+
+/// Describe one layer descriptor: every file it stands for and what is applied to it
+fn explain_layer(
+    out: &mut dyn Write,
+    task: &Task,
+    descriptor: &str,
+    variables: Option<&ItemVariables>,
+) -> std::io::Result<()> {
+    writeln!(out, " - {}", descriptor)?;
+
+    if helper::layer_alternatives(descriptor).len() > 1 {
+        let chosen = helper::resolve_fallback(descriptor, &task.layer_folders);
+        writeln!(out, "   alternative used: {}", chosen)?;
+    }
+    let descriptor = helper::resolve_fallback(descriptor, &task.layer_folders);
+
+    // Frames of a sequence share their tint and transforms
+    let first_frame = LayerSequence::parse(descriptor).map(|sequence| sequence.frame(0));
+    let (rest, transforms) = helper::split_transforms(first_frame.as_deref().unwrap_or(descriptor));
+    if let Some(spec) = transforms.condition {
+        let verdict = match (helper::parse_condition(spec), variables) {
+            (Err(e), _) => e.red(),
+            (Ok(condition), Some(variables)) if condition.holds(variables) => "drawn".green(),
+            (Ok(_), Some(_)) => "not drawn".yellow(),
+            (Ok(_), None) => "unknown without the source image".yellow(),
+        };
+        writeln!(out, "   condition: {} ({})", spec, verdict)?;
+    }
+    if let Some(factor) = transforms.scale {
+        match helper::parse_scale(factor) {
+            Ok(factor) => writeln!(out, "   scale: {}", factor)?,
+            Err(e) => writeln!(out, "   scale: {}", e.red())?,
+        }
+    }
+    if transforms.blend == BlendMode::Difference {
+        writeln!(out, "   blend: difference")?;
+    }
+    if transforms.premultiplied {
+        writeln!(out, "   premultiplied alpha")?;
+    }
+
+    let (name, tint) = helper::split_tint(rest);
+    match (tint, &task.layer_defaults.tint) {
+        (Some(tint), _) => writeln!(out, "   tint: {}", tint)?,
+        (None, Some(tint)) => writeln!(out, "   tint: {} (element default)", tint)?,
+        (None, None) => {}
+    }
+    if let Some(mask) = transforms.mask {
+        match helper::find_layer_path(&task.layer_folders, mask) {
+            Some(path) => writeln!(out, "   tint mask: {} ({})", path.display(), status(true))?,
+            None => writeln!(out, "   tint mask: {} ({})", mask, status(false))?,
+        }
+    }
+    if task.layer_defaults.opacity != 1.0 {
+        writeln!(out, "   opacity: {}", task.layer_defaults.opacity)?;
+    }
+
+    if let Some(border) = helper::parse_generated(name) {
+        match border {
+            Ok(_) => writeln!(out, "   generated on the canvas")?,
+            Err(e) => writeln!(out, "   generated: {}", e.red())?,
+        }
+        return Ok(());
+    }
+    if name.is_empty() || name == "none" {
+        writeln!(out, "   no file")?;
+        return Ok(());
+    }
+
+    // Sequences show every frame and automatic layers every candidate
+    let files = match LayerSequence::parse(descriptor) {
+        Some(sequence) => {
+            let frames = sequence.frames(&task.layer_folders);
+            if frames.is_empty() {
+                vec![sequence.frame(0)]
+            } else {
+                frames
+            }
+        }
+        None => helper::layer_choices(descriptor)
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+    };
+    for file in files {
+        let base_name = helper::layer_base_name(&file);
+        match helper::find_layer_path(&task.layer_folders, base_name) {
+            Some(path) => writeln!(out, "   {} ({})", path.display(), status(true))?,
+            None => match task.layer_folders.first() {
+                Some(folder) => writeln!(
+                    out,
+                    "   {} ({})",
+                    helper::force_png_path(folder, base_name).display(),
+                    status(false)
+                )?,
+                None => writeln!(out, "   {} ({})", base_name, status(false))?,
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Print how a task is going to be rendered, from its source image to its outputs,
+/// without rendering it
+pub fn run(out: &mut dyn Write, task: &Task, options: &RenderOptions) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{}",
+        format!("{}/{}", task.element_type, task.filename).bold()
+    )?;
+
+    let source = render::source_path(task, &options.source_folder);
+    let item = helper::open_image(&source, options.max_dimension);
+    writeln!(
+        out,
+        "Source: {} ({})",
+        source.display(),
+        status(item.is_ok())
+    )?;
+    if let Err(e) = &item {
+        writeln!(out, "  {}", e.red())?;
+    }
+    if let Some(tint) = &task.item_tint {
+        writeln!(out, "Item tint: {}", tint)?;
+    }
+    if let Some(rect) = task.item_inner {
+        writeln!(
+            out,
+            "Item fitted in: {}x{} at {}, {}",
+            rect.w, rect.h, rect.x, rect.y
+        )?;
+    }
+    if task.item_offset.x != 0 || task.item_offset.y != 0 {
+        writeln!(
+            out,
+            "Item offset: {}, {}",
+            task.item_offset.x, task.item_offset.y
+        )?;
+    }
+
+    let layers = [task.layers.as_slice(), &task.above_layers].concat();
+    let canvas = match &item {
+        Ok(item) => Some((item.width(), item.height(), "source image")),
+        Err(_) => helper::layer_canvas_size(&task.layer_folders, &layers)
+            .map(|(width, height)| (width, height, "layers")),
+    };
+    match canvas {
+        Some((width, height, from)) => {
+            writeln!(out, "Canvas: {}x{} (from the {})", width, height, from)?
+        }
+        None => writeln!(out, "Canvas: {}", "unknown".red())?,
+    }
+
+    let variables = item.as_ref().ok().map(ItemVariables::measure);
+    if let Some(variables) = &variables {
+        writeln!(out, "Coverage: {:.3}", variables.coverage)?;
+    }
+
+    if task.layer_folders.is_empty() {
+        writeln!(out, "Layer folders: none, layers are not drawn")?;
+    } else {
+        writeln!(out, "Layer folders, by priority:")?;
+        for folder in &task.layer_folders {
+            writeln!(out, " - {}", folder.display())?;
+        }
+    }
+    for (heading, layers) in [
+        ("Layers under the item", &task.layers),
+        ("Layers over the item", &task.above_layers),
+    ] {
+        if layers.is_empty() {
+            continue;
+        }
+        writeln!(out, "{}:", heading)?;
+        for descriptor in layers {
+            explain_layer(out, task, descriptor, variables.as_ref())?;
+        }
+    }
+
+    if task.opacity != 1.0 {
+        writeln!(out, "Output opacity: {}", task.opacity)?;
+    }
+    let output = render::output_path(task, options);
+    writeln!(out, "Outputs:")?;
+    for scale in options.output_scales() {
+        writeln!(
+            out,
+            " - {}",
+            render::scaled_output_path(&output, scale).display()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn layers_are_listed_with_their_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let layers = dir.path().join("layers");
+        std::fs::create_dir_all(&layers).unwrap();
+        RgbaImage::from_pixel(2, 2, Rgba([255; 4]))
+            .save(layers.join("bg.png"))
+            .unwrap();
+        let task = Task {
+            element_type: "Perks".to_string(),
+            filename: "a".to_string(),
+            layers: vec!["bg".to_string(), "gone#ff0000:scale(2)".to_string()],
+            above_layers: vec!["frame:gen(1,#000000,0)".to_string()],
+            layer_folders: vec![layers.clone()],
+            ..Default::default()
+        };
+        let options = RenderOptions {
+            source_folder: dir.path().join("source"),
+            output_folder: dir.path().join("output"),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        run(&mut out, &task, &options).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let line_after = |text: &str| {
+            let mut lines = out.lines().skip_while(|line| !line.ends_with(text));
+            lines.nth(1).unwrap_or_default().to_string()
+        };
+
+        assert!(out.contains("Canvas: 2x2 (from the layers)"), "{}", out);
+        assert!(
+            line_after("Layers under the item:").ends_with(" - bg"),
+            "{}",
+            out
+        );
+        let bg = line_after(" - bg");
+        assert!(bg.contains(&layers.join("bg.png").display().to_string()));
+        assert!(bg.contains("found"), "{}", out);
+        assert!(out.contains("   scale: 2\n   tint: ff0000\n"), "{}", out);
+        let gone = format!("   {} (", layers.join("gone.png").display());
+        let gone = out.lines().find(|line| line.starts_with(&gone)).unwrap();
+        assert!(gone.contains("missing"), "{}", out);
+        assert!(line_after("Layers over the item:").ends_with("frame:gen(1,#000000,0)"));
+        assert!(out.contains("   generated on the canvas\n"), "{}", out);
+        let output = options.output_folder.join("Perks/a.png");
+        assert!(
+            out.ends_with(&format!("Outputs:\n - {}\n", output.display())),
+            "{}",
+            out
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::Context;
+use colored::Colorize;
+use pack_creator::helper;
+use pack_creator::render::RenderOptions;
+use std::path::{Path, PathBuf};
+
+/// Compare every written output with the file at the same place in the `reference`
+/// pack, write their heatmaps as PNG under the same relative paths in `folder` and
+/// print the `top` most changed ones
+pub fn write(
+    outputs: &[PathBuf],
+    options: &RenderOptions,
+    reference: &Path,
+    folder: &Path,
+    top: usize,
+) -> anyhow::Result<()> {
+    let open = |path: &Path| {
+        helper::open_image(path, options.max_dimension)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    };
+
+    let mut changes = Vec::new();
+    let mut unmatched = 0;
+    for output in outputs.iter().filter(|path| path.is_file()) {
+        let Ok(relative) = output.strip_prefix(&options.output_folder) else {
+            continue;
+        };
+        let counterpart = reference.join(relative);
+        if !counterpart.is_file() {
+            unmatched += 1;
+            continue;
+        }
+        let (heatmap, change) =
+            match helper::difference_heatmap(&open(output)?, &open(&counterpart)?) {
+                Ok(heatmap) => heatmap,
+                Err(e) => {
+                    log::warn!("Cannot compare {}: {}", relative.display(), e);
+                    continue;
+                }
+            };
+        let path = folder.join(relative).with_extension("png");
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        heatmap
+            .save(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        changes.push((change, relative.display().to_string()));
+    }
+
+    println!(
+        "{}",
+        format!(
+            "{} heatmap(s) written to {}, {} output(s) not in the reference pack",
+            changes.len(),
+            folder.display(),
+            unmatched
+        )
+        .green()
+    );
+    changes.retain(|(change, _)| *change > 0.0);
+    if changes.is_empty() {
+        println!("{}", "No output changed".green());
+        return Ok(());
+    }
+    changes.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    println!(
+        "{}",
+        format!("Most changed outputs ({} changed):", changes.len()).yellow()
+    );
+    for (change, name) in changes.iter().take(top) {
+        println!(" - {} ({:.2}%)", name, change * 100.0);
+    }
+    Ok(())
+}
@@ -1,5 +1,22 @@
+use crate::timings::{self, Stage};
 use image::{DynamicImage, ImageBuffer, Rgba, imageops::overlay};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Gray values below this are left untouched when tinting a mask
+pub const TINT_THRESHOLD: u8 = 37;
+
+/// Largest width or height of an image that will be decoded
+pub const DEFAULT_MAX_DIMENSION: u32 = 16384;
+
+/// Tint of layers colored after the rarity in the name of the entry
+pub const RARITY_TINT: &str = "rarity";
+
+/// Default pattern finding the rarity at the end of a file name, `perk_name_rare`
+pub const DEFAULT_RARITY_PATTERN: &str = "_(common|uncommon|rare|very_rare|ultra_rare|event)$";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Platform {
@@ -45,7 +62,7 @@ pub fn resolve_or_default(provided: Option<&str>, default: &Path, platform: Plat
     let resolved = resolve_full_path(&candidate);
 
     if !is_path_compatible(&resolved, platform) {
-        eprintln!(
+        log::warn!(
             "Incompatible path for platform {:?}: {} → using default {}",
             platform,
             resolved.display(),
@@ -55,7 +72,7 @@ pub fn resolve_or_default(provided: Option<&str>, default: &Path, platform: Plat
     }
 
     if !resolved.exists() {
-        eprintln!(
+        log::warn!(
             "Path does not exist: {} → using default {}",
             resolved.display(),
             default.display()
@@ -66,19 +83,150 @@ pub fn resolve_or_default(provided: Option<&str>, default: &Path, platform: Plat
     resolved
 }
 
-/// Convert a hex string like "#RRGGBB" or "RRGGBB" into (r, g, b)
+/// 64-bit FNV-1a hasher, stable across platforms and compiler versions unlike `DefaultHasher`
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+}
+
+impl std::hash::Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Strip the `#` or `0x` prefix of a hex color, rejecting whitespace inside it
+pub fn normalize_hex(hex: &str) -> Result<&str, String> {
+    let trimmed = hex.trim();
+    let digits = trimmed
+        .strip_prefix("0x")
+        .or_else(|| trimmed.strip_prefix("0X"))
+        .unwrap_or_else(|| trimmed.trim_start_matches('#'));
+
+    if digits.chars().any(char::is_whitespace) {
+        return Err(format!("Hex color must not contain whitespace: '{}'", hex));
+    }
+
+    Ok(digits)
+}
+
+/// Convert a hex string like "#RRGGBB", "0xRRGGBB" or "RRGGBB" into (r, g, b)
 pub fn hex_to_rgb(hex: &str) -> Result<(u8, u8, u8), String> {
-    let hex = hex.trim_start_matches('#');
+    let hex = normalize_hex(hex)?;
 
-    if hex.len() != 6 {
-        return Err("Hex color must be 6 characters long".to_string());
+    // Work on characters so non-ASCII input is reported instead of cut mid-character
+    let digits: Vec<char> = hex.chars().collect();
+    if digits.len() != 6 {
+        return Err(format!("Hex color must be 6 characters long: '{}'", hex));
     }
 
-    let r = u8::from_str_radix(&hex[0..2], 16).map_err(|_| "Invalid red value")?;
-    let g = u8::from_str_radix(&hex[2..4], 16).map_err(|_| "Invalid green value")?;
-    let b = u8::from_str_radix(&hex[4..6], 16).map_err(|_| "Invalid blue value")?;
+    let channel = |name: &str, position: usize| {
+        let pair: String = digits[position..position + 2].iter().collect();
+        // `from_str_radix` alone would also accept a sign
+        u8::from_str_radix(&pair, 16)
+            .ok()
+            .filter(|_| pair.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("Invalid {} value '{}' at position {}", name, pair, position))
+    };
+
+    Ok((
+        channel("red", 0)?,
+        channel("green", 2)?,
+        channel("blue", 4)?,
+    ))
+}
+
+/// Named colors usable wherever a color is expected, after the item rarities of the game
+pub static PALETTE: phf::Map<&'static str, (u8, u8, u8)> = phf::phf_map! {
+    "common" => (0xab, 0x71, 0x3c),
+    "uncommon" => (0xe8, 0xc2, 0x52),
+    "rare" => (0x19, 0x9b, 0x1e),
+    "very_rare" => (0xac, 0x3e, 0xe3),
+    "ultra_rare" => (0xff, 0x09, 0x55),
+    "event" => (0xff, 0x88, 0x00),
+};
+
+/// Convert a color given as HEX, `hsl(H,S%,L%)`, `hsv(H,S%,V%)` or a [`PALETTE`] name
+/// into (r, g, b). Hue is in degrees, the other components in percent.
+pub fn parse_color(color: &str) -> Result<(u8, u8, u8), String> {
+    let trimmed = color.trim();
+    let lower = trimmed.to_ascii_lowercase();
+    if let Some(color) = PALETTE.get(lower.as_str()) {
+        return Ok(*color);
+    }
+    let (model, args) = match lower.split_once('(') {
+        Some((model @ ("hsl" | "hsv"), rest)) => (
+            model,
+            rest.strip_suffix(')')
+                .ok_or_else(|| format!("Missing ')' in '{}'", trimmed))?,
+        ),
+        _ => return hex_to_rgb(trimmed),
+    };
+
+    let components: Vec<&str> = args.split(',').map(str::trim).collect();
+    let [hue, a, b] = components[..] else {
+        return Err(format!("Expected 3 components in '{}'", trimmed));
+    };
+    let number = |token: &str, name: &str| {
+        token
+            .trim_end_matches('%')
+            .parse::<f32>()
+            .map_err(|_| format!("Invalid {} '{}' in '{}'", name, token, trimmed))
+    };
+    let percent = |token: &str, name: &str| {
+        let value = number(token, name)?;
+        if !(0.0..=100.0).contains(&value) {
+            return Err(format!(
+                "Invalid {} '{}' in '{}', expected 0% to 100%",
+                name, token, trimmed
+            ));
+        }
+        Ok(value / 100.0)
+    };
+
+    let hue = number(hue, "hue")?;
+    Ok(if model == "hsl" {
+        hsl_to_rgb(hue, percent(a, "saturation")?, percent(b, "lightness")?)
+    } else {
+        hsv_to_rgb(hue, percent(a, "saturation")?, percent(b, "value")?)
+    })
+}
+
+/// Convert a hue in degrees, saturation and lightness from 0.0 to 1.0 into (r, g, b)
+pub fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (u8, u8, u8) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    chroma_to_rgb(hue, chroma, lightness - chroma / 2.0)
+}
+
+/// Convert a hue in degrees, saturation and value from 0.0 to 1.0 into (r, g, b)
+pub fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> (u8, u8, u8) {
+    let chroma = value * saturation;
+    chroma_to_rgb(hue, chroma, value - chroma)
+}
 
-    Ok((r, g, b))
+/// Shared end of the HSL and HSV conversions, `offset` being added to every channel
+fn chroma_to_rgb(hue: f32, chroma: f32, offset: f32) -> (u8, u8, u8) {
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let channel = |c: f32| ((c + offset) * 255.0).round().clamp(0.0, 255.0) as u8;
+    (channel(r), channel(g), channel(b))
 }
 
 /// Useful to make a grayscale mask change color, preserving transparency
@@ -87,34 +235,1190 @@ pub fn colorize_grayscale_image(
     hex_color: &str,
     threshold: u8,
 ) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, String> {
-    let (r_tint, g_tint, b_tint) = hex_to_rgb(hex_color)?;
-
-    Ok(ImageBuffer::from_fn(
-        gray_img.width(),
-        gray_img.height(),
-        |x, y| {
-            let p = gray_img.get_pixel(x, y);
-            let gray = p[0];
-            let alpha = p[1];
-
-            if gray < threshold {
-                Rgba([gray, gray, gray, alpha])
-            } else {
-                let scale = gray as u16;
-                Rgba([
-                    (scale * r_tint as u16 / 255) as u8,
-                    (scale * g_tint as u16 / 255) as u8,
-                    (scale * b_tint as u16 / 255) as u8,
-                    alpha,
-                ])
-            }
+    let tint = parse_color(hex_color)?;
+    Ok(colorize_rows(gray_img, threshold, |_| tint))
+}
+
+/// Same as [`colorize_grayscale_image`], the color fading from `top` on the
+/// first row to `bottom` on the last one
+pub fn colorize_grayscale_gradient(
+    gray_img: &ImageBuffer<image::LumaA<u8>, Vec<u8>>,
+    top: (u8, u8, u8),
+    bottom: (u8, u8, u8),
+    threshold: u8,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let last_row = gray_img.height().saturating_sub(1).max(1) as f32;
+    let lerp =
+        |from: u8, to: u8, t: f32| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+
+    colorize_rows(gray_img, threshold, |y| {
+        let t = y as f32 / last_row;
+        (
+            lerp(top.0, bottom.0, t),
+            lerp(top.1, bottom.1, t),
+            lerp(top.2, bottom.2, t),
+        )
+    })
+}
+
+/// Multiply the gray of every pixel at or above `threshold` by the color of its row
+fn colorize_rows(
+    gray_img: &ImageBuffer<image::LumaA<u8>, Vec<u8>>,
+    threshold: u8,
+    row_color: impl Fn(u32) -> (u8, u8, u8),
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let row_colors: Vec<(u8, u8, u8)> = (0..gray_img.height()).map(row_color).collect();
+
+    ImageBuffer::from_fn(gray_img.width(), gray_img.height(), |x, y| {
+        let p = gray_img.get_pixel(x, y);
+        let gray = p[0];
+        let alpha = p[1];
+
+        if gray < threshold {
+            Rgba([gray, gray, gray, alpha])
+        } else {
+            let (r_tint, g_tint, b_tint) = row_colors[y as usize];
+            let scale = gray as u16;
+            Rgba([
+                (scale * r_tint as u16 / 255) as u8,
+                (scale * g_tint as u16 / 255) as u8,
+                (scale * b_tint as u16 / 255) as u8,
+                alpha,
+            ])
+        }
+    })
+}
+
+/// [`colorize_rows`] at 16 bits per channel, the gray being multiplied by the
+/// color of its row scaled to 65535
+fn colorize_rows16(
+    gray_img: &ImageBuffer<image::LumaA<u16>, Vec<u16>>,
+    threshold: u8,
+    row_color: impl Fn(u32) -> (u8, u8, u8),
+) -> ImageBuffer<Rgba<u16>, Vec<u16>> {
+    let row_colors: Vec<(u8, u8, u8)> = (0..gray_img.height()).map(row_color).collect();
+    let threshold = threshold as u16 * 257;
+
+    ImageBuffer::from_fn(gray_img.width(), gray_img.height(), |x, y| {
+        let [gray, alpha] = gray_img.get_pixel(x, y).0;
+        if gray < threshold {
+            Rgba([gray, gray, gray, alpha])
+        } else {
+            let (r_tint, g_tint, b_tint) = row_colors[y as usize];
+            let channel = |tint: u8| (gray as u32 * (tint as u32 * 257) / 65535) as u16;
+            Rgba([channel(r_tint), channel(g_tint), channel(b_tint), alpha])
+        }
+    })
+}
+
+/// Tint given after the `#` of a descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tint {
+    /// `RRGGBB`, `hsl(...)` or `hsv(...)`
+    Flat((u8, u8, u8)),
+    /// `TOP:BOTTOM:vgrad`, fading from the top row to the bottom one
+    VerticalGradient((u8, u8, u8), (u8, u8, u8)),
+    /// `~COLOR:PERCENT`, the layer blended this far toward its flat tint
+    Partial((u8, u8, u8), u8),
+}
+
+/// Parse the tint of a descriptor
+pub fn parse_tint(spec: &str) -> Result<Tint, String> {
+    if let Some(partial) = spec.strip_prefix('~') {
+        return match partial.split_once(':') {
+            Some((color, percent)) => match percent.trim().parse::<u8>() {
+                Ok(percent) if percent <= 100 => Ok(Tint::Partial(parse_color(color)?, percent)),
+                _ => Err(format!(
+                    "Invalid percent '{}' in tint '{}', expected 0 to 100",
+                    percent, spec
+                )),
+            },
+            None => Err(format!("Invalid tint '{}', expected ~COLOR:PERCENT", spec)),
+        };
+    }
+    match spec.split(':').collect::<Vec<_>>()[..] {
+        [color] => Ok(Tint::Flat(parse_color(color)?)),
+        [top, bottom, "vgrad"] => Ok(Tint::VerticalGradient(
+            parse_color(top)?,
+            parse_color(bottom)?,
+        )),
+        _ => Err(format!(
+            "Invalid tint '{}', expected COLOR, TOP:BOTTOM:vgrad or ~COLOR:PERCENT",
+            spec
+        )),
+    }
+}
+
+/// Parse a `dominant`, `dominant(K)`, `dominant:NAME` or `dominant(K):NAME` tint into
+/// its cluster count and the layer it is taken from, the item when there is no name.
+/// `None` for other tints.
+pub fn parse_dominant_tint(spec: &str) -> Option<Result<(usize, Option<&str>), String>> {
+    let rest = spec.strip_prefix("dominant")?;
+    let (clusters, rest) = match rest.strip_prefix('(') {
+        Some(args) => match args.split_once(')') {
+            Some((k, rest)) => match k.trim().parse::<usize>() {
+                Ok(k) if k > 0 => (k, rest),
+                _ => {
+                    return Some(Err(format!(
+                        "Invalid cluster count '{}' in '{}', expected a positive integer",
+                        k, spec
+                    )));
+                }
+            },
+            None => return Some(Err(format!("Missing ')' in '{}'", spec))),
         },
+        None => (3, rest),
+    };
+    match rest.strip_prefix(':') {
+        Some(name) if !name.is_empty() => Some(Ok((clusters, Some(name)))),
+        None if rest.is_empty() => Some(Ok((clusters, None))),
+        _ => Some(Err(format!(
+            "Invalid tint '{}', expected dominant[(K)][:NAME]",
+            spec
+        ))),
+    }
+}
+
+/// Color of the largest of `k` clusters of the visible pixels of an image, found
+/// with k-means. Falls back to their average when clustering gives nothing.
+/// `None` when the image is fully transparent.
+pub fn dominant_color(img: &DynamicImage, k: usize) -> Option<(u8, u8, u8)> {
+    let pixels: Vec<[f32; 3]> = img
+        .to_rgba8()
+        .pixels()
+        .filter(|p| p[3] > 0)
+        .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32])
+        .collect();
+    if pixels.is_empty() {
+        return None;
+    }
+    // Sampling keeps large images fast, clusters barely move
+    let step = pixels.len().div_ceil(4096);
+    let samples: Vec<[f32; 3]> = pixels.iter().step_by(step).copied().collect();
+
+    let distance = |a: &[f32; 3], b: &[f32; 3]| (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f32>();
+    let mean = |points: &[[f32; 3]]| {
+        let mut sum = [0.0; 3];
+        for point in points {
+            (0..3).for_each(|i| sum[i] += point[i]);
+        }
+        sum.map(|c| c / points.len() as f32)
+    };
+
+    // Spread the starting centroids over the samples, keeping the result deterministic
+    let k = k.min(samples.len());
+    let mut centroids: Vec<[f32; 3]> = (0..k).map(|i| samples[i * samples.len() / k]).collect();
+    let mut members: Vec<Vec<[f32; 3]>> = Vec::new();
+    for _ in 0..16 {
+        members = vec![Vec::new(); k];
+        for sample in &samples {
+            let nearest = (0..k)
+                .min_by(|&a, &b| {
+                    distance(sample, &centroids[a]).total_cmp(&distance(sample, &centroids[b]))
+                })
+                .unwrap_or(0);
+            members[nearest].push(*sample);
+        }
+        let moved: Vec<[f32; 3]> = members
+            .iter()
+            .zip(&centroids)
+            .map(|(points, centroid)| {
+                if points.is_empty() {
+                    *centroid
+                } else {
+                    mean(points)
+                }
+            })
+            .collect();
+        if moved == centroids {
+            break;
+        }
+        centroids = moved;
+    }
+
+    let [r, g, b] = members
+        .iter()
+        .zip(&centroids)
+        .filter(|(points, _)| !points.is_empty())
+        .max_by_key(|(points, _)| points.len())
+        .map_or_else(|| mean(&samples), |(_, centroid)| *centroid);
+    Some((r.round() as u8, g.round() as u8, b.round() as u8))
+}
+
+/// Replace the `dominant` tints of layers by the HEX color they stand for, taken
+/// from `item` or from the layer they name. Tints that can't be resolved are dropped
+/// with a warning.
+pub fn resolve_dominant_tints(
+    layers: &[String],
+    layer_folders: &[PathBuf],
+    item: &DynamicImage,
+    max_dimension: u32,
+) -> Vec<String> {
+    layers
+        .iter()
+        .map(|descriptor| {
+            // Transforms follow the tint and are kept as they are
+            let (rest, _) = split_transforms(descriptor);
+            let transforms = &descriptor[rest.len()..];
+            let (name, Some(tint)) = split_tint(rest) else {
+                return descriptor.clone();
+            };
+            let Some(parsed) = parse_dominant_tint(tint) else {
+                return descriptor.clone();
+            };
+            let color = parsed.and_then(|(k, source)| {
+                let color = match source {
+                    Some(source) => {
+                        let path = find_layer_path(layer_folders, source)
+                            .ok_or_else(|| format!("layer '{}' not found", source))?;
+                        dominant_color(&open_image(&path, max_dimension)?, k)
+                    }
+                    None => dominant_color(item, k),
+                };
+                color.ok_or_else(|| "no visible pixel to take a color from".to_string())
+            });
+            match color {
+                Ok((r, g, b)) => format!("{}#{:02x}{:02x}{:02x}{}", name, r, g, b, transforms),
+                Err(e) => {
+                    log::warn!("Ignoring tint '{}' of layer '{}': {}", tint, name, e);
+                    format!("{}{}", name, transforms)
+                }
+            }
+        })
+        .collect()
+}
+
+/// [`PALETTE`] color of the rarity a file name carries, named by the first capture
+/// group of `pattern`, or by the whole match without groups
+pub fn rarity_color(filename: &str, pattern: &regex::Regex) -> Option<(u8, u8, u8)> {
+    let captures = pattern.captures(strip_image_extension(filename))?;
+    let rarity = captures.get(1).or_else(|| captures.get(0))?;
+    PALETTE
+        .get(rarity.as_str().to_ascii_lowercase().as_str())
+        .copied()
+}
+
+/// Replace the `rarity` tints of layers by the HEX color of the rarity of the entry.
+/// Without a rarity the tints are dropped with a warning.
+pub fn resolve_rarity_tints(
+    layers: &[String],
+    rarity: Option<(u8, u8, u8)>,
+    filename: &str,
+) -> Vec<String> {
+    layers
+        .iter()
+        .map(|descriptor| {
+            // Transforms follow the tint and are kept as they are
+            let (rest, _) = split_transforms(descriptor);
+            let transforms = &descriptor[rest.len()..];
+            let (name, Some(RARITY_TINT)) = split_tint(rest) else {
+                return descriptor.clone();
+            };
+            match rarity {
+                Some((r, g, b)) => format!("{}#{:02x}{:02x}{:02x}{}", name, r, g, b, transforms),
+                None => {
+                    log::warn!(
+                        "Ignoring tint '{}' of layer '{}': no rarity in '{}'",
+                        RARITY_TINT,
+                        name,
+                        filename
+                    );
+                    format!("{}{}", name, transforms)
+                }
+            }
+        })
+        .collect()
+}
+
+/// Colorize a grayscale mask image with the given tint, at 16 bits per channel
+/// for 16-bit RGBA images
+pub fn tint_image(img: &DynamicImage, tint: &str) -> Result<DynamicImage, String> {
+    if let DynamicImage::ImageRgba16(_) = img {
+        return Ok(tint_image16(img, parse_tint(tint)?));
+    }
+    let gray_img = img.to_luma_alpha8();
+    let colored = match parse_tint(tint)? {
+        Tint::Flat(color) => colorize_rows(&gray_img, TINT_THRESHOLD, |_| color),
+        Tint::VerticalGradient(top, bottom) => {
+            colorize_grayscale_gradient(&gray_img, top, bottom, TINT_THRESHOLD)
+        }
+        Tint::Partial(color, percent) => {
+            let mut blended = img.to_rgba8();
+            let tinted = colorize_rows(&gray_img, TINT_THRESHOLD, |_| color);
+            let t = percent as f32 / 100.0;
+            for (pixel, target) in blended.pixels_mut().zip(tinted.pixels()) {
+                for (c, target) in pixel.0.iter_mut().zip(target.0) {
+                    *c = (*c as f32 + (target as f32 - *c as f32) * t).round() as u8;
+                }
+            }
+            blended
+        }
+    };
+    Ok(DynamicImage::ImageRgba8(colored))
+}
+
+/// [`tint_image`] at 16 bits per channel
+fn tint_image16(img: &DynamicImage, tint: Tint) -> DynamicImage {
+    let gray_img = img.to_luma_alpha16();
+    let last_row = gray_img.height().saturating_sub(1).max(1) as f32;
+    let lerp =
+        |from: u8, to: u8, t: f32| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    let mut colored = colorize_rows16(&gray_img, TINT_THRESHOLD, |y| match tint {
+        Tint::Flat(color) | Tint::Partial(color, _) => color,
+        Tint::VerticalGradient(top, bottom) => {
+            let t = y as f32 / last_row;
+            (
+                lerp(top.0, bottom.0, t),
+                lerp(top.1, bottom.1, t),
+                lerp(top.2, bottom.2, t),
+            )
+        }
+    });
+    if let Tint::Partial(_, percent) = tint {
+        let t = percent as f32 / 100.0;
+        for (pixel, original) in colored.pixels_mut().zip(img.to_rgba16().pixels()) {
+            for (c, original) in pixel.0.iter_mut().zip(original.0) {
+                *c = (original as f32 + (*c as f32 - original as f32) * t).round() as u16;
+            }
+        }
+    }
+    DynamicImage::ImageRgba16(colored)
+}
+
+/// Blend from `original` to `tinted` by the luminance times the alpha of `mask`,
+/// stretched over them: black or transparent keeps the original, opaque white
+/// takes the tint. Both images are RGBA of the same size and depth.
+pub fn blend_by_mask(
+    original: &DynamicImage,
+    tinted: &DynamicImage,
+    mask: &DynamicImage,
+) -> DynamicImage {
+    let (width, height) = (original.width(), original.height());
+    let mask = if mask.width() == width && mask.height() == height {
+        mask.to_luma_alpha16()
+    } else {
+        mask.resize_exact(width, height, image::imageops::FilterType::Triangle)
+            .to_luma_alpha16()
+    };
+    let weights = mask
+        .pixels()
+        .map(|pixel| pixel[0] as f32 / 65535.0 * pixel[1] as f32 / 65535.0);
+    let lerp = |from: f32, to: f32, t: f32| from + (to - from) * t;
+    match (original, tinted) {
+        (DynamicImage::ImageRgba16(original), DynamicImage::ImageRgba16(tinted)) => {
+            let mut blended = original.clone();
+            for ((pixel, target), t) in blended.pixels_mut().zip(tinted.pixels()).zip(weights) {
+                for (c, target) in pixel.0.iter_mut().zip(target.0) {
+                    *c = lerp(*c as f32, target as f32, t).round() as u16;
+                }
+            }
+            DynamicImage::ImageRgba16(blended)
+        }
+        _ => {
+            let mut blended = original.to_rgba8();
+            for ((pixel, target), t) in blended
+                .pixels_mut()
+                .zip(tinted.to_rgba8().pixels())
+                .zip(weights)
+            {
+                for (c, target) in pixel.0.iter_mut().zip(target.0) {
+                    *c = lerp(*c as f32, target as f32, t).round() as u8;
+                }
+            }
+            DynamicImage::ImageRgba8(blended)
+        }
+    }
+}
+
+/// Transparent RGBA image, at 16 bits per channel when `like` is 16-bit RGBA
+pub fn blank_like(like: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    match like {
+        DynamicImage::ImageRgba16(_) => DynamicImage::new_rgba16(width, height),
+        _ => DynamicImage::new_rgba8(width, height),
+    }
+}
+
+/// Draw `top` over `bottom` at the given position, at 16 bits per channel
+/// when `bottom` is 16-bit RGBA
+pub fn overlay_image(bottom: &mut DynamicImage, top: &DynamicImage, x: i64, y: i64) {
+    match bottom {
+        DynamicImage::ImageRgba16(bottom) => overlay(bottom, &top.to_rgba16(), x, y),
+        _ => overlay(bottom, top, x, y),
+    }
+}
+
+/// Split a `name#RRGGBB` descriptor into its name and optional HEX color.
+/// A `#` between parentheses, like in `border:gen(2,#ff0000,4)`, belongs to the name.
+/// The `~` of partial tints is kept, `name~COLOR:PERCENT` giving `~COLOR:PERCENT`;
+/// any other `~` belongs to the name.
+pub fn split_tint(descriptor: &str) -> (&str, Option<&str>) {
+    // The last `~` followed by a color and a number of percent
+    let is_partial = |tint: &str| {
+        tint.rsplit_once(':').is_some_and(|(color, percent)| {
+            !color.is_empty()
+                && !color.contains('~')
+                && !percent.is_empty()
+                && percent.bytes().all(|b| b.is_ascii_digit())
+        })
+    };
+    let mut depth = 0_usize;
+    for (index, c) in descriptor.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '#' if depth == 0 => return (&descriptor[..index], Some(&descriptor[index + 1..])),
+            '~' if depth == 0 && is_partial(&descriptor[index + 1..]) => {
+                return (&descriptor[..index], Some(&descriptor[index..]));
+            }
+            _ => {}
+        }
+    }
+    (descriptor, None)
+}
+
+/// Rounded rectangle border along the edges of the canvas, drawn by a
+/// `name:gen(WIDTH,COLOR,RADIUS)` layer instead of reading a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Border {
+    pub width: f32,
+    pub color: (u8, u8, u8),
+    pub radius: f32,
+}
+
+/// Parse the border of a generated layer name, `None` for layers read from files
+pub fn parse_generated(name: &str) -> Option<Result<Border, String>> {
+    let (_, args) = name.split_once(":gen(")?;
+    let parse = || {
+        let args = args
+            .strip_suffix(')')
+            .ok_or_else(|| format!("Missing ')' in '{}'", name))?;
+        // The color may hold commas itself, like `hsl(...)`
+        let (Some((width, rest)), Some((_, radius))) =
+            (args.split_once(','), args.rsplit_once(','))
+        else {
+            return Err(format!("Expected gen(WIDTH,COLOR,RADIUS) in '{}'", name));
+        };
+        let color = rest.rsplit_once(',').map_or(rest, |(color, _)| color);
+        let number = |value: &str, what: &str| match value.trim().parse::<f32>() {
+            Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+            _ => Err(format!("Invalid {} '{}' in '{}'", what, value.trim(), name)),
+        };
+        Ok(Border {
+            width: number(width, "width")?,
+            color: parse_color(color)?,
+            radius: number(radius, "radius")?,
+        })
+    };
+    Some(parse())
+}
+
+/// Draw a border along the edges of a canvas, antialiased, the rest being transparent
+pub fn draw_border(width: u32, height: u32, border: &Border) -> DynamicImage {
+    let (half_width, half_height) = (width as f32 / 2.0, height as f32 / 2.0);
+    // Signed distance to a rounded rectangle centered on the canvas, negative inside
+    let distance = |x: f32, y: f32, inset: f32, radius: f32| {
+        let radius = radius
+            .min(half_width - inset)
+            .min(half_height - inset)
+            .max(0.0);
+        let qx = x.abs() - (half_width - inset - radius);
+        let qy = y.abs() - (half_height - inset - radius);
+        qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - radius
+    };
+    let (r, g, b) = border.color;
+    DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| {
+        let (x, y) = (x as f32 + 0.5 - half_width, y as f32 + 0.5 - half_height);
+        let outer = distance(x, y, 0.0, border.radius);
+        let inner = distance(x, y, border.width, border.radius - border.width);
+        let coverage = (0.5 - outer).clamp(0.0, 1.0) * (0.5 + inner).clamp(0.0, 1.0);
+        Rgba([r, g, b, (coverage * 255.0).round() as u8])
+    }))
+}
+
+/// How a layer is combined with what is under it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Drawn over it
+    #[default]
+    Normal,
+    /// `|layer - under|` for every channel, faded by the alpha of the layer
+    Difference,
+}
+
+/// Transforms of a layer, given after its name and tint
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayerTransforms<'a> {
+    /// Factor of `:scale(FACTOR)`, unparsed
+    pub scale: Option<&'a str>,
+    /// `:difference`, drawn over otherwise
+    pub blend: BlendMode,
+    /// `:premul` for files whose colors are already multiplied by their alpha,
+    /// `:straight` (the default) for files with independent colors and alpha
+    pub premultiplied: bool,
+    /// Condition of `:if(CONDITION)`, unparsed
+    pub condition: Option<&'a str>,
+    /// Layer file of `:mask=NAME`, limiting the tint to where it is light and opaque
+    pub mask: Option<&'a str>,
+}
+
+/// Split the `:scale(FACTOR)`, `:if(CONDITION)`, `:mask=NAME`, `:difference`, `:premul`
+/// and `:straight` transforms, in any order, off the end of a layer descriptor
+pub fn split_transforms(descriptor: &str) -> (&str, LayerTransforms<'_>) {
+    let mut transforms = LayerTransforms::default();
+    let mut rest = descriptor;
+    loop {
+        if let Some(stripped) = rest.strip_suffix(":difference") {
+            transforms.blend = BlendMode::Difference;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_suffix(":premul") {
+            transforms.premultiplied = true;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_suffix(":straight") {
+            transforms.premultiplied = false;
+            rest = stripped;
+        } else if let (stripped, Some(factor)) = split_argument(rest, "scale") {
+            transforms.scale = Some(factor);
+            rest = stripped;
+        } else if let (stripped, Some(condition)) = split_argument(rest, "if") {
+            transforms.condition = Some(condition);
+            rest = stripped;
+        } else if let Some((stripped, mask)) = rest
+            .rsplit_once(":mask=")
+            .filter(|(_, mask)| !mask.is_empty() && !mask.contains([':', '#', '(', ')']))
+        {
+            transforms.mask = Some(mask);
+            rest = stripped;
+        } else {
+            return (rest, transforms);
+        }
+    }
+}
+
+/// Name of the layer file of a descriptor, without its tint and transforms
+pub fn layer_base_name(descriptor: &str) -> &str {
+    split_tint(split_transforms(descriptor).0).0
+}
+
+/// Turn colors multiplied by their alpha back into independent ones,
+/// which is what compositing expects
+pub fn unpremultiply(img: &mut DynamicImage) {
+    if let DynamicImage::ImageRgba16(rgba) = img {
+        for pixel in rgba.pixels_mut() {
+            let [r, g, b, a] = pixel.0;
+            if a > 0 && a < u16::MAX {
+                let channel =
+                    |c: u16| ((c as u64 * 65535 + a as u64 / 2) / a as u64).min(65535) as u16;
+                *pixel = Rgba([channel(r), channel(g), channel(b), a]);
+            }
+        }
+        return;
+    }
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        if a > 0 && a < 255 {
+            let channel = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+            *pixel = Rgba([channel(r), channel(g), channel(b), a]);
+        }
+    }
+    *img = DynamicImage::ImageRgba8(rgba);
+}
+
+/// Draw `top` over `bottom` at its origin with the given blend mode
+pub fn blend(bottom: &mut DynamicImage, top: &DynamicImage, mode: BlendMode) {
+    match mode {
+        BlendMode::Normal => overlay_image(bottom, top, 0, 0),
+        BlendMode::Difference => {
+            // Worked out at 8 bits per channel whatever the depth of the canvas
+            let high_depth = matches!(bottom, DynamicImage::ImageRgba16(_));
+            let mut under = bottom.to_rgba8();
+            let top = top.to_rgba8();
+            let (width, height) = (
+                under.width().min(top.width()),
+                under.height().min(top.height()),
+            );
+            for y in 0..height {
+                for x in 0..width {
+                    let [tr, tg, tb, ta] = top.get_pixel(x, y).0;
+                    let pixel = under.get_pixel_mut(x, y);
+                    let [r, g, b, a] = pixel.0;
+                    let alpha = ta as f32 / 255.0;
+                    let mix = |t: u8, u: u8| {
+                        let difference = (t as f32 - u as f32).abs();
+                        (u as f32 + (difference - u as f32) * alpha).round() as u8
+                    };
+                    let a = (ta as f32 + a as f32 * (1.0 - alpha)).round() as u8;
+                    *pixel = Rgba([mix(tr, r), mix(tg, g), mix(tb, b), a]);
+                }
+            }
+            *bottom = DynamicImage::ImageRgba8(under);
+            if high_depth {
+                *bottom = DynamicImage::ImageRgba16(bottom.to_rgba16());
+            }
+        }
+    }
+}
+
+/// Per-pixel difference of two images of the same size, black where they match.
+/// Each channel is the largest of the color and alpha differences, so changes
+/// of transparency show as gray. Returns the image and how many pixels differ.
+pub fn difference_image(
+    a: &DynamicImage,
+    b: &DynamicImage,
+) -> Result<(DynamicImage, usize), String> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(format!(
+            "Images have different sizes: {}x{} and {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        ));
+    }
+    let (a, b) = (a.to_rgba8(), b.to_rgba8());
+    let mut differing = 0;
+    let diff = ImageBuffer::from_fn(a.width(), a.height(), |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y).0, b.get_pixel(x, y).0);
+        let alpha = pa[3].abs_diff(pb[3]);
+        let channel = |i: usize| pa[i].abs_diff(pb[i]).max(alpha);
+        let pixel = image::Rgb([channel(0), channel(1), channel(2)]);
+        if pixel.0 != [0, 0, 0] {
+            differing += 1;
+        }
+        pixel
+    });
+    Ok((DynamicImage::ImageRgb8(diff), differing))
+}
+
+/// Color of a difference from 0.0 to 1.0, going from blue through cyan,
+/// green and yellow to red
+fn heat_color(t: f32) -> image::Rgb<u8> {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 255.0],
+        [0.0, 255.0, 255.0],
+        [0.0, 255.0, 0.0],
+        [255.0, 255.0, 0.0],
+        [255.0, 0.0, 0.0],
+    ];
+    let position = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let fraction = position - index as f32;
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+    image::Rgb(std::array::from_fn(|i| {
+        (from[i] + (to[i] - from[i]) * fraction).round() as u8
+    }))
+}
+
+/// Heatmap of the per-pixel difference of two images of the same size, blue where
+/// they match and red where they differ most. Differences are amplified by a square
+/// root so small shifts still show. Also returns the mean difference, from 0.0 to 1.0.
+pub fn difference_heatmap(
+    a: &DynamicImage,
+    b: &DynamicImage,
+) -> Result<(DynamicImage, f32), String> {
+    let (diff, _) = difference_image(a, b)?;
+    let diff = diff.to_rgb8();
+    let mut total = 0.0;
+    let heatmap = ImageBuffer::from_fn(diff.width(), diff.height(), |x, y| {
+        let difference = *diff.get_pixel(x, y).0.iter().max().unwrap() as f32 / 255.0;
+        total += difference;
+        heat_color(difference.sqrt())
+    });
+    let pixels = (diff.width() * diff.height()).max(1);
+    Ok((DynamicImage::ImageRgb8(heatmap), total / pixels as f32))
+}
+
+/// Split a `name:TRANSFORM(ARGUMENT)` layer descriptor into the rest of the descriptor
+/// and the argument of the transform
+fn split_argument<'a>(descriptor: &'a str, transform: &str) -> (&'a str, Option<&'a str>) {
+    match descriptor
+        .rsplit_once(&format!(":{}(", transform))
+        .and_then(|(rest, argument)| Some((rest, argument.strip_suffix(')')?)))
+        .filter(|(_, argument)| !argument.contains(['(', ')']))
+    {
+        Some((rest, argument)) => (rest, Some(argument.trim())),
+        None => (descriptor, None),
+    }
+}
+
+/// Measurement of the item that `:if(...)` conditions compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    /// Share of the pixels of the item that aren't fully transparent, from 0 to 1
+    Coverage,
+}
+
+/// Values of every [`Variable`] for an item
+#[derive(Debug, Clone, Copy)]
+pub struct ItemVariables {
+    pub coverage: f32,
+}
+
+impl ItemVariables {
+    pub fn measure(item: &DynamicImage) -> Self {
+        let rgba = item.to_rgba8();
+        let pixels = rgba.pixels().len().max(1);
+        let visible = rgba.pixels().filter(|p| p[3] > 0).count();
+        ItemVariables {
+            coverage: visible as f32 / pixels as f32,
+        }
+    }
+
+    fn get(&self, variable: Variable) -> f32 {
+        match variable {
+            Variable::Coverage => self.coverage,
+        }
+    }
+}
+
+/// A `VARIABLE OPERATOR NUMBER` comparison, the operator being `<`, `>`, `<=` or `>=`
+#[derive(Debug, Clone, Copy)]
+pub struct Condition {
+    pub variable: Variable,
+    pub operator: Ordering,
+    /// Whether the operator also holds on equality
+    pub or_equal: bool,
+    pub value: f32,
+}
+
+impl Condition {
+    pub fn holds(&self, variables: &ItemVariables) -> bool {
+        match variables.get(self.variable).partial_cmp(&self.value) {
+            Some(Ordering::Equal) => self.or_equal,
+            ordering => ordering == Some(self.operator),
+        }
+    }
+}
+
+/// Parse the condition of an `:if(...)` transform, e.g. `coverage<0.3`
+pub fn parse_condition(spec: &str) -> Result<Condition, String> {
+    let invalid = || {
+        format!(
+            "Invalid condition '{}', expected VARIABLE<NUMBER with <, >, <= or >=",
+            spec
+        )
+    };
+    let start = spec.find(['<', '>']).ok_or_else(invalid)?;
+    let (variable, rest) = spec.split_at(start);
+    let operator = if rest.starts_with('<') {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    };
+    let (or_equal, value) = match rest[1..].strip_prefix('=') {
+        Some(value) => (true, value),
+        None => (false, &rest[1..]),
+    };
+
+    let variable = match variable.trim() {
+        "coverage" => Variable::Coverage,
+        other => {
+            return Err(format!(
+                "Unknown variable '{}' in '{}', expected coverage",
+                other, spec
+            ));
+        }
+    };
+    let value = value
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(invalid)?;
+    Ok(Condition {
+        variable,
+        operator,
+        or_equal,
+        value,
+    })
+}
+
+/// Drop the layers whose `:if(...)` condition doesn't hold for the item, removing the
+/// transform from the others. Layers with invalid conditions are dropped with a warning.
+pub fn resolve_conditions(layers: &[String], variables: &ItemVariables) -> Vec<String> {
+    layers
+        .iter()
+        .filter_map(|descriptor| {
+            let (rest, transforms) = split_transforms(descriptor);
+            let Some(spec) = transforms.condition else {
+                return Some(descriptor.clone());
+            };
+            match parse_condition(spec) {
+                Ok(condition) if condition.holds(variables) => {
+                    let transforms = &descriptor[rest.len()..];
+                    let start = transforms.rfind(":if(").unwrap_or(0);
+                    let end = transforms[start..]
+                        .find(')')
+                        .map_or(transforms.len(), |i| start + i + 1);
+                    Some(format!(
+                        "{}{}{}",
+                        rest,
+                        &transforms[..start],
+                        &transforms[end..]
+                    ))
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    log::warn!("Ignoring layer '{}': {}", descriptor, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Parse the factor of a `:scale(...)` transform, a positive number
+pub fn parse_scale(factor: &str) -> Result<f32, String> {
+    match factor.parse::<f32>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        _ => Err(format!(
+            "Invalid scale '{}', expected a positive number",
+            factor
+        )),
+    }
+}
+
+/// Resize an image by `factor`, keeping at least one pixel on each side
+pub fn resize_by(
+    img: &DynamicImage,
+    factor: f32,
+    filter: image::imageops::FilterType,
+) -> DynamicImage {
+    let width = ((img.width() as f32 * factor).round() as u32).max(1);
+    let height = ((img.height() as f32 * factor).round() as u32).max(1);
+    img.resize_exact(width, height, filter)
+}
+
+/// Resize an image by `factor` around its center, keeping its dimensions:
+/// upscaled images are cropped and downscaled ones padded with transparency
+pub fn scale_centered(img: &DynamicImage, factor: f32) -> DynamicImage {
+    let (width, height) = (img.width(), img.height());
+    let scaled_width = ((width as f32 * factor).round() as u32).max(1);
+    let scaled_height = ((height as f32 * factor).round() as u32).max(1);
+    let scaled = img.resize_exact(
+        scaled_width,
+        scaled_height,
+        image::imageops::FilterType::Triangle,
+    );
+
+    let mut centered = blank_like(img, width, height);
+    overlay_image(
+        &mut centered,
+        &scaled,
+        (width as i64 - scaled_width as i64) / 2,
+        (height as i64 - scaled_height as i64) / 2,
+    );
+    centered
+}
+
+/// Apply an unsharp mask to the RGB of an image, leaving alpha untouched
+/// so transparent edges don't get halos
+pub fn sharpen(img: &mut DynamicImage, sigma: f32, threshold: i32) {
+    let original = img.to_rgba8();
+    let mut sharpened = image::imageops::unsharpen(&original, sigma, threshold);
+    for (pixel, source) in sharpened.pixels_mut().zip(original.pixels()) {
+        pixel[3] = source[3];
+    }
+    *img = DynamicImage::ImageRgba8(sharpened);
+}
+
+/// Smallest rectangle holding every visible pixel as (x, y, width, height),
+/// `None` when the image is fully transparent
+pub fn opaque_bounds(img: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+    let rgba = img.to_rgba8();
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        if pixel[3] > 0 {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    (min_x != u32::MAX).then(|| (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
+
+/// Crop an image to its visible pixels and surround them with `margin`
+/// transparent pixels on every side. Fully transparent images are kept as they are.
+/// Fails when the padded image would be larger than [`DEFAULT_MAX_DIMENSION`].
+pub fn normalize_margin(img: &DynamicImage, margin: u32) -> Result<DynamicImage, String> {
+    let Some((x, y, width, height)) = opaque_bounds(img) else {
+        return Ok(img.clone());
+    };
+    let padded = |side: u32| {
+        margin
+            .checked_mul(2)
+            .and_then(|margins| side.checked_add(margins))
+            .filter(|&padded| padded <= DEFAULT_MAX_DIMENSION)
+            .ok_or_else(|| {
+                format!(
+                    "a margin of {} makes the {}x{} visible pixels larger than {} pixels",
+                    margin, width, height, DEFAULT_MAX_DIMENSION
+                )
+            })
+    };
+    let mut normalized = blank_like(img, padded(width)?, padded(height)?);
+    overlay_image(
+        &mut normalized,
+        &img.crop_imm(x, y, width, height),
+        margin as i64,
+        margin as i64,
+    );
+    Ok(normalized)
+}
+
+/// Reduce a 16-bit image to 8 bits per channel with Floyd-Steinberg error diffusion:
+/// what each pixel loses to rounding is carried over to its right and lower
+/// neighbours, so smooth gradients turn into fine noise instead of bands
+pub fn dither_floyd_steinberg(
+    img: &ImageBuffer<Rgba<u16>, Vec<u16>>,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let mut dithered = ImageBuffer::new(width, height);
+    // Errors of the current and next rows, shifted by one to leave room on both sides
+    let mut current = vec![[0.0f32; 4]; width as usize + 2];
+    let mut next = current.clone();
+    for y in 0..height {
+        for x in 0..width {
+            let source = img.get_pixel(x, y);
+            let mut pixel = [0u8; 4];
+            let i = x as usize + 1;
+            for c in 0..4 {
+                let value = source[c] as f32 + current[i][c];
+                let rounded = (value / 257.0).round().clamp(0.0, 255.0);
+                pixel[c] = rounded as u8;
+                let error = value - rounded * 257.0;
+                current[i + 1][c] += error * 7.0 / 16.0;
+                next[i - 1][c] += error * 3.0 / 16.0;
+                next[i][c] += error * 5.0 / 16.0;
+                next[i + 1][c] += error / 16.0;
+            }
+            dithered.put_pixel(x, y, Rgba(pixel));
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill([0.0; 4]);
+    }
+    dithered
+}
+
+/// Blend an image over a solid color, dropping its alpha channel
+pub fn flatten_over(img: &DynamicImage, (r, g, b): (u8, u8, u8)) -> DynamicImage {
+    let rgba = img.to_rgba8();
+    let blend = |channel: u8, background: u8, alpha: u8| {
+        ((channel as u16 * alpha as u16 + background as u16 * (255 - alpha as u16) + 127) / 255)
+            as u8
+    };
+    DynamicImage::ImageRgb8(ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [pr, pg, pb, pa] = rgba.get_pixel(x, y).0;
+        image::Rgb([blend(pr, r, pa), blend(pg, g, pa), blend(pb, b, pa)])
+    }))
+}
+
+/// Multiply the RGB of every pixel by a row-major 3x3 matrix, preserving alpha
+pub fn apply_color_matrix(img: &mut DynamicImage, matrix: &[f32; 9]) {
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+        let channel = |row: usize| {
+            let m = &matrix[row * 3..row * 3 + 3];
+            (m[0] * r + m[1] * g + m[2] * b).round().clamp(0.0, 255.0) as u8
+        };
+        *pixel = Rgba([channel(0), channel(1), channel(2), a]);
+    }
+    *img = DynamicImage::ImageRgba8(rgba);
+}
+
+/// Replacement of one color by another, written `FROM=TO` with colors as
+/// accepted by [`parse_color`], e.g. `#ff0000=#ff8800`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorRemap {
+    pub from: (u8, u8, u8),
+    pub to: (u8, u8, u8),
+}
+
+impl FromStr for ColorRemap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected FROM=TO, got '{}'", s))?;
+        Ok(ColorRemap {
+            from: parse_color(from)?,
+            to: parse_color(to)?,
+        })
+    }
+}
+
+/// Quantize the RGB channels of an image, and alpha with `alpha`, to `levels`
+/// evenly spaced values from 0 to the largest, 16-bit RGBA images keeping their depth
+pub fn posterize(img: &mut DynamicImage, levels: u16, alpha: bool) {
+    if levels < 2 {
+        return;
+    }
+    let steps = (levels - 1) as f32;
+    let quantize = |c: f32, max: f32| ((c / max * steps).round() / steps * max).round();
+    let channels = if alpha { 4 } else { 3 };
+    match img {
+        DynamicImage::ImageRgba16(rgba) => {
+            for pixel in rgba.pixels_mut() {
+                for c in &mut pixel.0[..channels] {
+                    *c = quantize(*c as f32, 65535.0) as u16;
+                }
+            }
+        }
+        _ => {
+            let mut rgba = img.to_rgba8();
+            for pixel in rgba.pixels_mut() {
+                for c in &mut pixel.0[..channels] {
+                    *c = quantize(*c as f32, 255.0) as u8;
+                }
+            }
+            *img = DynamicImage::ImageRgba8(rgba);
+        }
+    }
+}
+
+/// Replace the RGB of visible pixels matching the `from` color of a remap by its
+/// `to` color, preserving alpha. Pixels match when no channel is more than
+/// `tolerance` away, the first matching remap being used.
+pub fn remap_colors(img: &mut DynamicImage, remaps: &[ColorRemap], tolerance: u8) {
+    if remaps.is_empty() {
+        return;
+    }
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        if a == 0 {
+            continue;
+        }
+        let matches = |(fr, fg, fb): (u8, u8, u8)| {
+            r.abs_diff(fr) <= tolerance
+                && g.abs_diff(fg) <= tolerance
+                && b.abs_diff(fb) <= tolerance
+        };
+        if let Some(remap) = remaps.iter().find(|remap| matches(remap.from)) {
+            let (r, g, b) = remap.to;
+            *pixel = Rgba([r, g, b, a]);
+        }
+    }
+    *img = DynamicImage::ImageRgba8(rgba);
+}
+
+/// Darken the RGB of an image toward its corners, preserving alpha. The corners
+/// are multiplied by `1 - strength`, the falloff following a smoothstep of the
+/// distance to the center.
+pub fn vignette(img: &mut DynamicImage, strength: f32) {
+    if strength <= 0.0 {
+        return;
+    }
+    let mut rgba = img.to_rgba8();
+    let (center_x, center_y) = (rgba.width() as f32 / 2.0, rgba.height() as f32 / 2.0);
+    let corner = center_x.hypot(center_y).max(f32::EPSILON);
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        // Distances are taken from the middle of the pixel
+        let distance = (x as f32 + 0.5 - center_x).hypot(y as f32 + 0.5 - center_y) / corner;
+        let t = distance.clamp(0.0, 1.0);
+        let factor = 1.0 - strength * t * t * (3.0 - 2.0 * t);
+        let [r, g, b, a] = pixel.0;
+        let channel = |c: u8| (c as f32 * factor).round().clamp(0.0, 255.0) as u8;
+        *pixel = Rgba([channel(r), channel(g), channel(b), a]);
+    }
+    *img = DynamicImage::ImageRgba8(rgba);
+}
+
+/// Whether a name refers to a remote image rather than a file
+pub fn is_url(name: &str) -> bool {
+    name.starts_with("http://") || name.starts_with("https://")
+}
+
+/// File name of a URL without its extension, used to name outputs of remote items
+pub fn url_file_stem(url: &str) -> &str {
+    let last = url.trim_end_matches('/').rsplit('/').next().unwrap_or(url);
+    last.rsplit_once('.').map_or(last, |(stem, _)| stem)
+}
+
+/// Open an image from disk, or download it when the path is a URL
+pub fn open_image(path: &Path, max_dimension: u32) -> Result<DynamicImage, String> {
+    timings::record(Stage::Decode, || decode_image(path, max_dimension))
+}
+
+fn decode_image(path: &Path, max_dimension: u32) -> Result<DynamicImage, String> {
+    match path.to_str() {
+        Some(url) if is_url(url) => open_remote_image(url, max_dimension),
+        _ => {
+            // Read the header first so oversized images are never allocated
+            let dimensions = image::image_dimensions(path).map_err(|e| e.to_string())?;
+            check_dimensions(dimensions, max_dimension)?;
+            image::open(path).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Reject images wider or taller than `max_dimension`
+fn check_dimensions((width, height): (u32, u32), max_dimension: u32) -> Result<(), String> {
+    if width > max_dimension || height > max_dimension {
+        return Err(format!(
+            "image is {}x{}, larger than the {} pixel limit",
+            width, height, max_dimension
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "remote")]
+fn open_remote_image(url: &str, max_dimension: u32) -> Result<DynamicImage, String> {
+    let bytes = crate::remote::fetch(url)?;
+    let reader = || {
+        image::io::Reader::new(std::io::Cursor::new(&bytes[..]))
+            .with_guessed_format()
+            .map_err(|e| e.to_string())
+    };
+    let dimensions = reader()?.into_dimensions().map_err(|e| e.to_string())?;
+    check_dimensions(dimensions, max_dimension)?;
+    reader()?.decode().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "remote"))]
+fn open_remote_image(url: &str, _max_dimension: u32) -> Result<DynamicImage, String> {
+    Err(format!(
+        "cannot download '{}': built without the `remote` feature",
+        url
     ))
 }
 
-/// Normalize path to `.png`
+/// Normalize path to `.png`, URLs are kept as they are
 pub fn force_png_path(base: &Path, name: &str) -> PathBuf {
-    base.join(format!("{}.png", name))
+    if is_url(name) {
+        return PathBuf::from(name);
+    }
+    base.join(format!("{}.png", strip_image_extension(name)))
+}
+
+/// Zero-pad the number a name ends with to `width` digits, `addon_1` giving `addon_001`
+/// at width 3. Names without a trailing number are left as they are.
+pub fn pad_trailing_number(name: &str, width: usize) -> String {
+    let stem = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = &name[stem.len()..];
+    if number.is_empty() {
+        return name.to_string();
+    }
+    format!("{stem}{number:0>width$}")
+}
+
+/// Remove a trailing `.png`, `.webp`, `.jpg` or `.jpeg` from a name, whatever its case
+pub fn strip_image_extension(name: &str) -> &str {
+    match name.rsplit_once('.') {
+        Some((stem, extension))
+            if ["png", "webp", "jpg", "jpeg"]
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known)) =>
+        {
+            stem
+        }
+        _ => name,
+    }
 }
 
 pub fn resolve_full_path(path: &Path) -> PathBuf {
@@ -122,13 +1426,12 @@ pub fn resolve_full_path(path: &Path) -> PathBuf {
 
     // Expand ~ on Unix-like systems
     #[cfg(unix)]
-    if let Some(path_str) = path.to_str() {
-        if path_str == "~" || path_str.starts_with("~/") {
-            if let Some(home) = dirs::home_dir() {
-                let stripped = path_str.trim_start_matches("~/");
-                p = home.join(stripped);
-            }
-        }
+    if let Some(path_str) = path.to_str()
+        && (path_str == "~" || path_str.starts_with("~/"))
+        && let Some(home) = dirs::home_dir()
+    {
+        let stripped = path_str.trim_start_matches("~/");
+        p = home.join(stripped);
     }
 
     // Convert to absolute if it's not already
@@ -141,62 +1444,1140 @@ pub fn resolve_full_path(path: &Path) -> PathBuf {
     }
 }
 
-/// Apply layers using the provided layer folder
-/// Returns a list of missing layer file paths.
+/// What happened to a single layer while stacking
+#[derive(Clone)]
+pub struct LayerTrace {
+    pub name: String,
+    pub path: PathBuf,
+    pub tint: Option<String>,
+    pub found: bool,
+    /// Transforms given after the name and tint, like `:scale(2):premul`
+    pub transforms: String,
+    pub blend: BlendMode,
+    /// Share of the visible pixels under the layer that it fully covered,
+    /// only set by the order lint when above its threshold
+    pub hides: Option<f32>,
+    /// The layer as it was drawn, tinted and transformed, only kept when asked for
+    pub image: Option<DynamicImage>,
+}
+
+/// Find a layer in the first folder that has it
+pub fn find_layer_path(layer_folders: &[PathBuf], name: &str) -> Option<PathBuf> {
+    if is_url(name) {
+        return Some(PathBuf::from(name));
+    }
+    layer_folders
+        .iter()
+        .map(|folder| force_png_path(folder, name))
+        .find(|path| path.is_file())
+}
+
+/// Replace every `glob:dir/pattern` layer by the names of the matching PNG layers,
+/// sorted, a name found in several folders being used once. A tint after the
+/// pattern applies to every match. Also returns the patterns that matched nothing.
+pub fn expand_layer_globs(
+    layers: &[String],
+    layer_folders: &[PathBuf],
+) -> (Vec<String>, Vec<String>) {
+    let mut expanded = Vec::with_capacity(layers.len());
+    let mut unmatched = Vec::new();
+
+    for layer in layers {
+        let Some(pattern) = layer.strip_prefix("glob:") else {
+            expanded.push(layer.clone());
+            continue;
+        };
+        let (pattern, tint) = split_tint(pattern);
+        let (dir, file_pattern) = match pattern.rsplit_once('/') {
+            Some((dir, file_pattern)) => (dir, file_pattern),
+            None => ("", pattern),
+        };
+        let Ok(glob) = globset::Glob::new(file_pattern) else {
+            unmatched.push(layer.clone());
+            continue;
+        };
+        let matcher = glob.compile_matcher();
+
+        let mut names = std::collections::BTreeSet::new();
+        for folder in layer_folders {
+            let Ok(entries) = std::fs::read_dir(folder.join(dir)) else {
+                continue;
+            };
+            for path in entries.flatten().map(|entry| entry.path()) {
+                if path.is_file()
+                    && path
+                        .extension()
+                        .is_some_and(|e| e.eq_ignore_ascii_case("png"))
+                    && let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+                    && matcher.is_match(stem)
+                {
+                    names.insert(if dir.is_empty() {
+                        stem.to_string()
+                    } else {
+                        format!("{}/{}", dir, stem)
+                    });
+                }
+            }
+        }
+
+        if names.is_empty() {
+            unmatched.push(layer.clone());
+        }
+        expanded.extend(names.into_iter().map(|name| match tint {
+            Some(tint) => format!("{}#{}", name, tint),
+            None => name,
+        }));
+    }
+
+    (expanded, unmatched)
+}
+
+/// Size of the first layer of a list that can be found
+pub fn layer_canvas_size(layer_folders: &[PathBuf], layers: &[String]) -> Option<(u32, u32)> {
+    layers
+        .iter()
+        .filter(|name| !name.is_empty() && *name != "none")
+        .filter_map(|name| find_layer_path(layer_folders, layer_base_name(name)))
+        .find_map(|path| image::image_dimensions(path).ok())
+}
+
+/// Modifiers applied to every layer of an element type
+#[derive(Debug, Clone)]
+pub struct LayerModifiers {
+    /// Used by layers whose descriptor has no tint
+    pub tint: Option<String>,
+    /// Multiplies the alpha of every layer
+    pub opacity: f32,
+}
+
+impl Default for LayerModifiers {
+    fn default() -> Self {
+        LayerModifiers {
+            tint: None,
+            opacity: 1.0,
+        }
+    }
+}
+
+/// Multiply the alpha of every pixel by `factor`, clamped to 0.0..=1.0
+pub fn scale_alpha(image: &mut DynamicImage, factor: f32) {
+    let factor = factor.clamp(0.0, 1.0);
+    if factor == 1.0 {
+        return;
+    }
+    if let DynamicImage::ImageRgba16(rgba) = image {
+        for pixel in rgba.pixels_mut() {
+            pixel[3] = (pixel[3] as f32 * factor).round() as u16;
+        }
+        return;
+    }
+    let mut rgba = image.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * factor).round() as u8;
+    }
+    *image = DynamicImage::ImageRgba8(rgba);
+}
+
+/// Open a layer and recolor it if a HEX tint is given, in its descriptor or
+/// else in the element defaults. Returns its trace along with the image,
+/// `None` if it can't be opened.
+fn decode_layer(
+    layer_folders: &[PathBuf],
+    layer_name: &str,
+    defaults: &LayerModifiers,
+    max_dimension: u32,
+    canvas: (u32, u32),
+    high_depth: bool,
+) -> (LayerTrace, Option<DynamicImage>) {
+    // Split name, optional HEX color and transforms, invalid scales being
+    // ignored like invalid colors
+    let descriptor = layer_name;
+    let (layer_name, transforms) = split_transforms(layer_name);
+    let factor = transforms.scale.and_then(|factor| parse_scale(factor).ok());
+    let (base_name, hex_color) = split_tint(layer_name);
+    let hex_color = hex_color.or(defaults.tint.as_deref());
+
+    // Build the full path to the layer image, reporting the
+    // highest priority folder when no folder has it
+    let (layer_img_path, layer_img) = match parse_generated(base_name) {
+        Some(border) => (
+            PathBuf::from(base_name),
+            border
+                .ok()
+                .map(|border| draw_border(canvas.0, canvas.1, &border)),
+        ),
+        None => {
+            let path = find_layer_path(layer_folders, base_name)
+                .unwrap_or_else(|| force_png_path(&layer_folders[0], base_name));
+            let layer_img = open_image(&path, max_dimension).ok();
+            (path, layer_img)
+        }
+    };
+
+    // Process the layer image if it could be opened
+    let processed_img = layer_img.map(|layer_img| {
+        timings::record(Stage::Composite, || {
+            // Work on RGBA whatever the color type of the file (gray, indexed, RGB, 16-bit),
+            // at the depth of the canvas
+            let mut layer_img = if high_depth {
+                DynamicImage::ImageRgba16(layer_img.to_rgba16())
+            } else {
+                DynamicImage::ImageRgba8(layer_img.to_rgba8())
+            };
+            if transforms.premultiplied {
+                unpremultiply(&mut layer_img);
+            }
+
+            // Recolor grayscale layer if HEX specified, only where the mask lets it
+            let mut layer_img = match hex_color.map(|hex| tint_image(&layer_img, hex)) {
+                Some(Ok(colored)) => match transforms.mask {
+                    Some(mask) => match find_layer_path(layer_folders, mask)
+                        .ok_or_else(|| "not found".to_string())
+                        .and_then(|path| open_image(&path, max_dimension))
+                    {
+                        Ok(mask) => blend_by_mask(&layer_img, &colored, &mask),
+                        Err(e) => {
+                            log::warn!(
+                                "Ignoring the tint of layer '{}': mask '{}': {}",
+                                base_name,
+                                mask,
+                                e
+                            );
+                            layer_img
+                        }
+                    },
+                    None => colored,
+                },
+                _ => layer_img,
+            };
+            if let Some(factor) = factor {
+                layer_img = scale_centered(&layer_img, factor);
+            }
+            scale_alpha(&mut layer_img, defaults.opacity);
+            layer_img
+        })
+    });
+
+    let trace = LayerTrace {
+        name: base_name.to_string(),
+        path: layer_img_path,
+        tint: hex_color.map(str::to_string),
+        found: processed_img.is_some(),
+        transforms: descriptor[layer_name.len()..].to_string(),
+        blend: transforms.blend,
+        hides: None,
+        image: None,
+    };
+    (trace, processed_img)
+}
+
+/// What to draw in place of a layer that can't be found or opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnMissingLayer {
+    /// Leave it out
+    #[default]
+    Skip,
+    /// Draw a magenta and black checkerboard over the whole canvas
+    Placeholder,
+    /// Leave it out, the caller failing the image
+    Error,
+}
+
+impl FromStr for OnMissingLayer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(OnMissingLayer::Skip),
+            "placeholder" => Ok(OnMissingLayer::Placeholder),
+            "error" => Ok(OnMissingLayer::Error),
+            _ => Err(format!(
+                "Unknown mode '{}', expected skip, placeholder or error",
+                s
+            )),
+        }
+    }
+}
+
+/// How layers are stacked
+#[derive(Debug, Clone, Copy)]
+pub struct StackOptions {
+    /// Threads of the shared pool decoding the layers of a single image
+    pub jobs: usize,
+    /// Layers wider or taller than this are treated as missing
+    pub max_dimension: u32,
+    pub on_missing: OnMissingLayer,
+    /// Report layers fully covering more than this share of the visible pixels under them
+    pub lint_order: Option<f32>,
+    /// Keep the image of every drawn layer in its trace
+    pub keep_images: bool,
+}
+
+/// Checkerboard of 8 by 8 cells of two colors, `even` in the top-left corner
+pub fn checkerboard(width: u32, height: u32, even: Rgba<u8>, odd: Rgba<u8>) -> DynamicImage {
+    let cell = (width.max(height) / 8).max(1);
+    DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| {
+        if (x / cell + y / cell).is_multiple_of(2) {
+            even
+        } else {
+            odd
+        }
+    }))
+}
+
+/// Magenta and black checkerboard, showing where a layer is missing
+fn missing_layer_placeholder(width: u32, height: u32) -> DynamicImage {
+    checkerboard(
+        width,
+        height,
+        Rgba([255, 0, 255, 255]),
+        Rgba([0, 0, 0, 255]),
+    )
+}
+
+/// Apply layers using the provided layer folders, searched in order
+/// Layers are decoded by up to `options.jobs` threads of the shared pool, then composited in order.
+/// Layers without a tint of their own use the one of `defaults`.
+/// Returns a trace of every layer in stacking order.
 pub fn stack_layers(
     input_image: &mut DynamicImage,
-    input_image_path: &PathBuf,
-    layer_folder: &Path,
-    layers: &Vec<String>,
-) -> Vec<String> {
-    let mut missing_layers = Vec::new();
-    let mut missing_layer_paths = Vec::new();
+    layer_folders: &[PathBuf],
+    layers: &[String],
+    defaults: &LayerModifiers,
+    options: &StackOptions,
+) -> Vec<LayerTrace> {
+    crate::profile_span!("stack_layers", layers = layers.len());
+    let StackOptions {
+        jobs,
+        max_dimension,
+        ..
+    } = *options;
+
+    // Skip empty or "none"
+    let layers: Vec<&String> = layers
+        .iter()
+        .filter(|name| !name.is_empty() && *name != "none")
+        .collect();
+
+    let mut traces = Vec::with_capacity(layers.len());
+    let canvas = (input_image.width(), input_image.height());
+    let high_depth = matches!(input_image, DynamicImage::ImageRgba16(_));
+
+    // Automatic layers decode every candidate, the choice depends on the image so far
+    let decode = |name: &&String| match auto_candidates(name) {
+        Some(candidates) => candidates
+            .iter()
+            .map(|candidate| {
+                decode_layer(
+                    layer_folders,
+                    candidate,
+                    defaults,
+                    max_dimension,
+                    canvas,
+                    high_depth,
+                )
+            })
+            .collect(),
+        None => vec![decode_layer(
+            layer_folders,
+            name,
+            defaults,
+            max_dimension,
+            canvas,
+            high_depth,
+        )],
+    };
+
+    if jobs > 1 && layers.len() > 1 {
+        // Splitting in at most `jobs` pieces caps the threads used by a single image
+        let min_len = layers.len().div_ceil(jobs);
+        let decoded: Vec<Vec<(LayerTrace, Option<DynamicImage>)>> = layers
+            .par_iter()
+            .with_min_len(min_len)
+            .map(decode)
+            .collect();
+
+        for candidates in decoded {
+            overlay_decoded(input_image, candidates, options, &mut traces);
+        }
+    } else {
+        for name in &layers {
+            overlay_decoded(input_image, decode(name), options, &mut traces);
+        }
+    }
+
+    traces
+}
+
+/// Candidates of a `name:auto(a,b,...)` layer, `None` for other layers
+pub fn auto_candidates(descriptor: &str) -> Option<Vec<&str>> {
+    let (_, list) = descriptor.split_once(":auto(")?;
+    let list = list.strip_suffix(')')?;
+    Some(
+        list.split(',')
+            .map(str::trim)
+            .filter(|candidate| !candidate.is_empty())
+            .collect(),
+    )
+}
+
+/// Alternatives of a `first|second|...` layer, tried in order. A `|` between
+/// parentheses belongs to its alternative.
+pub fn layer_alternatives(descriptor: &str) -> Vec<&str> {
+    let mut alternatives = Vec::new();
+    let (mut depth, mut start) = (0_usize, 0);
+    for (index, c) in descriptor.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '|' if depth == 0 => {
+                alternatives.push(&descriptor[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    alternatives.push(&descriptor[start..]);
+    alternatives
+}
+
+/// The first alternative of a layer that can be drawn, the first one when none can
+pub fn resolve_fallback<'a>(descriptor: &'a str, layer_folders: &[PathBuf]) -> &'a str {
+    let alternatives = layer_alternatives(descriptor);
+    alternatives
+        .iter()
+        .find(|alternative| {
+            let name = layer_base_name(alternative);
+            parse_generated(name).is_some() || find_layer_path(layer_folders, name).is_some()
+        })
+        .unwrap_or(&alternatives[0])
+}
+
+/// Replace every `first|second|...` layer by the alternative that is drawn
+pub fn resolve_fallbacks(layers: &[String], layer_folders: &[PathBuf]) -> Vec<String> {
+    layers
+        .iter()
+        .map(|descriptor| resolve_fallback(descriptor, layer_folders).to_string())
+        .collect()
+}
+
+/// Every layer a descriptor may stand for: each of its alternatives,
+/// and their candidates for automatic layers
+pub fn layer_choices(descriptor: &str) -> Vec<&str> {
+    layer_alternatives(descriptor)
+        .into_iter()
+        .flat_map(|alternative| auto_candidates(alternative).unwrap_or_else(|| vec![alternative]))
+        .collect()
+}
+
+/// Overlay a decoded layer on top of the input image, picking among the
+/// candidates of an automatic layer the one contrasting most with it
+fn overlay_decoded(
+    input_image: &mut DynamicImage,
+    mut candidates: Vec<(LayerTrace, Option<DynamicImage>)>,
+    options: &StackOptions,
+    traces: &mut Vec<LayerTrace>,
+) {
+    if candidates.is_empty() {
+        return;
+    }
+
+    let chosen = if candidates.len() > 1
+        && let Some(background) = average_luminance(input_image)
+    {
+        candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, img))| Some((i, average_luminance(img.as_ref()?)?)))
+            .max_by(|(_, a), (_, b)| (a - background).abs().total_cmp(&(b - background).abs()))
+            .map_or(0, |(i, _)| i)
+    } else {
+        // Without anything to contrast with, the first candidate that exists
+        candidates
+            .iter()
+            .position(|(_, img)| img.is_some())
+            .unwrap_or(0)
+    };
 
-    for layer_name in layers {
-        // Skip empty or "none"
-        if layer_name.is_empty() || layer_name == "none" {
+    let (mut trace, mut processed_img) = candidates.swap_remove(chosen);
+    if processed_img.is_none() && options.on_missing == OnMissingLayer::Placeholder {
+        processed_img = Some(missing_layer_placeholder(
+            input_image.width(),
+            input_image.height(),
+        ));
+    }
+    if let Some(processed_img) = processed_img {
+        // Difference layers are meant to be drawn over everything
+        if let Some(threshold) = options.lint_order
+            && trace.blend == BlendMode::Normal
+        {
+            trace.hides = hidden_share(input_image, &processed_img).filter(|&s| s > threshold);
+        }
+        timings::record(Stage::Composite, || {
+            blend(input_image, &processed_img, trace.blend)
+        });
+        if options.keep_images {
+            trace.image = Some(processed_img);
+        }
+    }
+    traces.push(trace);
+}
+
+/// Share of the visible pixels of `under` that fully opaque pixels of `layer`,
+/// drawn at its origin, cover. `None` when nothing is visible under it.
+pub fn hidden_share(under: &DynamicImage, layer: &DynamicImage) -> Option<f32> {
+    let under = under.to_rgba8();
+    let layer = layer.to_rgba8();
+    let (mut visible, mut hidden) = (0_usize, 0_usize);
+    for (x, y, pixel) in under.enumerate_pixels() {
+        if pixel[3] == 0 {
             continue;
         }
+        visible += 1;
+        if layer.get_pixel_checked(x, y).is_some_and(|p| p[3] == 255) {
+            hidden += 1;
+        }
+    }
+    (visible > 0).then(|| hidden as f32 / visible as f32)
+}
 
-        // Split name and optional HEX color
-        let mut parts = layer_name.splitn(2, '#');
-        let base_name = parts.next().unwrap();
-        let hex_color = parts.next();
+/// Average relative luminance of the visible pixels of an image, from 0.0 to 1.0,
+/// weighted by their alpha. `None` when the image is fully transparent.
+pub fn average_luminance(img: &DynamicImage) -> Option<f32> {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
 
-        // Build the full path to the layer image
-        let layer_img_path = force_png_path(layer_folder, base_name);
+    let (mut sum, mut weight) = (0.0, 0.0);
+    for pixel in img.to_rgba8().pixels() {
+        let [r, g, b, a] = pixel.0;
+        let a = a as f32 / 255.0;
+        sum += (0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)) * a;
+        weight += a;
+    }
+    (weight > 0.0).then(|| sum / weight)
+}
 
-        // Try opening the layer image
-        match image::open(&layer_img_path) {
-            Ok(layer_img) => {
-                let mut processed_img = layer_img.clone();
+/// Group all missing layers of a trace under the input image path
+pub fn group_missing_layers(input_image_path: &Path, traces: &[LayerTrace]) -> Option<String> {
+    let mut missing = traces.iter().filter(|t| !t.found).peekable();
+    missing.peek()?;
 
-                // Recolor grayscale layer if HEX specified
-                if let Some(hex) = hex_color {
-                    let gray_img = layer_img.to_luma_alpha8();
-                    if let Ok(colored) = colorize_grayscale_image(&gray_img, hex, 37) {
-                        processed_img = DynamicImage::ImageRgba8(colored);
-                    }
-                }
+    let mut grouped = format!("{}:\n", input_image_path.display());
+    for trace in missing {
+        grouped.push_str(&format!("\t- {}\n", trace.path.display()));
+    }
+    Some(grouped.trim_end().to_string())
+}
+
+/// Write the layer trace of an output next to it as `<name>.layers.txt`
+pub fn write_layer_trace(
+    output_path: &Path,
+    meta: &BTreeMap<String, serde_json::Value>,
+    traces: &[LayerTrace],
+) -> std::io::Result<()> {
+    let mut content = String::new();
+    for (key, value) in meta {
+        content.push_str(&format!("# {}: {}\n", key, value));
+    }
+    for (i, trace) in traces.iter().enumerate() {
+        content.push_str(&format!(
+            "{}. {} | path: {} | tint: {} | transforms: {} | blend: {} | {}\n",
+            i + 1,
+            trace.name,
+            trace.path.display(),
+            trace.tint.as_deref().unwrap_or("none"),
+            if trace.transforms.is_empty() {
+                "none"
+            } else {
+                &trace.transforms
+            },
+            match trace.blend {
+                BlendMode::Normal => "normal",
+                BlendMode::Difference => "difference",
+            },
+            if trace.found { "found" } else { "missing" }
+        ));
+    }
+    std::fs::write(output_path.with_extension("layers.txt"), content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn tilde_only_starts_partial_tints() {
+        assert_eq!(split_tint("frame~ff0000:50"), ("frame", Some("~ff0000:50")));
+        assert_eq!(split_tint("old~frame"), ("old~frame", None));
+        assert_eq!(
+            split_tint("old~frame#ff0000"),
+            ("old~frame", Some("ff0000"))
+        );
+    }
+
+    fn pixels(colors: &[[u8; 4]]) -> DynamicImage {
+        let mut image = RgbaImage::new(colors.len() as u32, 1);
+        for (pixel, color) in image.pixels_mut().zip(colors) {
+            *pixel = Rgba(*color);
+        }
+        DynamicImage::ImageRgba8(image)
+    }
+
+    #[test]
+    fn color_matrix() {
+        let original = pixels(&[[10, 20, 30, 255], [255, 255, 255, 128], [0, 0, 0, 0]]);
+        let mut image = original.clone();
+        apply_color_matrix(&mut image, &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image, original);
+
+        let sepia = [
+            0.393, 0.769, 0.189, 0.349, 0.686, 0.168, 0.272, 0.534, 0.131,
+        ];
+        apply_color_matrix(&mut image, &sepia);
+        assert_eq!(
+            image,
+            pixels(&[[25, 22, 17, 255], [255, 255, 239, 128], [0, 0, 0, 0]])
+        );
+    }
+
+    #[test]
+    fn override_folder_shadows_shared_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let (overrides, shared) = (dir.path().join("override"), dir.path().join("shared"));
+        for path in [
+            overrides.join("frame.png"),
+            shared.join("frame.png"),
+            shared.join("bg.png"),
+        ] {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            RgbaImage::new(1, 1).save(path).unwrap();
+        }
 
-                // Overlay the layer on top of the input image
-                overlay(input_image, &processed_img, 0, 0);
+        let folders = [overrides.clone(), shared.clone()];
+        assert_eq!(
+            find_layer_path(&folders, "frame"),
+            Some(overrides.join("frame.png"))
+        );
+        assert_eq!(find_layer_path(&folders, "bg"), Some(shared.join("bg.png")));
+        assert_eq!(find_layer_path(&folders, "gone"), None);
+    }
+
+    #[test]
+    fn hex_prefixes_and_case() {
+        assert_eq!(normalize_hex(" 0XffAA00 "), Ok("ffAA00"));
+        assert_eq!(hex_to_rgb("0xFFAA00"), Ok((0xff, 0xaa, 0x00)));
+        assert_eq!(hex_to_rgb("#FFAA00"), Ok((0xff, 0xaa, 0x00)));
+        assert_eq!(hex_to_rgb("ffaa00"), Ok((0xff, 0xaa, 0x00)));
+        assert!(hex_to_rgb("ff aa 00").is_err());
+    }
+
+    #[test]
+    fn vertical_gradient_tint() {
+        let mask = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 5, Rgba([255; 4])));
+        let tinted = tint_image(&mask, "ff0000:0000ff:vgrad").unwrap().to_rgba8();
+        assert_eq!(tinted.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(tinted.get_pixel(1, 2).0, [128, 0, 128, 255]);
+        assert_eq!(tinted.get_pixel(1, 4).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn sharpen_increases_edge_contrast() {
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba([100, 100, 100, 255]));
+        for x in 4..8 {
+            for y in 0..8 {
+                image.put_pixel(x, y, Rgba([150, 150, 150, 255]));
             }
-            Err(_) => {
-                // Collect missing layer paths first
-                missing_layer_paths.push(layer_img_path);
+        }
+        let mut sharpened = DynamicImage::ImageRgba8(image);
+        sharpen(&mut sharpened, 1.0, 0);
+        let sharpened = sharpened.to_rgba8();
+
+        let (dark, light) = (sharpened.get_pixel(3, 4).0, sharpened.get_pixel(4, 4).0);
+        assert!(dark[0] < 100 && light[0] > 150, "{:?} {:?}", dark, light);
+        assert_eq!(dark[3], 255);
+        // Flat areas are left alone
+        assert_eq!(sharpened.get_pixel(0, 4).0, [100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn names_with_and_without_extension() {
+        let base = Path::new("Perks");
+        for name in ["icon", "icon.png", "icon.PNG"] {
+            assert_eq!(strip_image_extension(name), "icon");
+            assert_eq!(force_png_path(base, name), base.join("icon.png"));
+        }
+        assert_eq!(force_png_path(base, "icon.v2"), base.join("icon.v2.png"));
+    }
+
+    #[test]
+    fn hsl_and_hsv_colors() {
+        assert_eq!(parse_color("hsl(0,100%,50%)"), Ok((255, 0, 0)));
+        assert_eq!(parse_color("HSV(120, 100%, 100%)"), Ok((0, 255, 0)));
+        assert_eq!(parse_color("hsl(240,100%,50%)"), parse_color("#0000ff"));
+        assert!(parse_color("hsl(0,120%,50%)").is_err());
+        assert!(parse_color("hsl(0,100%)").is_err());
+
+        // Colors written back as HSL come back within rounding
+        for (r, g, b) in [(171, 113, 60), (25, 155, 30), (172, 62, 227), (10, 10, 10)] {
+            let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+            let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+            let lightness = (max + min) / 2.0;
+            let chroma = max - min;
+            let saturation = if chroma == 0.0 {
+                0.0
+            } else {
+                chroma / (1.0 - (2.0 * lightness - 1.0).abs())
+            };
+            let hue = if chroma == 0.0 {
+                0.0
+            } else if max == r {
+                60.0 * ((g - b) / chroma).rem_euclid(6.0)
+            } else if max == g {
+                60.0 * ((b - r) / chroma + 2.0)
+            } else {
+                60.0 * ((r - g) / chroma + 4.0)
+            };
+            let spec = format!(
+                "hsl({},{}%,{}%)",
+                hue,
+                saturation * 100.0,
+                lightness * 100.0
+            );
+            let (r2, g2, b2) = parse_color(&spec).unwrap();
+            for (original, parsed) in [(r, r2), (g, g2), (b, b2)] {
+                let original = (original * 255.0).round() as i32;
+                assert!((original - parsed as i32).abs() <= 1, "{}", spec);
             }
         }
     }
 
-    // Group all missing layers under the input image path
-    if !missing_layer_paths.is_empty() {
-        let mut grouped = format!("{}:\n", input_image_path.display());
-        for path in missing_layer_paths {
-            grouped.push_str(&format!("\t- {}\n", path.display()));
+    #[test]
+    fn globs_expand_to_sorted_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let (overrides, shared) = (dir.path().join("override"), dir.path().join("shared"));
+        let files = [
+            overrides.join("fx/glow_b.png"),
+            shared.join("fx/glow_b.png"),
+            shared.join("fx/glow_a.png"),
+            shared.join("fx/glow_c.txt"),
+            shared.join("fx/halo.png"),
+        ];
+        for path in files {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
         }
-        missing_layers.push(grouped.trim_end().to_string());
+
+        let layers = ["bg", "glob:fx/glow_*#ff0000", "glob:fx/nothing_*"].map(String::from);
+        let (expanded, unmatched) = expand_layer_globs(&layers, &[overrides, shared]);
+        assert_eq!(expanded, ["bg", "fx/glow_a#ff0000", "fx/glow_b#ff0000"]);
+        assert_eq!(unmatched, ["glob:fx/nothing_*"]);
     }
 
-    missing_layers
+    #[test]
+    fn margins_are_normalized() {
+        let padded = |(x, y): (u32, u32)| {
+            let mut img = RgbaImage::new(10, 10);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                img.put_pixel(x + dx, y + dy, Rgba([255, 0, 0, 255]));
+            }
+            normalize_margin(&DynamicImage::ImageRgba8(img), 3).unwrap()
+        };
+        let (near, far) = (padded((1, 1)), padded((6, 3)));
+        assert_eq!(near, far);
+        assert_eq!((near.width(), near.height()), (8, 8));
+        assert_eq!(opaque_bounds(&near), Some((3, 3, 2, 2)));
+
+        let one_pixel = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255])));
+        let error = normalize_margin(&one_pixel, DEFAULT_MAX_DIMENSION).unwrap_err();
+        assert!(error.contains("1x1 visible pixels"), "{}", error);
+    }
+
+    #[test]
+    fn hex_errors_show_the_bad_part() {
+        let error = |hex: &str| hex_to_rgb(hex).unwrap_err();
+        assert_eq!(error("ffzz00"), "Invalid green value 'zz' at position 2");
+        assert_eq!(
+            error("#ff00"),
+            "Hex color must be 6 characters long: 'ff00'"
+        );
+        assert_eq!(error("ff00+1"), "Invalid blue value '+1' at position 4");
+        assert_eq!(error("ffé000"), "Invalid green value 'é0' at position 2");
+        assert!(error("ff 000").contains("'ff 000'"));
+    }
+
+    #[test]
+    fn scaled_layers_stay_centered() {
+        // A 50x50 square in the middle of a 100x100 layer
+        let square = RgbaImage::from_fn(100, 100, |x, y| {
+            let inside = (25..75).contains(&x) && (25..75).contains(&y);
+            Rgba([255, 0, 0, if inside { 255 } else { 0 }])
+        });
+        let scaled = scale_centered(&DynamicImage::ImageRgba8(square), 1.2).to_rgba8();
+        assert_eq!(scaled.dimensions(), (100, 100));
+
+        // Grown to 60x60 in the 120x120 layer, cropped by 10 pixels on each side
+        let mostly_opaque: Vec<(u32, u32)> = scaled
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] >= 128)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        let (xs, ys): (Vec<u32>, Vec<u32>) = mostly_opaque.iter().copied().unzip();
+        assert_eq!((xs.iter().min(), xs.iter().max()), (Some(&20), Some(&79)));
+        assert_eq!((ys.iter().min(), ys.iter().max()), (Some(&20), Some(&79)));
+        assert_eq!(mostly_opaque.len(), 60 * 60);
+    }
+
+    #[test]
+    fn dominant_color_of_the_largest_cluster() {
+        // Mostly red with some blue, the transparent pixels being left out
+        let img = RgbaImage::from_fn(10, 10, |x, y| match (x, y) {
+            (0..=5, _) => Rgba([250, 10, 10, 255]),
+            (6..=7, _) => Rgba([10, 10, 250, 255]),
+            _ => Rgba([0, 255, 0, 0]),
+        });
+        let img = DynamicImage::ImageRgba8(img);
+        assert_eq!(dominant_color(&img, 2), Some((250, 10, 10)));
+        assert_eq!(dominant_color(&img, 1), Some((190, 10, 70)));
+        assert_eq!(dominant_color(&DynamicImage::new_rgba8(2, 2), 3), None);
+
+        let layers =
+            resolve_dominant_tints(&["bg#dominant(2):scale(2)".to_string()], &[], &img, 64);
+        assert_eq!(layers, ["bg#fa0a0a:scale(2)"]);
+    }
+
+    #[test]
+    fn difference_is_black_where_images_match() {
+        let a = RgbaImage::from_fn(4, 4, |x, y| {
+            Rgba([(x * 60) as u8, (y * 60) as u8, 100, 255])
+        });
+        let mut b = a.clone();
+        b.put_pixel(2, 1, Rgba([255, 0, 100, 255]));
+        let (a, b) = (DynamicImage::ImageRgba8(a), DynamicImage::ImageRgba8(b));
+
+        let mut blended = a.clone();
+        blend(&mut blended, &a, BlendMode::Difference);
+        assert!(
+            blended
+                .to_rgba8()
+                .pixels()
+                .all(|pixel| pixel.0 == [0, 0, 0, 255])
+        );
+        let mut blended = a.clone();
+        blend(&mut blended, &b, BlendMode::Difference);
+        assert_eq!(blended.to_rgba8().get_pixel(2, 1).0, [135, 60, 0, 255]);
+
+        let (diff, differing) = difference_image(&a, &b).unwrap();
+        assert_eq!(differing, 1);
+        let diff = diff.to_rgb8();
+        assert_eq!(diff.get_pixel(2, 1).0, [135, 60, 0]);
+        assert_eq!(
+            diff.pixels().filter(|pixel| pixel.0 != [0, 0, 0]).count(),
+            1
+        );
+        assert_eq!(difference_image(&a, &a).unwrap().1, 0);
+        assert!(difference_image(&a, &DynamicImage::new_rgba8(2, 2)).is_err());
+    }
+
+    #[test]
+    fn generated_border_is_opaque_at_the_edges_only() {
+        let border = parse_generated("frame:gen(2,#00ff00,0)").unwrap().unwrap();
+        assert_eq!(border.color, (0, 255, 0));
+        let image = draw_border(10, 8, &border).to_rgba8();
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let on_edge = x < 2 || y < 2 || x >= 8 || y >= 6;
+            assert_eq!(
+                pixel.0,
+                [0, 255, 0, if on_edge { 255 } else { 0 }],
+                "({}, {})",
+                x,
+                y
+            );
+        }
+
+        // Rounded corners leave the very corner out
+        let rounded = parse_generated("frame:gen(2,hsl(0,100%,50%),4)")
+            .unwrap()
+            .unwrap();
+        assert_eq!(rounded.color, (255, 0, 0));
+        let image = draw_border(10, 10, &rounded).to_rgba8();
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        assert_eq!(image.get_pixel(5, 0)[3], 255);
+        assert_eq!(image.get_pixel(5, 5)[3], 0);
+
+        assert!(parse_generated("frame").is_none());
+        assert!(parse_generated("frame:gen(2,#00ff00)").unwrap().is_err());
+    }
+
+    #[test]
+    fn coverage_condition_gates_a_layer() {
+        // A quarter of the item is visible
+        let item = RgbaImage::from_fn(4, 4, |x, y| {
+            Rgba([0, 0, 0, if x < 2 && y < 2 { 255 } else { 0 }])
+        });
+        let variables = ItemVariables::measure(&DynamicImage::ImageRgba8(item));
+        assert_eq!(variables.coverage, 0.25);
+
+        let layers: Vec<String> = [
+            "bg",
+            "glow#ff0000:if(coverage<0.3):scale(2)",
+            "frame:if(coverage >= 0.5)",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            resolve_conditions(&layers, &variables),
+            ["bg", "glow#ff0000:scale(2)"]
+        );
+        let layers = vec![
+            "edge:if(coverage<=0.25)".to_string(),
+            "bad:if(size<1)".to_string(),
+        ];
+        assert_eq!(resolve_conditions(&layers, &variables), ["edge"]);
+    }
+
+    #[test]
+    fn fallback_is_used_when_the_primary_is_absent() {
+        let dir = tempfile::tempdir().unwrap();
+        RgbaImage::new(1, 1)
+            .save(dir.path().join("plain.png"))
+            .unwrap();
+        let folders = [dir.path().to_path_buf()];
+
+        let layers: Vec<String> = [
+            "fancy#ff0000|plain#00ff00",
+            "plain|fancy",
+            "fancy|gone",
+            "fancy|border:gen(1,#000000,0)",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            resolve_fallbacks(&layers, &folders),
+            ["plain#00ff00", "plain", "fancy", "border:gen(1,#000000,0)"]
+        );
+        assert_eq!(layer_alternatives("a:auto(b|c)|d"), ["a:auto(b|c)", "d"]);
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners() {
+        let mut img =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([200, 200, 200, 128])));
+        vignette(&mut img, 0.5);
+        let img = img.to_rgba8();
+        let (corner, center) = (img.get_pixel(0, 0), img.get_pixel(5, 5));
+        assert!(corner[0] < center[0], "{:?} {:?}", corner, center);
+        assert!(center[0] >= 195);
+        // Symmetric, alpha untouched
+        assert_eq!(img.get_pixel(9, 9), corner);
+        assert!(img.pixels().all(|pixel| pixel[3] == 128));
+    }
+
+    #[test]
+    fn partial_tint_ends() {
+        let layer = pixels(&[[200, 200, 200, 255], [100, 100, 100, 128]]);
+        let tinted = |tint: &str| tint_image(&layer, tint).unwrap().to_rgba8().into_raw();
+        assert_eq!(tinted("~ff0000:0"), layer.to_rgba8().into_raw());
+        assert_eq!(tinted("~ff0000:100"), tinted("ff0000"));
+        assert_eq!(tinted("~ff0000:50")[..4], [200, 100, 100, 255]);
+
+        // At 16 bits too
+        let layer16 = DynamicImage::ImageRgba16(layer.to_rgba16());
+        let tinted16 = |tint: &str| tint_image(&layer16, tint).unwrap().to_rgba16().into_raw();
+        assert_eq!(tinted16("~ff0000:0"), layer16.to_rgba16().into_raw());
+        assert_eq!(tinted16("~ff0000:100"), tinted16("ff0000"));
+        assert!(parse_tint("~ff0000:101").is_err());
+    }
+
+    #[test]
+    fn trailing_numbers_are_padded() {
+        assert_eq!(pad_trailing_number("addon_1", 3), "addon_001");
+        assert_eq!(pad_trailing_number("addon_1234", 3), "addon_1234");
+        assert_eq!(pad_trailing_number("addon", 3), "addon");
+    }
+
+    #[test]
+    fn heatmap_is_hot_only_where_images_differ() {
+        let a = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        let mut b = a.clone();
+        b.put_pixel(1, 2, Rgba([255, 255, 255, 255]));
+        let (a, b) = (DynamicImage::ImageRgba8(a), DynamicImage::ImageRgba8(b));
+
+        let (heatmap, change) = difference_heatmap(&a, &a).unwrap();
+        assert_eq!(change, 0.0);
+        assert!(
+            heatmap
+                .to_rgb8()
+                .pixels()
+                .all(|pixel| pixel.0 == [0, 0, 255])
+        );
+
+        let (heatmap, change) = difference_heatmap(&a, &b).unwrap();
+        assert_eq!(change, 1.0 / 16.0);
+        let heatmap = heatmap.to_rgb8();
+        assert_eq!(heatmap.get_pixel(1, 2).0, [255, 0, 0]);
+        assert_eq!(
+            heatmap
+                .pixels()
+                .filter(|pixel| pixel.0 == [0, 0, 255])
+                .count(),
+            15
+        );
+    }
+
+    #[test]
+    fn remap_replaces_only_the_given_color() {
+        let remap: ColorRemap = "ff0000=ff8000".parse().unwrap();
+        let mut img = pixels(&[
+            [255, 0, 0, 255],
+            [255, 0, 0, 128],
+            [250, 0, 0, 255],
+            [0, 0, 255, 255],
+        ]);
+
+        remap_colors(&mut img, &[remap], 0);
+        assert_eq!(
+            img.to_rgba8()
+                .pixels()
+                .map(|pixel| pixel.0)
+                .collect::<Vec<_>>(),
+            [
+                [255, 128, 0, 255],
+                [255, 128, 0, 128],
+                [250, 0, 0, 255],
+                [0, 0, 255, 255],
+            ]
+        );
+
+        // Nearly equal colors are replaced within the tolerance
+        let mut near = pixels(&[[250, 0, 0, 255]]);
+        remap_colors(&mut near, &[remap], 5);
+        assert_eq!(near.to_rgba8().get_pixel(0, 0).0, [255, 128, 0, 255]);
+    }
+
+    #[test]
+    fn dithered_gradients_trade_bands_for_noise() {
+        // Four 8-bit steps spread over 64 columns, each step a band when rounded
+        let (width, height) = (64, 16);
+        let value = |x: u32| 0x4000 + (x * 4 * 257 / width) as u16;
+        let gradient = ImageBuffer::from_fn(width, height, |x, _| {
+            let v = value(x);
+            Rgba([v, v, v, u16::MAX])
+        });
+        let dithered = dither_floyd_steinberg(&gradient);
+
+        let column = |x: u32| -> Vec<f32> {
+            (0..height)
+                .map(|y| dithered.get_pixel(x, y)[0] as f32)
+                .collect()
+        };
+        let noisy = (0..width)
+            .filter(|&x| column(x).iter().any(|&c| c != column(x)[0]))
+            .count();
+        assert!(noisy > width as usize / 2, "{} noisy columns", noisy);
+
+        // On average a column keeps the value of the gradient, unlike rounding it
+        let (mut dithered_error, mut rounded_error) = (0.0, 0.0);
+        for x in 0..width {
+            let exact = value(x) as f32 / 257.0;
+            let mean = column(x).iter().sum::<f32>() / height as f32;
+            dithered_error += (mean - exact).abs();
+            rounded_error += (exact.round() - exact).abs();
+        }
+        assert!(
+            dithered_error < rounded_error / 2.0,
+            "{} against {}",
+            dithered_error,
+            rounded_error
+        );
+        assert!(dithered.pixels().all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn posterized_gradient_has_as_many_values_as_levels() {
+        let gradient = RgbaImage::from_fn(256, 1, |x, _| {
+            Rgba([x as u8, 255 - x as u8, x as u8, x as u8])
+        });
+        let distinct = |img: &DynamicImage, channel: usize| {
+            let values: std::collections::BTreeSet<u8> = img
+                .to_rgba8()
+                .pixels()
+                .map(|pixel| pixel[channel])
+                .collect();
+            values.into_iter().collect::<Vec<_>>()
+        };
+
+        let mut img = DynamicImage::ImageRgba8(gradient.clone());
+        posterize(&mut img, 4, false);
+        for channel in 0..3 {
+            assert_eq!(distinct(&img, channel), [0, 85, 170, 255]);
+        }
+        assert_eq!(distinct(&img, 3).len(), 256);
+
+        posterize(&mut img, 4, true);
+        assert_eq!(distinct(&img, 3), [0, 85, 170, 255]);
+
+        let mut unchanged = DynamicImage::ImageRgba8(gradient.clone());
+        posterize(&mut unchanged, 256, true);
+        assert_eq!(unchanged.to_rgba8(), gradient);
+    }
+
+    #[test]
+    fn parallel_decoding_stacks_like_the_serial_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let colors = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 128],
+            [0, 0, 255, 64],
+            [255, 255, 255, 200],
+        ];
+        let mut layers = Vec::new();
+        for (i, color) in colors.iter().enumerate() {
+            let name = format!("layer{i}");
+            RgbaImage::from_fn(8, 8, |x, y| match (x + y) as usize % (i + 2) {
+                0 => Rgba([0, 0, 0, 0]),
+                _ => Rgba(*color),
+            })
+            .save(dir.path().join(format!("{name}.png")))
+            .unwrap();
+            layers.push(name);
+        }
+        layers.push("missing".to_string());
+        layers.push("layer1#00ffff".to_string());
+
+        let stacked = |jobs: usize| {
+            let mut canvas = DynamicImage::new_rgba8(8, 8);
+            let options = StackOptions {
+                jobs,
+                max_dimension: DEFAULT_MAX_DIMENSION,
+                on_missing: OnMissingLayer::Skip,
+                lint_order: None,
+                keep_images: false,
+            };
+            let traces = stack_layers(
+                &mut canvas,
+                &[dir.path().to_path_buf()],
+                &layers,
+                &LayerModifiers::default(),
+                &options,
+            );
+            let names: Vec<(String, bool)> = traces
+                .into_iter()
+                .map(|trace| (trace.name, trace.found))
+                .collect();
+            (canvas.to_rgba8(), names)
+        };
+
+        let serial = stacked(1);
+        assert_eq!(serial.1.len(), layers.len());
+        for jobs in [2, 4, 16] {
+            assert_eq!(stacked(jobs), serial, "with {} jobs", jobs);
+        }
+    }
 }
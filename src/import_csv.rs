@@ -0,0 +1,130 @@
+//! Conversion of a spreadsheet export into elements_layering.json
+
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Element type, then file name, then layers
+pub type Layering = BTreeMap<String, BTreeMap<String, Vec<String>>>;
+
+/// A row of the CSV, columns being matched by their header
+#[derive(Deserialize)]
+struct Row {
+    element: String,
+    filename: String,
+    /// Separated by `;`, as `|` separates the alternatives of a layer, e.g.
+    /// `bg;frame#ff0000|frame`
+    #[serde(default)]
+    layers: String,
+}
+
+/// Read a CSV with `element`, `filename` and `layers` columns in any order.
+/// Quoted fields may hold commas, every field is trimmed and empty lines are skipped.
+pub fn read(path: &Path) -> anyhow::Result<Layering> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut layering = Layering::new();
+    for (index, row) in reader.deserialize::<Row>().enumerate() {
+        // The header is line 1
+        let line = index + 2;
+        let row = row.with_context(|| format!("{}: invalid line {}", path.display(), line))?;
+        anyhow::ensure!(
+            !row.element.is_empty() && !row.filename.is_empty(),
+            "{}: line {} has no element or filename",
+            path.display(),
+            line
+        );
+
+        let layers = row
+            .layers
+            .split(';')
+            .map(str::trim)
+            .filter(|layer| !layer.is_empty())
+            .map(str::to_string)
+            .collect();
+        let previous = layering
+            .entry(row.element.clone())
+            .or_default()
+            .insert(row.filename.clone(), layers);
+        anyhow::ensure!(
+            previous.is_none(),
+            "{}: line {} repeats '{}/{}'",
+            path.display(),
+            line,
+            row.element,
+            row.filename
+        );
+    }
+    Ok(layering)
+}
+
+/// Convert a CSV and write it as JSON to `output`, or print it without one
+pub fn run(csv_path: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+    let layering = read(csv_path)?;
+    let json = serde_json::to_string_pretty(&layering)? + "\n";
+    match output {
+        Some(output) => {
+            std::fs::write(output, json)
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            let entries: usize = layering.values().map(BTreeMap::len).sum();
+            log::info!("Wrote {} entries to {}", entries, output.display());
+        }
+        None => print!("{}", json),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_keep_their_fallbacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layers.csv");
+        std::fs::write(
+            &path,
+            "filename,element,layers\na,Perks,\"bg; frame#ff0000|frame ;\"\n",
+        )
+        .unwrap();
+
+        let layering = read(&path).unwrap();
+        assert_eq!(layering["Perks"]["a"], ["bg", "frame#ff0000|frame"]);
+    }
+
+    #[test]
+    fn csv_becomes_the_layering() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("layers.csv");
+        std::fs::write(
+            &csv,
+            "layers,element,filename\n\
+             \"bg#ff0000; frame:auto(light,dark)\",Perks,a\n\
+             \n\
+             bg,Perks,b\n\
+             , Items , c\n",
+        )
+        .unwrap();
+        let output = dir.path().join("elements_layering.json");
+        run(&csv, Some(&output)).unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({
+                "Items": { "c": [] },
+                "Perks": { "a": ["bg#ff0000", "frame:auto(light,dark)"], "b": ["bg"] },
+            })
+        );
+        pack_creator::config::load_layering(&output).unwrap();
+
+        std::fs::write(&csv, "element,filename,layers\nPerks,a,bg\nPerks,a,frame\n").unwrap();
+        let error = read(&csv).unwrap_err().to_string();
+        assert!(error.ends_with("line 3 repeats 'Perks/a'"), "{}", error);
+    }
+}
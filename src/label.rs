@@ -0,0 +1,370 @@
+//! Text labels drawn onto outputs for debugging, with a TrueType font or a
+//! built-in 3x5 pixel font
+
+use ab_glyph::{Font as _, FontArc, PxScale, ScaleFont};
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// Corner of the image a label is anchored to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    #[default]
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Corner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "top-left" | "tl" => Ok(Corner::TopLeft),
+            "top-right" | "tr" => Ok(Corner::TopRight),
+            "bottom-left" | "bl" => Ok(Corner::BottomLeft),
+            "bottom-right" | "br" => Ok(Corner::BottomRight),
+            _ => Err(format!(
+                "Unknown corner '{}', expected top-left, top-right, bottom-left or bottom-right",
+                s
+            )),
+        }
+    }
+}
+
+/// A TrueType or OpenType font read from a file
+#[derive(Clone)]
+pub struct Font {
+    path: PathBuf,
+    font: FontArc,
+}
+
+impl Font {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        let font = FontArc::try_from_vec(bytes).map_err(|e| e.to_string())?;
+        Ok(Font {
+            path: path.to_path_buf(),
+            font,
+        })
+    }
+}
+
+impl std::fmt::Debug for Font {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Font").field(&self.path).finish()
+    }
+}
+
+/// How labels are drawn
+#[derive(Debug, Clone)]
+pub struct LabelStyle {
+    pub color: (u8, u8, u8),
+    pub corner: Corner,
+    /// Size of a font pixel in image pixels, text of `font` being as tall as
+    /// the built-in font and the space under it
+    pub scale: u32,
+    /// Font of the labels, the built-in pixel font when `None`
+    pub font: Option<Font>,
+}
+
+/// Rows of a glyph, the 3 low bits of each row being its pixels from left to right.
+/// Letters are uppercase only, unknown characters show as `?`.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        ' ' => [0b000; 5],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+/// Width of a line of text in image pixels
+fn text_width(text: &str, scale: u32) -> u32 {
+    (text.chars().count() as u32 * (GLYPH_WIDTH + 1) * scale).saturating_sub(scale)
+}
+
+/// Draw a line of text with its top-left corner at `left`, `top`.
+/// What falls outside of the image is cut.
+fn draw_text(
+    img: &mut DynamicImage,
+    text: &str,
+    (left, top): (i64, i64),
+    color: (u8, u8, u8),
+    scale: u32,
+) {
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let (r, g, b) = color;
+    for (index, c) in text.chars().enumerate() {
+        let glyph_left = left + (index as u32 * advance) as i64;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                // Fill the font pixel, skipping what falls outside of the image
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = glyph_left + (column * scale + dx) as i64;
+                        let y = top + (row as u32 * scale + dy) as i64;
+                        if (0..img.width() as i64).contains(&x)
+                            && (0..img.height() as i64).contains(&y)
+                        {
+                            img.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Size of a line of text drawn with `font` at `height` pixels
+fn font_text_size(font: &FontArc, text: &str, height: f32) -> (u32, u32) {
+    let scaled = font.as_scaled(PxScale::from(height));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    (width.ceil() as u32, scaled.height().ceil() as u32)
+}
+
+/// Draw a line of text with `font` at `height` pixels, its top-left corner at `left`,
+/// `top`, antialiased edges being blended over the image. What falls outside of
+/// the image is cut.
+fn draw_font_text(
+    img: &mut DynamicImage,
+    font: &FontArc,
+    text: &str,
+    (left, top): (i64, i64),
+    (r, g, b): (u8, u8, u8),
+    height: f32,
+) {
+    let scaled = font.as_scaled(PxScale::from(height));
+    let mut caret = left as f32;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(
+            scaled.scale(),
+            ab_glyph::point(caret, top as f32 + scaled.ascent()),
+        );
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|dx, dy, coverage| {
+            let x = bounds.min.x as i64 + dx as i64;
+            let y = bounds.min.y as i64 + dy as i64;
+            if !(0..img.width() as i64).contains(&x) || !(0..img.height() as i64).contains(&y) {
+                return;
+            }
+            // The text over what is under it, by the coverage of the pixel
+            let coverage = coverage.clamp(0.0, 1.0);
+            let under = img.get_pixel(x as u32, y as u32).0;
+            let under_alpha = under[3] as f32 / 255.0 * (1.0 - coverage);
+            let alpha = coverage + under_alpha;
+            if alpha <= 0.0 {
+                return;
+            }
+            let blend = |over: u8, under: u8| {
+                ((over as f32 * coverage + under as f32 * under_alpha) / alpha).round() as u8
+            };
+            let pixel = Rgba([
+                blend(r, under[0]),
+                blend(g, under[1]),
+                blend(b, under[2]),
+                (alpha * 255.0).round() as u8,
+            ]);
+            img.put_pixel(x as u32, y as u32, pixel);
+        });
+    }
+}
+
+/// Draw a line of text in a corner of an image, one font pixel away from its edges.
+/// Text that doesn't fit is cut.
+pub fn draw_label(img: &mut DynamicImage, text: &str, style: &LabelStyle) {
+    let scale = style.scale.max(1);
+    let font_height = ((GLYPH_HEIGHT + 1) * scale) as f32;
+    let (width, height) = match &style.font {
+        Some(font) => font_text_size(&font.font, text, font_height),
+        None => (text_width(text, scale), GLYPH_HEIGHT * scale),
+    };
+
+    let left = match style.corner {
+        Corner::TopLeft | Corner::BottomLeft => scale as i64,
+        Corner::TopRight | Corner::BottomRight => img.width() as i64 - width as i64 - scale as i64,
+    };
+    let top = match style.corner {
+        Corner::TopLeft | Corner::TopRight => scale as i64,
+        Corner::BottomLeft | Corner::BottomRight => {
+            img.height() as i64 - height as i64 - scale as i64
+        }
+    };
+    match &style.font {
+        Some(font) => draw_font_text(img, &font.font, text, (left, top), style.color, font_height),
+        None => draw_text(img, text, (left, top), style.color, scale),
+    }
+}
+
+/// White image with a row per color: a swatch of it, its name and its HEX,
+/// in black text `scale` image pixels per font pixel
+pub fn palette_legend(colors: &[(&str, (u8, u8, u8))], scale: u32) -> DynamicImage {
+    let scale = scale.max(1);
+    // Rows are as tall as the swatches, with a font pixel of margin around the text
+    let row = (GLYPH_HEIGHT + 2) * scale;
+    let labels: Vec<String> = colors
+        .iter()
+        .map(|(name, (r, g, b))| format!("{} #{:02x}{:02x}{:02x}", name, r, g, b))
+        .collect();
+    let text = labels
+        .iter()
+        .map(|label| text_width(label, scale))
+        .max()
+        .unwrap_or(0);
+    let width = scale + row + 2 * scale + text + scale;
+    let height = scale + colors.len() as u32 * (row + scale);
+
+    let mut legend = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        width,
+        height,
+        Rgba([255, 255, 255, 255]),
+    ));
+    for (index, ((_, (r, g, b)), label)) in colors.iter().zip(&labels).enumerate() {
+        let top = scale + index as u32 * (row + scale);
+        for y in top..top + row {
+            for x in scale..scale + row {
+                legend.put_pixel(x, y, Rgba([*r, *g, *b, 255]));
+            }
+        }
+        draw_text(
+            &mut legend,
+            label,
+            ((scale + row + 2 * scale) as i64, (top + scale) as i64),
+            (0, 0, 0),
+            scale,
+        );
+    }
+    legend
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::PALETTE;
+
+    #[test]
+    fn legend_has_a_swatch_row_per_color() {
+        let colors: Vec<(&str, (u8, u8, u8))> = PALETTE.entries().map(|(n, c)| (*n, *c)).collect();
+        let legend = palette_legend(&colors, 2).to_rgba8();
+
+        // Walk down the middle of the swatches, counting the runs of colored pixels
+        let x = 2 + (GLYPH_HEIGHT + 2) * 2 / 2;
+        let mut swatches = Vec::new();
+        let mut previous = Rgba([255; 4]);
+        for y in 0..legend.height() {
+            let pixel = *legend.get_pixel(x, y);
+            if pixel != previous && pixel != Rgba([255; 4]) {
+                swatches.push(pixel);
+            }
+            previous = pixel;
+        }
+        let expected: Vec<Rgba<u8>> = colors
+            .iter()
+            .map(|(_, (r, g, b))| Rgba([*r, *g, *b, 255]))
+            .collect();
+        assert_eq!(swatches, expected);
+    }
+
+    #[test]
+    fn font_labels_keep_their_case_in_their_corner() {
+        // Any TrueType font will do, the test is skipped where none is installed
+        let path = Path::new("/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf");
+        if !path.exists() {
+            return;
+        }
+        let style = LabelStyle {
+            color: (255, 0, 0),
+            corner: Corner::TopRight,
+            scale: 4,
+            font: Some(Font::open(path).unwrap()),
+        };
+        let label = |text: &str| {
+            let mut img = DynamicImage::new_rgba8(100, 40);
+            draw_label(&mut img, text, &style);
+            img.to_rgba8()
+        };
+
+        let lower = label("ab");
+        assert_ne!(lower, label("AB"));
+        // 24 pixels tall, 4 pixels away from the top right corner
+        let drawn: Vec<(u32, u32)> = lower
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] > 0)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert!(!drawn.is_empty());
+        assert!(
+            drawn
+                .iter()
+                .all(|&(x, y)| (50..96).contains(&x) && (4..28).contains(&y))
+        );
+        assert!(lower.pixels().all(|pixel| pixel[3] == 0 || pixel[0] == 255));
+
+        assert!(Font::open(Path::new("Cargo.toml")).is_err());
+    }
+}
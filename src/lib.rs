@@ -0,0 +1,11 @@
+//! Layering engine of the pack creator, also usable from other programs
+
+pub mod animation;
+pub mod config;
+pub mod helper;
+pub mod label;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod render;
+pub mod timings;
+pub mod watermark;
@@ -1,50 +1,142 @@
 use anyhow::Context;
+use checksums::Checksum;
+use clap::Parser;
+use cli::Warning;
 use colored::Colorize;
-use image::imageops::overlay;
-use rayon::prelude::*;
-use std::collections::HashMap;
-use std::fs::File;
+use pack_creator::animation::LayerSequence;
+use pack_creator::config::{self, BatchJob, FolderSource, OutputFormat};
+use pack_creator::helper;
+use pack_creator::label::{self, LabelStyle};
+use pack_creator::render::{
+    self, BitDepth, Dither, Flatten, OutputColor, OutputLayout, RenderOptions, StackCache, Task,
+    TaskEvent,
+};
+use pack_creator::timings::{self, Stage};
+use pack_creator::watermark::Watermark;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
-mod helper;
+mod atlas;
+mod cas;
+mod checksums;
+mod clean;
+mod cli;
+mod explain;
+mod heatmap;
+mod import_csv;
+mod resume;
+mod strip;
+mod tarball;
+mod validate;
+mod verify;
 
-type GameFolders = HashMap<String, HashMap<String, Vec<String>>>;
+fn main() -> anyhow::Result<()> {
+    let cli = cli::Cli::parse();
+
+    // RUST_LOG sets the level, --log-level overrides it. Each record is written in
+    // a single call so lines of parallel tasks don't interleave.
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    if let Some(level) = cli.log_level {
+        logger.filter_level(level);
+    }
+    logger.format_timestamp(None).format_target(false).init();
+
+    // The trace is written when the guard is dropped, once everything is done
+    #[cfg(feature = "profile")]
+    let _profile = match &cli.profile {
+        Some(path) => {
+            use tracing_subscriber::prelude::*;
+            let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+                .file(path)
+                .include_args(true)
+                .build();
+            // Not `init`, which would also take over the logger
+            tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+                .context("Failed to start the profile trace")?;
+            Some(guard)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "remote")]
+    pack_creator::remote::set_retries(cli.retries);
 
-#[derive(serde::Deserialize)]
-struct Settings {
-    layers_location: HashMap<String, String>,
-    output_path: Option<String>,
-    input_path: Option<String>,
+    match &cli.command {
+        Some(cli::Command::DiffImage { a, b, output }) => {
+            return diff_image(a, b, output, cli.max_dimension);
+        }
+        Some(cli::Command::ImportCsv { csv, output }) => {
+            return import_csv::run(csv, output.as_deref());
+        }
+        _ => {}
+    }
+
+    if let Some(path) = &cli.palette_legend {
+        return write_palette_legend(path);
+    }
+
+    match &cli.batch {
+        Some(batch_path) => run_batch(&cli, batch_path),
+        None => run_pack(&cli, &BatchJob::default(), &mut std::io::stdout()),
+    }
 }
 
-struct Task {
-    element_type: String,
-    filename: String,
-    layers: Vec<String>,
-    layer_folder: Option<PathBuf>,
+/// Render every pack of a batch file, carrying on after failures unless strict
+fn run_batch(cli: &cli::Cli, batch_path: &Path) -> anyhow::Result<()> {
+    let jobs = config::load_batch(batch_path)?;
+    let mut failed = Vec::new();
+    for (index, job) in jobs.iter().enumerate() {
+        log::info!(
+            "Pack {}/{}: {}",
+            index + 1,
+            jobs.len(),
+            job.settings.display()
+        );
+        if let Err(e) = run_pack(cli, job, &mut std::io::stdout()) {
+            log::error!("Pack {} failed: {:#}", index + 1, e);
+            anyhow::ensure!(!cli.strict, "Stopping the batch after pack {}", index + 1);
+            failed.push(index + 1);
+        }
+    }
+    anyhow::ensure!(
+        failed.is_empty(),
+        "{} of {} pack(s) failed: {:?}",
+        failed.len(),
+        jobs.len(),
+        failed
+    );
+    Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    // Measure processing time
+/// Render a single pack, its files given by `job`. The report of the run,
+/// `--print-config`, `--list-element-types` and `explain` are written to `out`.
+fn run_pack(cli: &cli::Cli, job: &BatchJob, out: &mut dyn std::io::Write) -> anyhow::Result<()> {
+    // Measure processing time, and the time of every stage of this pack only
     let start_time = Instant::now();
+    let start_totals = timings::Totals::now();
 
     // Load settings.json
-    let settings_file =
-        File::open("settings.json").context("settings.json must exist in the working directory")?;
-    let settings: Settings =
-        serde_json::from_reader(settings_file).context("Failed to parse settings.json")?;
+    let settings = {
+        pack_creator::profile_span!("load_settings");
+        config::load_settings(&job.settings)?
+    };
 
     let platform = helper::detect_platform();
-    println!("{}", format!("Platform: {:?}", platform).yellow());
+    log::info!("Platform: {:?}", platform);
 
     // Resolve input folder (use default if missing or empty)
-    let source_folder = helper::resolve_or_default(
-        settings.input_path.as_deref(),
-        Path::new("Source_Pack"),
-        platform,
-    );
+    let source_folder = match &job.source {
+        Some(source) => helper::resolve_full_path(source),
+        None => helper::resolve_or_default(
+            settings.input_path.as_deref(),
+            Path::new("Source_Pack"),
+            platform,
+        ),
+    };
 
     // Check if the input folder exists, else return an error
     anyhow::ensure!(
@@ -53,150 +145,1615 @@ fn main() -> anyhow::Result<()> {
         source_folder.display()
     );
 
-    println!(
-        "{}",
-        format!("Input folder: {}", source_folder.display()).yellow()
-    );
+    log::info!("Input folder: {}", source_folder.display());
+
+    // Load elements_layering.json, or the layering files given instead
+    let mut data = {
+        pack_creator::profile_span!("load_layering");
+        if cli.batch.is_some() || cli.layering.is_empty() {
+            config::load_layering(&job.layering)?
+        } else {
+            config::load_layerings(&cli.layering, cli.strict)?
+        }
+    };
+    let presets = match &cli.presets {
+        Some(path) => config::load_presets(path)?,
+        None => config::Presets::new(),
+    };
+    config::expand_presets(&mut data, &presets)?;
+
+    if let Some(cli::Command::Validate) = cli.command {
+        return validate::run(
+            &settings,
+            &data,
+            &source_folder,
+            cli.layer_fallback(),
+            platform,
+        );
+    }
+
+    if cli.list_element_types {
+        list_element_types(&settings, &data, cli.layer_fallback(), platform, out)?;
+        return Ok(());
+    }
 
     // Resolve output folder (use default if missing or empty)
-    let output_folder = helper::resolve_or_default(
-        settings.output_path.as_deref(),
-        Path::new("Output_Pack"),
-        platform,
-    );
+    let output_folder = match &job.output {
+        Some(output) => output.clone(),
+        None => helper::resolve_or_default(
+            settings.output_path.as_deref(),
+            Path::new("Output_Pack"),
+            platform,
+        ),
+    };
     let output_folder = helper::resolve_full_path(&output_folder);
     std::fs::create_dir_all(&output_folder)?;
-    println!(
-        "{}",
-        format!("Output folder: {}", output_folder.display()).yellow()
-    );
-
-    // Load elements_layering.json
-    let file = File::open("elements_layering.json")
-        .context("elements_layering.json must exist in the working directory")?;
+    log::info!("Output folder: {}", output_folder.display());
 
-    let data: GameFolders =
-        serde_json::from_reader(file).context("Failed to parse elements_layering.json")?;
+    if !cli.element_types.is_empty() {
+        select_element_types(&mut data, &cli.element_types, cli.strict)?;
+    }
 
     // Collect tasks
     let mut tasks = Vec::new();
-    for (element_type, elements) in &data {
-        let layer_folder_path = settings
-            .layers_location
-            .get(element_type)
-            .filter(|s| !s.is_empty())
-            .map(|s| {
-                let p = helper::resolve_full_path(&PathBuf::from(s));
-                if helper::is_path_compatible(&p, platform) && p.exists() {
-                    p
+    {
+        pack_creator::profile_span!("build_tasks");
+        for (element_type, elements) in &data {
+            let (layer_folders, folder_source) =
+                settings.layer_folders(element_type, cli.layer_fallback(), platform);
+            let fallback_note = match folder_source {
+                FolderSource::Settings => None,
+                FolderSource::LayerRoot => Some("using the folder found in the layer root"),
+                FolderSource::Default => Some("using the default layer folder"),
+                FolderSource::Missing => Some("using the working directory"),
+            };
+            if let Some(note) = fallback_note {
+                log::info!("No settings entry for '{}', {}", element_type, note);
+            }
+            log::info!(
+                "Layer folder for '{}': {}",
+                element_type,
+                if layer_folders.is_empty() {
+                    "none".to_string()
                 } else {
-                    PathBuf::new()
+                    layer_folders
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
                 }
+            );
+
+            let layer_defaults = settings.layer_defaults(element_type);
+            let opacity = settings.opacity(element_type);
+            for (entry_name, entry) in elements {
+                // An entry named `filename#RRGGBB` tints the item itself
+                let (filename, item_tint) = helper::split_tint(entry_name);
+
+                // Expand `glob:` layers against the layer folders
+                let (layers, unmatched_below) =
+                    helper::expand_layer_globs(&entry.layers, &layer_folders);
+                let (above_layers, unmatched_above) =
+                    helper::expand_layer_globs(&entry.above, &layer_folders);
+                for pattern in unmatched_below.iter().chain(&unmatched_above) {
+                    if !cli.reports(Warning::UnmatchedGlob) {
+                        continue;
+                    }
+                    log::warn!(
+                        "No layer matches '{}' for '{}/{}'",
+                        pattern,
+                        element_type,
+                        entry_name
+                    );
+                }
+
+                tasks.push(Task {
+                    element_type: element_type.clone(),
+                    filename: filename.to_string(),
+                    item_tint: item_tint.map(str::to_string),
+                    layers,
+                    above_layers,
+                    meta: entry.meta.clone(),
+                    layer_folders: layer_folders.clone(),
+                    layer_defaults: layer_defaults.clone(),
+                    opacity,
+                    output_name: settings.output_name(element_type).map(str::to_string),
+                    item_offset: entry.offset,
+                    item_inner: entry.inner,
+                });
+            }
+        }
+    }
+
+    // Decode the placeholder and the background once for every task
+    let placeholder = match &cli.placeholder {
+        Some(path) => Some(
+            helper::open_image(path, cli.max_dimension)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Failed to open placeholder {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let global_background = match &cli.global_background {
+        Some(path) => Some(
+            helper::open_image(path, cli.max_dimension)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Failed to open background {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    anyhow::ensure!(
+        cli.scales
+            .iter()
+            .all(|scale| scale.is_finite() && *scale > 0.0),
+        "--scales must be positive numbers, got {:?}",
+        cli.scales
+    );
+    if let Some(strength) = cli.vignette {
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&strength),
+            "--vignette must be between 0 and 1, got {}",
+            strength
+        );
+    }
+    if let Some(seconds) = cli.timeout {
+        anyhow::ensure!(
+            seconds.is_finite() && seconds > 0.0,
+            "--timeout must be a positive number of seconds, got {}",
+            seconds
+        );
+    }
+    if let Some(levels) = cli.posterize {
+        anyhow::ensure!(
+            (2..=256).contains(&levels),
+            "--posterize must be between 2 and 256, got {}",
+            levels
+        );
+    }
+    if let Some(percent) = cli.lint_order {
+        anyhow::ensure!(
+            (0.0..=100.0).contains(&percent),
+            "--lint-order must be between 0 and 100, got {}",
+            percent
+        );
+    }
+
+    // Effects that only work at 8 bits per channel, dropping a 16-bit canvas
+    let effects = [
+        ("--color-matrix", cli.color_matrix.is_some()),
+        ("--vignette", cli.vignette.is_some()),
+        ("--sharpen", cli.sharpen.is_some()),
+        ("--remap", !cli.remap.is_empty()),
+        ("--watermark", cli.watermark.is_some()),
+        ("--debug-labels", cli.debug_labels),
+    ];
+    let set_options = |options: &[(&'static str, bool)]| -> Vec<&'static str> {
+        options
+            .iter()
+            .filter_map(|&(option, set)| set.then_some(option))
+            .collect()
+    };
+    if cli.bit_depth == BitDepth::Sixteen {
+        let format = cli.format.or(settings.defaults.format).unwrap_or_default();
+        let mut conflicts = set_options(&[
+            ("--format jpeg", format == OutputFormat::Jpeg),
+            ("--output-color rgb", cli.output_color == OutputColor::Rgb),
+            ("--animate", cli.animate.is_some()),
+        ]);
+        conflicts.extend(set_options(&effects));
+        anyhow::ensure!(
+            conflicts.is_empty(),
+            "--bit-depth 16 can't be combined with {}",
+            conflicts.join(", ")
+        );
+    }
+    if cli.dither != Dither::None {
+        let conflicts = set_options(&effects);
+        anyhow::ensure!(
+            conflicts.is_empty(),
+            "--dither can't be combined with {}",
+            conflicts.join(", ")
+        );
+    }
+
+    // Decode the watermark once for every task
+    let watermark = match &cli.watermark {
+        Some(path) => {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&cli.watermark_opacity),
+                "--watermark-opacity must be between 0 and 1, got {}",
+                cli.watermark_opacity
+            );
+            let image = helper::open_image(path, cli.max_dimension)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Failed to open watermark {}", path.display()))?;
+            Some(Watermark::new(
+                image,
+                cli.watermark_mode,
+                cli.watermark_opacity,
+                cli.watermark_fit,
+            ))
+        }
+        None => None,
+    };
+    let label_font = match &cli.label_font {
+        Some(path) => Some(
+            label::Font::open(path)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Failed to read font {}", path.display()))?,
+        ),
+        None => None,
+    };
+
+    let options = RenderOptions {
+        source_folder,
+        output_folder,
+        jobs_per_image: cli.jobs_per_image,
+        color_matrix: cli.color_matrix,
+        rarity_pattern: cli.rarity_pattern.clone(),
+        remap: cli.remap.clone(),
+        bit_depth: cli.bit_depth,
+        dither: cli.dither,
+        remap_tolerance: cli.remap_tolerance,
+        posterize: cli.posterize.map(|levels| (levels, cli.posterize_alpha)),
+        trace_layers: cli.trace_layers,
+        canvas_fill: cli.canvas_fill.and_then(|fill| fill.0),
+        global_background,
+        frame_delay_ms: cli.animate,
+        placeholder,
+        normalize_margin: cli.normalize_margin,
+        output_size: cli.size.or(settings.defaults.output_size),
+        format: cli.format.or(settings.defaults.format).unwrap_or_default(),
+        pad_numbers: cli.pad_numbers,
+        layout: if cli.mirror_tree {
+            OutputLayout::MirrorTree
+        } else {
+            OutputLayout::Flat
+        },
+        filter: cli.filter.or(settings.defaults.filter).unwrap_or_default(),
+        max_dimension: cli.max_dimension,
+        on_missing_layer: cli.on_missing_layer,
+        chunk_size: cli.chunk_size,
+        skip_empty: cli.skip_empty,
+        output_color: cli.output_color,
+        flatten: cli.flatten.unwrap_or(Flatten::Color(
+            cli.canvas_fill
+                .and_then(|fill| fill.0)
+                .unwrap_or((255, 255, 255)),
+        )),
+        mirror: cli.mirror,
+        watermark,
+        debug_labels: cli.debug_labels.then_some(LabelStyle {
+            color: cli.label_color,
+            corner: cli.label_corner,
+            scale: cli.label_scale,
+            font: label_font,
+        }),
+        vignette: cli.vignette,
+        sharpen: cli.sharpen,
+        sharpen_threshold: cli.sharpen_threshold,
+        seed: cli.seed,
+        max_skips: cli.max_skips,
+        timeout: cli.timeout.map(std::time::Duration::from_secs_f64),
+        scales: cli.scales.clone(),
+        reproducible: cli.reproducible,
+        stack_cache: cli.cache_stacks.then(StackCache::default),
+        lint_order: cli.lint_order.map(|percent| percent / 100.0),
+        export_layers: cli.export_layers.clone(),
+    };
+
+    if cli.print_config {
+        return print_config(cli, &settings, &data, platform, &options, out);
+    }
+
+    if let Some(sample) = &cli.sample_pixel {
+        return sample_pixel(&tasks, &options, sample);
+    }
+
+    if let Some(cli::Command::Explain {
+        entry: (element_type, filename),
+    }) = &cli.command
+    {
+        let task = find_task(&tasks, element_type, filename)?;
+        explain::run(out, task, &options)?;
+        return Ok(());
+    }
+
+    // Drop tasks matched by the ignore file
+    let ignore_path = match &cli.ignore_file {
+        Some(path) => {
+            anyhow::ensure!(
+                path.exists(),
+                "Ignore file does not exist: {}",
+                path.display()
+            );
+            path.clone()
+        }
+        None => options.source_folder.join(".deadbydaylightignore"),
+    };
+    if let Some(ignore) = config::load_ignore_file(&ignore_path, &options.source_folder)? {
+        let before = tasks.len();
+        tasks.retain(|task| {
+            let relative = Path::new(&task.element_type).join(&task.filename);
+            !ignore
+                .matched_path_or_any_parents(&relative, false)
+                .is_ignore()
+        });
+        log::info!(
+            "Ignored {} file(s) from {}",
+            before - tasks.len(),
+            ignore_path.display()
+        );
+    }
+
+    if let Some(path) = &cli.list_assets {
+        return list_assets(&tasks, &options, path);
+    }
+    if cli.verify_inputs {
+        let assets = referenced_assets(&tasks, &options);
+        verify::run(assets.iter().map(|(path, kind)| (path.as_path(), *kind)))?;
+    }
+
+    let atlas_path = cli.atlas.as_deref().map(helper::resolve_full_path);
+    if let Some(cli::Command::Clean { dry_run }) = cli.command {
+        // Outputs and their layer traces are kept
+        let expected: HashSet<PathBuf> = tasks
+            .iter()
+            .flat_map(|task| {
+                let path = render::output_path(task, &options);
+                expected_outputs(task, &options)
+                    .into_iter()
+                    .chain([path.with_extension("layers.txt")])
             })
-            .unwrap_or_else(PathBuf::new);
-        let layer_folder_path = helper::resolve_full_path(&layer_folder_path);
-        println!(
-            "{}",
-            format!(
-                "Layer folder for '{}': {}",
-                element_type,
-                layer_folder_path.display()
+            .chain(
+                [Checksum::Sha256, Checksum::Md5]
+                    .map(|checksum| options.output_folder.join(checksum.file_name())),
+            )
+            .chain(tasks.iter().flat_map(|task| {
+                let (image, json) = strip::paths(&task.element_type, &options.output_folder);
+                [image, json]
+            }))
+            .chain(
+                atlas_path
+                    .iter()
+                    .flat_map(|path| [path.clone(), atlas::json_path(path)]),
             )
-            .yellow()
+            .collect();
+        return clean::run(&expected, &options.output_folder, dry_run);
+    }
+
+    // Unchanged and resumed tasks keep their outputs, which are listed and laid out too
+    let strip_icons: Vec<strip::Icon> = match cli.strip {
+        Some(_) => tasks
+            .iter()
+            .map(|task| strip::Icon::new(task, &options))
+            .collect(),
+        None => Vec::new(),
+    };
+    let atlas_icons: Vec<atlas::Icon> = match &atlas_path {
+        Some(_) => tasks
+            .iter()
+            .map(|task| atlas::Icon::new(task, &options))
+            .collect(),
+        None => Vec::new(),
+    };
+    let summed_outputs: Vec<PathBuf> = match cli.checksums {
+        Checksum::None => Vec::new(),
+        _ => tasks
+            .iter()
+            .flat_map(|task| {
+                let strips = cli.strip.map(|_| {
+                    let (image, json) = strip::paths(&task.element_type, &options.output_folder);
+                    [image, json]
+                });
+                expected_outputs(task, &options)
+                    .into_iter()
+                    .chain(strips.into_iter().flatten())
+            })
+            .collect(),
+    };
+    let copied_outputs: Vec<PathBuf> = match (&cli.heatmap_against, &cli.cas_output) {
+        (None, None) => Vec::new(),
+        _ => tasks
+            .iter()
+            .flat_map(|task| expected_outputs(task, &options))
+            .collect(),
+    };
+
+    // Drop tasks whose source image and layers are older than --only-changed-since
+    if let Some(time) = cli.only_changed_since {
+        let before = tasks.len();
+        tasks.retain(|task| resume::changed_since(task, &options, time));
+        log::info!(
+            "Rendering {} of {} task(s) changed since the given time",
+            tasks.len(),
+            before
         );
+    }
 
-        for (filename, layers) in elements {
-            tasks.push(Task {
-                element_type: element_type.clone(),
-                filename: filename.clone(),
-                layers: layers.clone(),
-                layer_folder: Some(layer_folder_path.clone()),
-            });
-        }
+    // Drop tasks the resume manifest has current outputs for
+    let mut manifest = match &cli.resume {
+        Some(path) => Some(resume::Manifest::load(path)?),
+        None => None,
+    };
+    let options_hash = resume::options_hash(&options);
+    let mut input_hashes = HashMap::new();
+    if let Some(manifest) = &manifest {
+        let before = tasks.len();
+        tasks.retain(|task| {
+            let key = resume::task_key(task);
+            let inputs = resume::input_hash(task, &options);
+            let current = manifest.is_current(
+                &key,
+                &inputs,
+                &options_hash,
+                &expected_outputs(task, &options),
+            );
+            input_hashes.insert(key, inputs);
+            !current
+        });
+        log::info!("Resuming: {} task(s) already done", before - tasks.len());
+    }
+
+    if cli.interleave {
+        tasks = render::interleave_element_types(tasks);
     }
 
-    let skipped_images = Arc::new(Mutex::new(Vec::new()));
-    let missing_layers = Arc::new(Mutex::new(Vec::new()));
+    let written: Mutex<BTreeMap<String, Vec<PathBuf>>> = Mutex::new(BTreeMap::new());
+    let skipped_images = Mutex::new(Vec::new());
+    let missing_layers = Mutex::new(Vec::new());
+    let empty_outputs = Mutex::new(Vec::new());
+    let oversized_outputs = Mutex::new(Vec::new());
+    let processed = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
 
     // Process images in parallel
-    tasks.par_iter().for_each(|task| {
-        let Task {
-            element_type,
-            filename,
-            layers,
-            layer_folder,
-        } = task;
-
-        let item_img_path = helper::force_png_path(&source_folder.join(element_type), filename);
-
-        let item_img = match image::open(&item_img_path) {
-            Ok(img) => img,
-            Err(_) => {
-                let mut skipped = skipped_images.lock().unwrap();
-                skipped.push(filename.clone());
-                eprintln!(
-                    "{}",
-                    format!(
-                        "Skipping file '{}': could not open '{}'",
-                        filename,
-                        item_img_path.display()
-                    )
-                    .red()
-                );
-                return;
-            }
-        };
+    let outcome = {
+        pack_creator::profile_span!("render");
+        render::process_tasks(
+            &tasks,
+            &options,
+            Some(&|event| match event {
+                TaskEvent::Skipped { task, reason } => {
+                    if cli.reports(Warning::SkippedImage) {
+                        log::warn!("Skipping file '{}': {}", task.filename, reason);
+                    }
+                    skipped_images.lock().unwrap().push(task.filename.clone());
+                }
+                TaskEvent::Placeholder { task, reason } => {
+                    if cli.reports(Warning::SkippedImage) {
+                        log::warn!("Using placeholder for '{}': {}", task.filename, reason);
+                    }
+                    skipped_images.lock().unwrap().push(task.filename.clone());
+                }
+                TaskEvent::MissingLayer { report, .. } => {
+                    missing_layers.lock().unwrap().push(report);
+                }
+                TaskEvent::Failed {
+                    output_path, error, ..
+                } => {
+                    log::error!("Failed to produce '{}': {}", output_path.display(), error);
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+                TaskEvent::Empty { task } => {
+                    empty_outputs
+                        .lock()
+                        .unwrap()
+                        .push(format!("{}/{}", task.element_type, task.filename));
+                }
+                TaskEvent::Succeeded {
+                    task,
+                    output_path,
+                    bytes,
+                } => {
+                    log::debug!("Wrote '{}'", output_path.display());
+                    if let Some(max_bytes) = cli.max_output_bytes
+                        && bytes > max_bytes
+                    {
+                        if cli.reports(Warning::OversizedOutput) {
+                            log::warn!(
+                                "'{}' is {} bytes, over the {} byte budget",
+                                output_path.display(),
+                                bytes,
+                                max_bytes
+                            );
+                        }
+                        oversized_outputs.lock().unwrap().push(format!(
+                            "{} ({} bytes)",
+                            output_path.display(),
+                            bytes
+                        ));
+                    }
+                    processed.fetch_add(1, Ordering::Relaxed);
+                    if manifest.is_some() {
+                        written
+                            .lock()
+                            .unwrap()
+                            .entry(resume::task_key(task))
+                            .or_default()
+                            .push(output_path.to_path_buf());
+                    }
+                }
+                TaskEvent::Started { .. } => {}
+            }),
+        )
+    };
+
+    match &outcome {
+        Ok(()) => writeln!(out, "\n{}", "Processing complete!".green())?,
+        Err(_) => writeln!(out, "\n{}", "Processing stopped!".red())?,
+    }
+
+    let skipped = skipped_images.lock().unwrap();
+    print_warnings(
+        out,
+        "Skipped images",
+        &skipped,
+        cli.reports(Warning::SkippedImage),
+    )?;
+
+    let missing = missing_layers.lock().unwrap();
+    print_warnings(
+        out,
+        "Skipped layers",
+        &missing,
+        cli.reports(Warning::MissingLayer),
+    )?;
+
+    let empty = empty_outputs.lock().unwrap();
+    print_warnings(
+        out,
+        "Empty outputs (not written)",
+        &empty,
+        cli.reports(Warning::EmptyOutput),
+    )?;
+
+    #[cfg(feature = "remote")]
+    let failed_downloads: Vec<String> = pack_creator::remote::failures()
+        .into_iter()
+        .map(|(url, e)| format!("{}: {}", url, e))
+        .collect();
+    #[cfg(not(feature = "remote"))]
+    let failed_downloads: Vec<String> = Vec::new();
+    print_warnings(out, "Failed downloads", &failed_downloads, true)?;
+
+    let mut oversized = oversized_outputs.into_inner().unwrap();
+    oversized.sort();
+    print_warnings(
+        out,
+        "Outputs over --max-output-bytes",
+        &oversized,
+        cli.reports(Warning::OversizedOutput),
+    )?;
 
-        let mut final_img = image::DynamicImage::new_rgba8(item_img.width(), item_img.height());
+    let elapsed = start_time.elapsed();
+    writeln!(
+        out,
+        "{}",
+        format!("Total processing time: {:.2?}", elapsed).cyan()
+    )?;
+
+    if let Some(cache) = &options.stack_cache {
+        writeln!(
+            out,
+            "{}",
+            format!("Layer stacks reused: {}", cache.hits()).cyan()
+        )?;
+    }
 
-        if let Some(layer_folder) = layer_folder {
-            let missing =
-                helper::stack_layers(&mut final_img, &item_img_path, layer_folder, layers);
-            let mut missing_lock = missing_layers.lock().unwrap();
-            missing_lock.extend(missing);
+    if cli.timings {
+        let totals = timings::Totals::now();
+        writeln!(out, "{}", "Time summed over every thread:".cyan())?;
+        for stage in [Stage::Decode, Stage::Composite, Stage::Save] {
+            let spent = totals.since(&start_totals, stage);
+            writeln!(out, "{}", format!(" - {:?}: {:.2?}", stage, spent).cyan())?;
         }
+    }
 
-        overlay(&mut final_img, &item_img, 0, 0);
+    if outcome.is_ok()
+        && let Some(cell) = cli.strip
+    {
+        let count = strip::write(&strip_icons, &options, cell)?;
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "{} strip(s) written to {}",
+                count,
+                options.output_folder.display()
+            )
+            .green()
+        )?;
+    }
+
+    if outcome.is_ok()
+        && let Some(path) = &atlas_path
+    {
+        let count = atlas::write(&atlas_icons, path, &mut options.rng("atlas"))?;
+        writeln!(
+            out,
+            "{}",
+            format!("{} output(s) packed in {}", count, path.display()).green()
+        )?;
+    }
+
+    // Strips are written first so their outputs are summed too
+    if outcome.is_ok() && cli.checksums != Checksum::None {
+        let (path, count) =
+            checksums::write(cli.checksums, &options.output_folder, &summed_outputs)?;
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "Checksums of {} output(s) written to {}",
+                count,
+                path.display()
+            )
+            .green()
+        )?;
+    }
+
+    if outcome.is_ok()
+        && let Some(store) = &cli.cas_output
+    {
+        let store = helper::resolve_full_path(store);
+        let (stored, mapped) = cas::write(&store, &options.output_folder, &copied_outputs)?;
+        writeln!(
+            out,
+            "{}",
+            format!(
+                "{} output(s) stored as {} file(s) in {}",
+                mapped,
+                stored,
+                store.display()
+            )
+            .green()
+        )?;
+    }
+
+    if outcome.is_ok()
+        && let Some(reference) = &cli.heatmap_against
+    {
+        let folder = helper::resolve_full_path(&cli.heatmap_folder);
+        heatmap::write(
+            &copied_outputs,
+            &options,
+            reference,
+            &folder,
+            cli.heatmap_top,
+        )?;
+    }
 
-        let element_folder_name = Path::new(element_type)
-            .file_name()
-            .unwrap_or_else(|| std::ffi::OsStr::new("Unknown"));
-        let output_path = output_folder
-            .join(element_folder_name)
-            .join(format!("{filename}.png"));
+    if outcome.is_ok()
+        && let Some(path) = &cli.tar_gz
+    {
+        let path = helper::resolve_full_path(path);
+        let archived = tarball::write(&options.output_folder, &path)?;
+        writeln!(
+            out,
+            "{}",
+            format!("{} file(s) archived in {}", archived, path.display()).green()
+        )?;
+    }
 
-        if let Some(parent) = output_path.parent() {
-            let _ = std::fs::create_dir_all(parent);
+    // Record what was written even when the run stopped early, to resume from there
+    if let (Some(manifest), Some(path)) = (&mut manifest, &cli.resume) {
+        for (key, outputs) in written.into_inner().unwrap() {
+            let inputs = input_hashes.remove(&key).unwrap_or_default();
+            manifest.record(key, inputs, &options_hash, &outputs);
         }
+        manifest.save(path)?;
+    }
+
+    // Last, so the final line of stdout can be piped to jq whatever was written
+    if cli.summary_json_stdout {
+        let summary = Summary {
+            processed: processed.load(Ordering::Relaxed),
+            skipped: skipped.len(),
+            missing_layers: missing.len(),
+            empty: empty.len(),
+            failed: failed.load(Ordering::Relaxed),
+            failed_downloads: failed_downloads.len(),
+            oversized: oversized.len(),
+            elapsed_ms: elapsed.as_millis() as u64,
+        };
+        writeln!(out, "{}", summary.json_line())?;
+    }
+
+    anyhow::ensure!(
+        outcome.is_err() || !cli.strict || oversized.is_empty(),
+        "{} output(s) are larger than {} bytes",
+        oversized.len(),
+        cli.max_output_bytes.unwrap_or_default()
+    );
+
+    outcome.map_err(|e| match e {
+        render::Stopped::TooManySkips(_) => anyhow::anyhow!(
+            "{}, check that the input folder {} is the right one",
+            e,
+            options.source_folder.display()
+        ),
+        render::Stopped::TimedOut(_) => anyhow::anyhow!("{}", e),
+    })
+}
+
+/// Keep only the element types named, ignoring case. Names the configuration
+/// doesn't have are reported, and fail the run when strict.
+fn select_element_types(
+    data: &mut config::GameFolders,
+    names: &[String],
+    strict: bool,
+) -> anyhow::Result<()> {
+    let unknown: Vec<&String> = names
+        .iter()
+        .filter(|name| !data.keys().any(|key| key.eq_ignore_ascii_case(name)))
+        .collect();
+    for name in &unknown {
+        log::warn!("No element type '{}' in the configuration", name);
+    }
+    anyhow::ensure!(
+        !strict || unknown.is_empty(),
+        "Unknown element type(s): {}",
+        unknown
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
 
-        if let Err(e) = final_img.save(&output_path) {
-            eprintln!("Failed to save '{}': {}", output_path.display(), e);
+    data.retain(|key, _| names.iter().any(|name| key.eq_ignore_ascii_case(name)));
+    log::info!("Rendering {} element type(s)", data.len());
+    Ok(())
+}
+
+/// List the warnings of a category under a heading, or only count them when suppressed
+fn print_warnings(
+    out: &mut dyn std::io::Write,
+    heading: &str,
+    warnings: &[String],
+    shown: bool,
+) -> std::io::Result<()> {
+    if warnings.is_empty() {
+        return Ok(());
+    }
+    if !shown {
+        return writeln!(
+            out,
+            "{}",
+            format!("{}: {} (suppressed)", heading, warnings.len()).red()
+        );
+    }
+    writeln!(out, "{}", format!("{}:", heading).red())?;
+    for s in warnings {
+        writeln!(out, " - {}", s)?;
+    }
+    Ok(())
+}
+
+/// Every file a task writes its output to, one per scale
+fn expected_outputs(task: &Task, options: &RenderOptions) -> Vec<PathBuf> {
+    let path = render::output_path(task, options);
+    options
+        .output_scales()
+        .into_iter()
+        .map(|scale| render::scaled_output_path(&path, scale))
+        .collect()
+}
+
+/// Draw the named colors of the palette, by name, into a PNG
+fn write_palette_legend(path: &Path) -> anyhow::Result<()> {
+    let mut colors: Vec<(&str, (u8, u8, u8))> = helper::PALETTE
+        .entries()
+        .map(|(name, color)| (*name, *color))
+        .collect();
+    colors.sort();
+    pack_creator::label::palette_legend(&colors, 4)
+        .save(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!(
+        "{}",
+        format!("{} color(s) drawn in {}", colors.len(), path.display()).green()
+    );
+    Ok(())
+}
+
+/// Print every element type with its number of entries and where its layers are read from
+fn list_element_types(
+    settings: &config::Settings,
+    data: &config::GameFolders,
+    fallback: config::LayerFallback,
+    platform: helper::Platform,
+    out: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    let sorted: BTreeMap<&String, _> = data.iter().collect();
+    for (element_type, elements) in sorted {
+        writeln!(
+            out,
+            "{}: {} entr{}",
+            element_type.bold(),
+            elements.len(),
+            if elements.len() == 1 { "y" } else { "ies" }
+        )?;
+        let (folders, _) = settings.layer_folders(element_type, fallback, platform);
+        if folders.is_empty() {
+            writeln!(out, " - no layer folder")?;
         }
-    });
+        for folder in folders {
+            let status = if folder.is_dir() {
+                "found".green()
+            } else {
+                "missing".red()
+            };
+            writeln!(out, " - {} ({})", folder.display(), status)?;
+        }
+    }
+    Ok(())
+}
 
-    println!("\n{}", "Processing complete!".green());
+/// Print the options in effect once settings.json and the command line are combined
+fn print_config(
+    cli: &cli::Cli,
+    settings: &config::Settings,
+    data: &config::GameFolders,
+    platform: helper::Platform,
+    options: &RenderOptions,
+    out: &mut dyn std::io::Write,
+) -> anyhow::Result<()> {
+    let name = |value: &dyn std::fmt::Debug| format!("{:?}", value).to_lowercase();
 
-    let skipped = skipped_images.lock().unwrap();
-    if !skipped.is_empty() {
-        println!("{}", "Skipped images:".red());
-        for s in skipped.iter() {
-            println!(" - {}", s);
+    let element_types: BTreeMap<&String, serde_json::Value> = data
+        .keys()
+        .map(|element_type| {
+            let (folders, source) =
+                settings.layer_folders(element_type, cli.layer_fallback(), platform);
+            let defaults = settings.layer_defaults(element_type);
+            let config = serde_json::json!({
+                "layer_folders": folders,
+                "layer_folders_from": name(&source),
+                "layer_tint": defaults.tint,
+                "layer_opacity": defaults.opacity,
+                "opacity": settings.opacity(element_type),
+                "output_name": settings.output_name(element_type),
+            });
+            (element_type, config)
+        })
+        .collect();
+
+    // Split in groups merged into one object, a single json! being too deep for the compiler
+    let drawing = serde_json::json!({
+        "platform": name(&platform),
+        "input_folder": options.source_folder,
+        "output_folder": options.output_folder,
+        "format": name(&options.format),
+        "layout": name(&options.layout),
+        "pad_numbers": options.pad_numbers,
+        "output_color": name(&options.output_color),
+        "bit_depth": match options.bit_depth {
+            BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+        },
+        "dither": match options.dither {
+            Dither::None => "none",
+            Dither::FloydSteinberg => "floyd-steinberg",
+        },
+        "flatten": match options.flatten {
+            Flatten::Color((r, g, b)) => format!("color:#{:02x}{:02x}{:02x}", r, g, b),
+            Flatten::Checker => "checker".to_string(),
+        },
+        "normalize_margin": options.normalize_margin,
+        "output_size": options.output_size,
+        "filter": name(&options.filter),
+        "vignette": options.vignette,
+        "sharpen": options.sharpen,
+        "sharpen_threshold": options.sharpen_threshold,
+        "canvas_fill": options
+            .canvas_fill
+            .map(|(r, g, b)| format!("#{:02x}{:02x}{:02x}", r, g, b)),
+        "color_matrix": options.color_matrix,
+        "remap": options
+            .remap
+            .iter()
+            .map(|remap| {
+                let hex = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+                format!("{}={}", hex(remap.from), hex(remap.to))
+            })
+            .collect::<Vec<_>>(),
+        "remap_tolerance": options.remap_tolerance,
+        "posterize": cli.posterize,
+        "posterize_alpha": cli.posterize_alpha,
+        "rarity_pattern": options.rarity_pattern.as_str(),
+    });
+    let running = serde_json::json!({
+        "threads": rayon::current_num_threads(),
+        "jobs_per_image": options.jobs_per_image,
+        "chunk_size": options.chunk_size,
+        "interleave": cli.interleave,
+        "max_skips": options.max_skips,
+        "timeout": cli.timeout,
+        "cache_stacks": options.stack_cache.is_some(),
+        "lint_order": cli.lint_order,
+        "scales": options.output_scales(),
+        "reproducible": options.reproducible,
+    });
+    let extras = serde_json::json!({
+        "checksums": name(&cli.checksums),
+        "max_output_bytes": cli.max_output_bytes,
+        "cas_output": cli.cas_output,
+        "tar_gz": cli.tar_gz,
+        "heatmap_against": cli.heatmap_against,
+        "heatmap_folder": cli.heatmap_folder,
+        "heatmap_top": cli.heatmap_top,
+        "strip": cli.strip,
+        "atlas": cli.atlas,
+        "max_dimension": options.max_dimension,
+        "frame_delay_ms": options.frame_delay_ms,
+        "mirror": name(&options.mirror),
+        "placeholder": cli.placeholder,
+        "layering": cli.layering,
+        "presets": cli.presets,
+        "global_background": cli.global_background,
+        "watermark": cli.watermark,
+        "watermark_mode": cli.watermark.as_ref().map(|_| name(&cli.watermark_mode)),
+        "watermark_opacity": cli.watermark.as_ref().map(|_| cli.watermark_opacity),
+        "watermark_fit": cli.watermark.as_ref().map(|_| cli.watermark_fit),
+        "skip_empty": options.skip_empty,
+        "trace_layers": options.trace_layers,
+        "export_layers": options.export_layers,
+        "seed": options.seed,
+        "element_types": element_types,
+    });
+    let mut config = serde_json::Map::new();
+    for group in [drawing, running, extras] {
+        if let serde_json::Value::Object(group) = group {
+            config.extend(group);
         }
     }
+    writeln!(out, "{}", serde_json::to_string_pretty(&config)?)?;
+    Ok(())
+}
 
-    let missing = missing_layers.lock().unwrap();
-    if !missing.is_empty() {
-        println!("{}", "Skipped layers:".red());
-        for s in missing.iter() {
-            println!(" - {}", s);
+/// The task of an entry of elements_layering.json. Element types and names may both
+/// contain `/`, so the split between them is not taken into account.
+fn find_task<'a>(
+    tasks: &'a [Task],
+    element_type: &str,
+    filename: &str,
+) -> anyhow::Result<&'a Task> {
+    let wanted = format!("{}/{}", element_type, filename);
+    tasks
+        .iter()
+        .find(|t| format!("{}/{}", t.element_type, t.filename) == wanted)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No entry '{}/{}' in elements_layering.json",
+                element_type,
+                filename
+            )
+        })
+}
+
+/// Counts of a finished run, printed by --summary-json-stdout
+#[derive(serde::Serialize)]
+struct Summary {
+    processed: usize,
+    skipped: usize,
+    missing_layers: usize,
+    empty: usize,
+    failed: usize,
+    failed_downloads: usize,
+    oversized: usize,
+    elapsed_ms: u64,
+}
+
+impl Summary {
+    /// The summary as JSON on a single line, for scripts reading the last line
+    fn json_line(&self) -> String {
+        serde_json::to_string(self).expect("counts always serialize")
+    }
+}
+
+/// Render a single task and print the color of one of its pixels
+fn sample_pixel(
+    tasks: &[Task],
+    options: &RenderOptions,
+    sample: &cli::PixelSample,
+) -> anyhow::Result<()> {
+    let [r, g, b, a] = composited_pixel(tasks, options, sample)?;
+    println!(
+        "{}/{} ({}, {}): rgba({}, {}, {}, {}) #{:02x}{:02x}{:02x}{:02x}",
+        sample.element_type, sample.filename, sample.x, sample.y, r, g, b, a, r, g, b, a
+    );
+    Ok(())
+}
+
+/// Composite the task of a pixel sample and read the sampled pixel
+fn composited_pixel(
+    tasks: &[Task],
+    options: &RenderOptions,
+    sample: &cli::PixelSample,
+) -> anyhow::Result<[u8; 4]> {
+    let task = find_task(tasks, &sample.element_type, &sample.filename)?;
+
+    let composite = render::compose(task, options).map_err(anyhow::Error::msg)?;
+    let image = composite.image.to_rgba8();
+    anyhow::ensure!(
+        sample.x < image.width() && sample.y < image.height(),
+        "Pixel ({}, {}) is outside of the {}x{} image",
+        sample.x,
+        sample.y,
+        image.width(),
+        image.height()
+    );
+
+    Ok(image.get_pixel(sample.x, sample.y).0)
+}
+
+/// Every source and layer file the tasks reference, resolved like when rendering,
+/// with whether it's a "source" or a "layer"
+fn referenced_assets(tasks: &[Task], options: &RenderOptions) -> BTreeMap<PathBuf, &'static str> {
+    let mut assets = BTreeMap::new();
+
+    for task in tasks {
+        let source = render::source_path(task, &options.source_folder);
+        assets.insert(source, "source");
+
+        if task.layer_folders.is_empty() {
+            continue;
+        }
+        for layer in task.layers.iter().chain(&task.above_layers) {
+            let layer = helper::resolve_fallback(layer, &task.layer_folders);
+            // Every frame of a sequence is needed
+            let descriptors = match LayerSequence::parse(layer) {
+                Some(sequence) => {
+                    let frames = sequence.frames(&task.layer_folders);
+                    if frames.is_empty() {
+                        vec![sequence.frame(0)]
+                    } else {
+                        frames
+                    }
+                }
+                None => helper::layer_choices(layer)
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            };
+            for descriptor in descriptors {
+                let name = helper::layer_base_name(&descriptor);
+                if name.is_empty() || name == "none" || helper::parse_generated(name).is_some() {
+                    continue;
+                }
+                let layer_path = helper::find_layer_path(&task.layer_folders, name)
+                    .unwrap_or_else(|| helper::force_png_path(&task.layer_folders[0], name));
+                assets.entry(layer_path).or_insert("layer");
+                if let Some(mask) = helper::split_transforms(&descriptor).1.mask {
+                    let mask_path = helper::find_layer_path(&task.layer_folders, mask)
+                        .unwrap_or_else(|| helper::force_png_path(&task.layer_folders[0], mask));
+                    assets.entry(mask_path).or_insert("mask");
+                }
+            }
         }
     }
+    assets
+}
 
-    let elapsed = start_time.elapsed();
+/// Write every source and layer file the tasks reference as JSON,
+/// resolved like when rendering, along with whether it exists
+fn list_assets(tasks: &[Task], options: &RenderOptions, path: &Path) -> anyhow::Result<()> {
+    let assets = referenced_assets(tasks, options);
+    let list: Vec<serde_json::Value> = assets
+        .iter()
+        .map(|(asset, kind)| {
+            // URLs are not checked
+            let exists = asset
+                .to_str()
+                .is_none_or(|s| !helper::is_url(s))
+                .then(|| asset.exists());
+            serde_json::json!({
+                "kind": kind,
+                "path": asset.display().to_string(),
+                "exists": exists,
+            })
+        })
+        .collect();
+
+    let json = serde_json::to_string_pretty(&list)?;
+    std::fs::write(path, json + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    let missing = list.iter().filter(|a| a["exists"] == false).count();
     println!(
         "{}",
-        format!("Total processing time: {:.2?}", elapsed).cyan()
+        format!(
+            "Listed {} asset(s), {} missing, in {}",
+            list.len(),
+            missing,
+            path.display()
+        )
+        .green()
     );
+    Ok(())
+}
+
+/// Write the difference of two images and report how many pixels differ
+fn diff_image(a: &Path, b: &Path, output: &Path, max_dimension: u32) -> anyhow::Result<()> {
+    let open = |path: &Path| {
+        helper::open_image(path, max_dimension)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    };
+    let (diff, differing) =
+        helper::difference_image(&open(a)?, &open(b)?).map_err(anyhow::Error::msg)?;
+    diff.save(output)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
 
+    let message = format!(
+        "{} of {} pixel(s) differ, written to {}",
+        differing,
+        diff.width() * diff.height(),
+        output.display()
+    );
+    if differing == 0 {
+        println!("{}", message.green());
+    } else {
+        println!("{}", message.yellow());
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    /// A pack in a temporary folder: every entry of `layering` has a 4x4 gray
+    /// source image and every element type its layers in `layers/`, which holds
+    /// an opaque white `bg`
+    struct Pack {
+        dir: tempfile::TempDir,
+    }
+
+    impl Pack {
+        fn new(layering: serde_json::Value) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let pack = Pack { dir };
+            pack.save("layers/bg.png", RgbaImage::from_pixel(4, 4, Rgba([255; 4])));
+
+            let mut layers_location = serde_json::Map::new();
+            for (element_type, entries) in layering.as_object().unwrap() {
+                layers_location.insert(element_type.clone(), pack.path("layers").to_str().into());
+                for entry in entries.as_object().unwrap().keys() {
+                    let (filename, _) = helper::split_tint(entry);
+                    let source = RgbaImage::from_pixel(4, 4, Rgba([128, 128, 128, 255]));
+                    pack.save(&format!("source/{element_type}/{filename}.png"), source);
+                }
+            }
+            let settings = serde_json::json!({ "layers_location": layers_location });
+            std::fs::write(pack.path("settings.json"), settings.to_string()).unwrap();
+            std::fs::write(pack.path("layering.json"), layering.to_string()).unwrap();
+            pack
+        }
+
+        fn path(&self, relative: &str) -> PathBuf {
+            self.dir.path().join(relative)
+        }
+
+        fn save(&self, relative: &str, image: RgbaImage) {
+            let path = self.path(relative);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image.save(path).unwrap();
+        }
+
+        /// Change the settings.json of the pack
+        fn edit_settings(&self, edit: impl FnOnce(&mut serde_json::Value)) {
+            let path = self.path("settings.json");
+            let mut settings =
+                serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            edit(&mut settings);
+            std::fs::write(path, settings.to_string()).unwrap();
+        }
+
+        fn job(&self) -> BatchJob {
+            BatchJob {
+                settings: self.path("settings.json"),
+                layering: self.path("layering.json"),
+                source: Some(self.path("source")),
+                output: Some(self.path("output")),
+            }
+        }
+
+        /// Run the pack with these command line arguments, returning what it printed
+        fn run(&self, args: &[&str]) -> anyhow::Result<String> {
+            let cli = cli::Cli::try_parse_from(
+                std::iter::once("Pack_Creator").chain(args.iter().copied()),
+            )?;
+            let mut out = Vec::new();
+            run_pack(&cli, &self.job(), &mut out)?;
+            Ok(String::from_utf8(out)?)
+        }
+
+        /// The configuration printed by `--print-config` with these arguments
+        fn print_config(&self, args: &[&str]) -> serde_json::Value {
+            let printed = self.run(&[args, &["--print-config"]].concat()).unwrap();
+            serde_json::from_str(&printed).unwrap()
+        }
+
+        /// Files of the output folder, relative to it and sorted
+        fn outputs(&self) -> Vec<String> {
+            let output = self.path("output");
+            let mut files: Vec<String> = clean::files_under(&output)
+                .unwrap()
+                .into_iter()
+                .map(|file| {
+                    let relative = file.strip_prefix(&output).unwrap();
+                    relative.to_string_lossy().replace('\\', "/")
+                })
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    #[test]
+    fn ignore_file_drops_tasks() {
+        let pack = Pack::new(serde_json::json!({
+            "Perks": { "a": ["bg"], "b": ["bg"] },
+            "Items": { "c": ["bg"] },
+        }));
+        std::fs::write(
+            pack.path("source/.deadbydaylightignore"),
+            "Perks/b\nItems/*\n",
+        )
+        .unwrap();
+
+        pack.run(&[]).unwrap();
+        assert_eq!(pack.outputs(), ["Perks/a.png"]);
+    }
+
+    #[test]
+    fn sampled_pixel_of_a_composite() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "a": ["bg"] } }));
+        let mut source = RgbaImage::from_pixel(4, 4, Rgba([128, 128, 128, 255]));
+        source.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        pack.save("source/Perks/a.png", source);
+
+        let tasks = [Task {
+            element_type: "Perks".to_string(),
+            filename: "a".to_string(),
+            layers: vec!["bg#00ff00".to_string()],
+            layer_folders: vec![pack.path("layers")],
+            ..Default::default()
+        }];
+        let options = RenderOptions {
+            source_folder: pack.path("source"),
+            ..Default::default()
+        };
+        let sample = |filename: &str, x, y| {
+            let sample = cli::PixelSample {
+                element_type: "Perks".to_string(),
+                filename: filename.to_string(),
+                x,
+                y,
+            };
+            composited_pixel(&tasks, &options, &sample)
+        };
+        assert_eq!(sample("a", 0, 0).unwrap(), [0, 255, 0, 255]);
+        assert_eq!(sample("a", 1, 1).unwrap(), [128, 128, 128, 255]);
+        assert!(sample("a", 4, 0).is_err());
+        assert!(sample("b", 0, 0).is_err());
+    }
+
+    #[test]
+    fn summary_is_one_json_line() {
+        let summary = Summary {
+            processed: 3,
+            skipped: 1,
+            missing_layers: 2,
+            empty: 0,
+            failed: 0,
+            failed_downloads: 0,
+            oversized: 1,
+            elapsed_ms: 12,
+        };
+        let line = summary.json_line();
+        assert!(!line.contains('\n'));
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!({
+                "processed": 3,
+                "skipped": 1,
+                "missing_layers": 2,
+                "empty": 0,
+                "failed": 0,
+                "failed_downloads": 0,
+                "oversized": 1,
+                "elapsed_ms": 12,
+            })
+        );
+    }
+
+    #[test]
+    fn summary_is_the_last_line_printed() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "a": ["bg"], "b": ["bg"] } }));
+        let atlas = pack.path("atlas.png");
+        let tar_gz = pack.path("pack.tar.gz");
+        let printed = pack
+            .run(&[
+                "--summary-json-stdout",
+                "--atlas",
+                atlas.to_str().unwrap(),
+                "--checksums",
+                "sha256",
+                "--tar-gz",
+                tar_gz.to_str().unwrap(),
+            ])
+            .unwrap();
+        assert!(atlas.exists() && tar_gz.exists());
+
+        let last = printed.lines().last().unwrap();
+        let summary: serde_json::Value = serde_json::from_str(last).unwrap();
+        assert_eq!(summary["processed"], 2, "{}", printed);
+    }
+
+    #[test]
+    fn asset_list_matches_the_references() {
+        let pack = Pack::new(serde_json::json!({
+            "Perks": { "a": ["bg", "frame:mask=cut"], "b": ["gone|bg", "none"] },
+        }));
+        let list_path = pack.path("assets.json");
+        pack.run(&["--list-assets", list_path.to_str().unwrap()])
+            .unwrap();
+
+        let list: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&list_path).unwrap()).unwrap();
+        let asset = |kind, path: &str, exists| {
+            serde_json::json!({
+                "kind": kind,
+                "path": pack.path(path).display().to_string(),
+                "exists": exists,
+            })
+        };
+        assert_eq!(
+            list,
+            serde_json::json!([
+                asset("layer", "layers/bg.png", true),
+                asset("mask", "layers/cut.png", false),
+                asset("layer", "layers/frame.png", false),
+                asset("source", "source/Perks/a.png", true),
+                asset("source", "source/Perks/b.png", true),
+            ])
+        );
+        assert!(!pack.path("output/Perks").exists());
+    }
+
+    #[test]
+    fn clean_keeps_the_outputs_of_the_config() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "a": ["bg"] } }));
+        let args = ["--format", "jpeg", "--scales", "1,2"];
+        pack.run(&args).unwrap();
+        pack.save("output/Perks/old.png", RgbaImage::new(1, 1));
+        pack.save("output/Gone/b.png", RgbaImage::new(1, 1));
+
+        pack.run(&[&args[..], &["clean"]].concat()).unwrap();
+        assert_eq!(pack.outputs(), ["Perks/a.jpg", "Perks/a@2x.jpg"]);
+        assert!(!pack.path("output/Gone").exists());
+    }
+
+    #[test]
+    fn printed_config_prefers_flags_to_settings_defaults() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "a": ["bg"] } }));
+        pack.edit_settings(|settings| {
+            settings["defaults"] = serde_json::json!({ "format": "jpeg", "filter": "nearest" });
+        });
+
+        let from_settings = pack.print_config(&[]);
+        assert_eq!(from_settings["format"], "jpeg");
+        assert_eq!(from_settings["filter"], "nearest");
+
+        let overridden = pack.print_config(&["--format", "png"]);
+        assert_eq!(overridden["format"], "png");
+        assert_eq!(overridden["filter"], "nearest");
+        assert!(pack.outputs().is_empty());
+    }
+
+    #[test]
+    fn batch_renders_every_pack() {
+        let first = Pack::new(serde_json::json!({ "Perks": { "a": ["bg"] } }));
+        let second = Pack::new(serde_json::json!({ "Items": { "b": ["bg"] } }));
+        let job = |pack: &Pack| {
+            serde_json::json!({
+                "settings": pack.path("settings.json"),
+                "layering": pack.path("layering.json"),
+                "source": pack.path("source"),
+                "output": pack.path("output"),
+            })
+        };
+        let batch = first.path("batch.json");
+        std::fs::write(
+            &batch,
+            serde_json::json!([job(&first), job(&second)]).to_string(),
+        )
+        .unwrap();
+
+        let cli = cli::Cli::try_parse_from(["Pack_Creator"]).unwrap();
+        run_batch(&cli, &batch).unwrap();
+        assert_eq!(first.outputs(), ["Perks/a.png"]);
+        assert_eq!(second.outputs(), ["Items/b.png"]);
+    }
+
+    #[test]
+    fn element_types_given_limit_the_output() {
+        let pack = Pack::new(serde_json::json!({
+            "Perks": { "a": ["bg"] },
+            "Items": { "b": ["bg"] },
+        }));
+        assert!(pack.run(&["--strict", "perks", "addons"]).is_err());
+        assert!(pack.outputs().is_empty());
+
+        pack.run(&["perks", "addons"]).unwrap();
+        assert_eq!(pack.outputs(), ["Perks/a.png"]);
+    }
+
+    #[test]
+    fn presets_render_like_their_inline_form() {
+        let pack = Pack::new(serde_json::json!({
+            "Perks": { "inline": ["bg#ff8000:scale(0.5)"], "preset": ["bg=preset:orange"] }
+        }));
+        let presets = pack.path("presets.json");
+        std::fs::write(&presets, r##"{ "orange": "#ff8000:scale(0.5)" }"##).unwrap();
+
+        pack.run(&["--presets", presets.to_str().unwrap()]).unwrap();
+        let output = |name: &str| {
+            image::open(pack.path(&format!("output/Perks/{name}.png")))
+                .unwrap()
+                .to_rgba8()
+        };
+        assert_eq!(output("preset"), output("inline"));
+
+        std::fs::write(&presets, "{}").unwrap();
+        let error = pack
+            .run(&["--presets", presets.to_str().unwrap()])
+            .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("Unknown preset 'orange'"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn element_types_are_listed_with_their_counts() {
+        let pack = Pack::new(serde_json::json!({
+            "Perks": { "a": ["bg"], "b": ["bg"] },
+            "Items": { "c": ["bg"] },
+        }));
+        let layers = pack.path("layers");
+        let listing = pack.run(&["--list-element-types"]).unwrap();
+        assert_eq!(
+            listing.lines().collect::<Vec<_>>(),
+            [
+                "Items: 1 entry".to_string(),
+                format!(" - {} (found)", layers.display()),
+                "Perks: 2 entries".to_string(),
+                format!(" - {} (found)", layers.display()),
+            ]
+        );
+        assert!(!pack.path("output").exists());
+    }
+
+    #[test]
+    fn output_name_renames_the_folder() {
+        let pack = Pack::new(serde_json::json!({
+            "Perks": { "a": ["bg"] },
+            "Items": { "b": ["bg"] },
+        }));
+        let layers = pack.path("layers");
+        pack.edit_settings(|settings| {
+            settings["folders"] = serde_json::json!({
+                "Perks": { "layers": layers, "output_name": "Skills" },
+            });
+        });
+
+        pack.run(&[]).unwrap();
+        assert_eq!(pack.outputs(), ["Items/b.png", "Skills/a.png"]);
+    }
+
+    #[test]
+    fn resume_skips_tasks_with_current_outputs() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "a": ["bg"], "b": ["bg"] } }));
+        let manifest = pack.path("manifest.json");
+        let args = ["--resume", manifest.to_str().unwrap()];
+        pack.run(&args).unwrap();
+
+        // Outputs written again get a new modification time
+        let long_ago = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        let modified = |name: &str| {
+            let path = pack.path(&format!("output/Perks/{name}.png"));
+            std::fs::metadata(path).unwrap().modified().unwrap()
+        };
+        for name in ["a", "b"] {
+            let output = std::fs::File::options()
+                .write(true)
+                .open(pack.path(&format!("output/Perks/{name}.png")))
+                .unwrap();
+            output.set_modified(long_ago).unwrap();
+        }
+        pack.save(
+            "source/Perks/b.png",
+            RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255])),
+        );
+
+        pack.run(&args).unwrap();
+        assert_eq!(modified("a"), long_ago);
+        assert_ne!(modified("b"), long_ago);
+    }
+
+    #[test]
+    fn only_tasks_changed_since_are_rendered() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "a": ["bg"], "b": ["bg"] } }));
+        let long_ago = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        for input in ["layers/bg.png", "source/Perks/a.png", "source/Perks/b.png"] {
+            let file = std::fs::File::options()
+                .write(true)
+                .open(pack.path(input))
+                .unwrap();
+            file.set_modified(long_ago).unwrap();
+        }
+        pack.save(
+            "source/Perks/b.png",
+            RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255])),
+        );
+
+        pack.run(&["--only-changed-since", "2000-01-01T00:00:00Z"])
+            .unwrap();
+        assert!(!pack.path("output/Perks/a.png").exists());
+        assert!(pack.path("output/Perks/b.png").exists());
+    }
+
+    #[test]
+    fn outputs_over_the_byte_budget_are_flagged() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "big": ["bg"], "small": ["bg"] } }));
+        pack.save(
+            "source/Perks/big.png",
+            RgbaImage::from_fn(64, 64, |x, y| {
+                let noise = (x * 7919 + y * 104729).wrapping_mul(2654435761) >> 24;
+                Rgba([noise as u8, (noise >> 3) as u8, (noise << 2) as u8, 255])
+            }),
+        );
+
+        let args = ["--max-output-bytes", "1000"];
+        let report = pack.run(&args).unwrap();
+        assert!(
+            report.contains("Outputs over --max-output-bytes"),
+            "{}",
+            report
+        );
+        assert!(report.contains("big.png"), "{}", report);
+        assert!(!report.contains("small.png"), "{}", report);
+        assert!(pack.run(&[&args[..], &["--strict"]].concat()).is_err());
+    }
+
+    #[test]
+    fn suppressed_warnings_are_only_counted() {
+        let pack = Pack::new(serde_json::json!({ "Perks": { "a": ["bg", "gone"] } }));
+        let shown = pack.run(&[]).unwrap();
+        assert!(shown.contains("gone.png"), "{}", shown);
+
+        let suppressed = pack
+            .run(&["--suppress", "missing-layer", "--summary-json-stdout"])
+            .unwrap();
+        assert!(!suppressed.contains("gone.png"), "{}", suppressed);
+        assert!(
+            suppressed.contains("Skipped layers: 1 (suppressed)"),
+            "{}",
+            suppressed
+        );
+        let summary = suppressed
+            .lines()
+            .find(|line| line.starts_with('{'))
+            .unwrap();
+        let summary: serde_json::Value = serde_json::from_str(summary).unwrap();
+        assert_eq!(summary["missing_layers"], 1);
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+type Download = Result<Arc<Vec<u8>>, String>;
+
+/// Downloads made during this run, failures included so they aren't retried
+static CACHE: OnceLock<Mutex<HashMap<String, Download>>> = OnceLock::new();
+
+/// Extra attempts made for a download that fails
+static RETRIES: AtomicU32 = AtomicU32::new(0);
+
+/// Wait before the first retry, doubled before each of the next ones
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Try downloads that fail `retries` more times before giving up on them
+pub fn set_retries(retries: u32) {
+    RETRIES.store(retries, Ordering::Relaxed);
+}
+
+/// Download the bytes behind a URL, once per run
+pub fn fetch(url: &str) -> Download {
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(download) = cache.lock().unwrap().get(url) {
+        return download.clone();
+    }
+
+    let retries = RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    let download = loop {
+        match download(url) {
+            // Client errors won't go away by asking again
+            Err((e, retry)) if retry && attempt < retries => {
+                log::warn!("Download of '{}' failed, retrying: {}", url, e);
+                std::thread::sleep(RETRY_DELAY * 2_u32.saturating_pow(attempt));
+                attempt += 1;
+            }
+            Err((e, _)) if attempt > 0 => {
+                break Err(format!("{} (after {} attempts)", e, attempt + 1));
+            }
+            result => break result.map_err(|(e, _)| e),
+        }
+    };
+
+    cache
+        .lock()
+        .unwrap()
+        .insert(url.to_string(), download.clone());
+    download
+}
+
+/// A single attempt at downloading a URL, errors telling whether to retry it
+fn download(url: &str) -> Result<Arc<Vec<u8>>, (String, bool)> {
+    let response = reqwest::blocking::get(url).map_err(|e| (e.to_string(), true))?;
+    let response = match response.error_for_status_ref() {
+        Ok(_) => response,
+        Err(e) => return Err((e.to_string(), !response.status().is_client_error())),
+    };
+    response
+        .bytes()
+        .map(|bytes| Arc::new(bytes.to_vec()))
+        .map_err(|e| (e.to_string(), true))
+}
+
+/// Every URL that couldn't be downloaded during this run, with why, sorted
+pub fn failures() -> Vec<(String, String)> {
+    let Some(cache) = CACHE.get() else {
+        return Vec::new();
+    };
+    let mut failures: Vec<_> = cache
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(url, download)| Some((url.clone(), download.clone().err()?)))
+        .collect();
+    failures.sort();
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Answer the next requests to the returned URL with these statuses and bodies, in order
+    fn serve(responses: Vec<(u16, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn source_image_from_url() {
+        let image = RgbaImage::from_pixel(3, 2, Rgba([1, 2, 3, 255]));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(image.clone())
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        let url = serve(vec![(200, png)]) + "icon.png";
+
+        let opened = crate::helper::open_image(std::path::Path::new(&url), 16).unwrap();
+        assert_eq!(opened.to_rgba8(), image);
+        // Downloads are made once per run
+        assert!(fetch(&url).is_ok());
+
+        let missing = serve(vec![(404, Vec::new())]) + "missing.png";
+        assert!(crate::helper::open_image(std::path::Path::new(&missing), 16).is_err());
+        assert!(failures().iter().any(|(failed, _)| *failed == missing));
+    }
+
+    #[test]
+    fn exhausted_retries_give_the_placeholder() {
+        let url = serve(vec![(500, Vec::new()); 3]);
+        let task = crate::render::Task {
+            element_type: "Perks".to_string(),
+            filename: "flaky".to_string(),
+            ..Default::default()
+        };
+        let options = crate::render::RenderOptions {
+            source_folder: url.trim_end_matches('/').into(),
+            placeholder: Some(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                2,
+                2,
+                Rgba([255, 0, 255, 255]),
+            ))),
+            ..Default::default()
+        };
+
+        set_retries(2);
+        let composite = crate::render::compose(&task, &options);
+        set_retries(0);
+        let composite = composite.unwrap();
+        assert!(composite.placeholder_reason.is_some());
+        assert!(
+            composite
+                .image
+                .to_rgba8()
+                .pixels()
+                .all(|pixel| pixel.0 == [255, 0, 255, 255])
+        );
+        let source = url + "Perks/flaky.png";
+        let (_, reason) = failures()
+            .into_iter()
+            .find(|(failed, _)| *failed == source)
+            .unwrap();
+        assert!(reason.contains("after 3 attempts"), "{}", reason);
+    }
+}
@@ -0,0 +1,2328 @@
+use crate::animation;
+use crate::config::{Offset, OutputFormat, Rect, ResizeFilter};
+use crate::helper::{self, ColorRemap, LayerModifiers, LayerTrace, OnMissingLayer, StackOptions};
+use crate::label::{self, LabelStyle};
+use crate::timings::{self, Stage};
+use crate::watermark::Watermark;
+use image::codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder};
+use image::{DynamicImage, ImageEncoder, Rgba, RgbaImage, imageops, imageops::overlay};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A single icon to render
+pub struct Task {
+    pub element_type: String,
+    pub filename: String,
+    pub item_tint: Option<String>,
+    /// Layers drawn under the item
+    pub layers: Vec<String>,
+    /// Layers drawn over the item
+    pub above_layers: Vec<String>,
+    pub meta: BTreeMap<String, serde_json::Value>,
+    pub layer_folders: Vec<PathBuf>,
+    /// Tint and opacity of layers, from the element settings
+    pub layer_defaults: LayerModifiers,
+    /// Multiplies the alpha of the output once everything is composited
+    pub opacity: f32,
+    /// Output folder name replacing the last component of the element type
+    pub output_name: Option<String>,
+    /// Position of the item on the canvas, the parts falling outside of it are cut
+    pub item_offset: Offset,
+    /// Area the item is fitted and centered in before the offset moves it
+    pub item_inner: Option<Rect>,
+}
+
+impl Default for Task {
+    /// A task without layers, its item drawn as it is
+    fn default() -> Self {
+        Task {
+            element_type: String::new(),
+            filename: String::new(),
+            item_tint: None,
+            layers: Vec::new(),
+            above_layers: Vec::new(),
+            meta: BTreeMap::new(),
+            layer_folders: Vec::new(),
+            layer_defaults: LayerModifiers::default(),
+            opacity: 1.0,
+            output_name: None,
+            item_offset: Offset::default(),
+            item_inner: None,
+        }
+    }
+}
+
+/// Channels of the written outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputColor {
+    #[default]
+    Rgba,
+    /// Flattened over the `flatten` backdrop
+    Rgb,
+}
+
+/// Bits per channel of the canvas, the layers drawn on it and the written outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitDepth {
+    #[default]
+    Eight,
+    /// Only PNG outputs can hold it
+    Sixteen,
+}
+
+impl FromStr for BitDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            _ => Err(format!("Unknown bit depth '{}', expected 8 or 16", s)),
+        }
+    }
+}
+
+/// How 8-bit outputs are reduced from the 16-bit canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// The canvas stays at 8 bits, each step rounding its own result
+    #[default]
+    None,
+    /// The canvas is composited at 16 bits and error-diffused down to 8 at the end
+    FloydSteinberg,
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Dither::None),
+            "floyd-steinberg" => Ok(Dither::FloydSteinberg),
+            _ => Err(format!(
+                "Unknown dithering '{}', expected none or floyd-steinberg",
+                s
+            )),
+        }
+    }
+}
+
+/// Backdrop transparent pixels are flattened over in RGB and JPEG outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flatten {
+    Color((u8, u8, u8)),
+    /// Light gray and white checkerboard, like image editors show transparency
+    Checker,
+}
+
+impl FromStr for Flatten {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "white" => Ok(Flatten::Color((255, 255, 255))),
+            "black" => Ok(Flatten::Color((0, 0, 0))),
+            "checker" => Ok(Flatten::Checker),
+            lower => match lower.strip_prefix("color:") {
+                Some(color) => Ok(Flatten::Color(helper::parse_color(color)?)),
+                None => Err(format!(
+                    "Unknown flatten backdrop '{}', expected white, black, color:HEX or checker",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl Flatten {
+    /// Drop the alpha channel of an image, blending it over the backdrop
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        match *self {
+            Flatten::Color(color) => helper::flatten_over(img, color),
+            Flatten::Checker => {
+                let mut backdrop = helper::checkerboard(
+                    img.width(),
+                    img.height(),
+                    Rgba([204, 204, 204, 255]),
+                    Rgba([255, 255, 255, 255]),
+                );
+                overlay(&mut backdrop, img, 0, 0);
+                DynamicImage::ImageRgb8(backdrop.to_rgb8())
+            }
+        }
+    }
+}
+
+/// Flip applied to every finished composite
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mirror {
+    #[default]
+    None,
+    /// Left and right swapped
+    Horizontal,
+    /// Top and bottom swapped
+    Vertical,
+}
+
+impl Mirror {
+    pub fn apply(&self, img: &mut DynamicImage) {
+        match self {
+            Mirror::None => {}
+            Mirror::Horizontal => *img = img.fliph(),
+            Mirror::Vertical => *img = img.flipv(),
+        }
+    }
+}
+
+impl FromStr for Mirror {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Mirror::None),
+            "horizontal" => Ok(Mirror::Horizontal),
+            "vertical" => Ok(Mirror::Vertical),
+            _ => Err(format!(
+                "Unknown mirror '{}', expected none, horizontal or vertical",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for OutputColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rgba" => Ok(OutputColor::Rgba),
+            "rgb" => Ok(OutputColor::Rgb),
+            _ => Err(format!(
+                "Unknown output color '{}', expected rgba or rgb",
+                s
+            )),
+        }
+    }
+}
+
+/// Options shared by every task of a run
+pub struct RenderOptions {
+    pub source_folder: PathBuf,
+    pub output_folder: PathBuf,
+    pub jobs_per_image: usize,
+    pub color_matrix: Option<[f32; 9]>,
+    /// Finds the rarity in the names of entries, coloring their `rarity` tinted layers
+    pub rarity_pattern: regex::Regex,
+    /// Colors replaced in every output once everything else is drawn
+    pub remap: Vec<ColorRemap>,
+    /// Largest difference of any channel for a color to be replaced by `remap`
+    pub remap_tolerance: u8,
+    /// Levels every RGB channel is quantized to, and whether alpha is too
+    pub posterize: Option<(u16, bool)>,
+    pub trace_layers: bool,
+    /// Solid color the canvas starts with, transparent when `None`
+    pub canvas_fill: Option<(u8, u8, u8)>,
+    /// Drawn on every canvas before anything else, over the canvas fill
+    pub global_background: Option<DynamicImage>,
+    /// Frame delay of animated outputs, layer sequences only use their first frame when `None`
+    pub frame_delay_ms: Option<u16>,
+    /// Stands in for source images that can't be opened
+    pub placeholder: Option<DynamicImage>,
+    /// Outputs are cropped to their visible pixels then padded by this many pixels
+    pub normalize_margin: Option<u32>,
+    /// Outputs are resized to fit a square of this size
+    pub output_size: Option<u32>,
+    pub format: OutputFormat,
+    pub layout: OutputLayout,
+    /// Trailing numbers of output names are zero-padded to this many digits
+    pub pad_numbers: Option<usize>,
+    pub filter: ResizeFilter,
+    /// Source images and layers wider or taller than this are not decoded
+    pub max_dimension: u32,
+    /// What happens to layers that can't be found or opened
+    pub on_missing_layer: OnMissingLayer,
+    /// Render tasks this many at a time to bound memory use, all at once when `None` or 0
+    pub chunk_size: Option<usize>,
+    /// Don't write outputs without a single visible pixel
+    pub skip_empty: bool,
+    pub output_color: OutputColor,
+    pub bit_depth: BitDepth,
+    pub dither: Dither,
+    /// Backdrop of RGB and JPEG outputs
+    pub flatten: Flatten,
+    /// Flips every composite, before the watermark and debug labels are drawn
+    pub mirror: Mirror,
+    /// Drawn over every output before the debug labels
+    pub watermark: Option<Watermark>,
+    /// Draw the name of each output onto it, for debugging
+    pub debug_labels: Option<LabelStyle>,
+    /// Darkening of the corners of outputs, from 0 to 1, applied after resizing
+    pub vignette: Option<f32>,
+    /// Sigma of the unsharp mask applied after resizing, no sharpening when `None`
+    pub sharpen: Option<f32>,
+    /// Smallest difference from the blurred image that gets sharpened
+    pub sharpen_threshold: i32,
+    /// Seeds every random number generator, making randomized choices like the
+    /// order of same-sized icons in the atlas reproducible
+    pub seed: u64,
+    /// Stop rendering once more than this many images were skipped
+    pub max_skips: Option<usize>,
+    /// Stop rendering once it has been running for this long
+    pub timeout: Option<Duration>,
+    /// Every task is written once per scale, `name@2x.png` for a scale of 2,
+    /// only at scale 1 when empty
+    pub scales: Vec<f32>,
+    /// Write PNGs with pinned encoder settings and fully transparent pixels cleared,
+    /// so identical visible pixels give identical files
+    pub reproducible: bool,
+    /// Every drawn layer is also written on its own under this folder, for debugging
+    pub export_layers: Option<PathBuf>,
+    /// Warn about layers fully covering more than this share of the visible pixels under them
+    pub lint_order: Option<f32>,
+    /// Reuses the layers under the item across tasks sharing them, `None` to always stack them
+    pub stack_cache: Option<StackCache>,
+}
+
+impl Default for RenderOptions {
+    /// The options of a run without any flag, reading and writing the current folder
+    fn default() -> Self {
+        RenderOptions {
+            source_folder: PathBuf::new(),
+            output_folder: PathBuf::new(),
+            jobs_per_image: 1,
+            color_matrix: None,
+            rarity_pattern: regex::Regex::new(helper::DEFAULT_RARITY_PATTERN)
+                .expect("the default rarity pattern is valid"),
+            remap: Vec::new(),
+            remap_tolerance: 0,
+            posterize: None,
+            trace_layers: false,
+            canvas_fill: None,
+            global_background: None,
+            frame_delay_ms: None,
+            placeholder: None,
+            normalize_margin: None,
+            output_size: None,
+            format: OutputFormat::default(),
+            layout: OutputLayout::default(),
+            pad_numbers: None,
+            filter: ResizeFilter::default(),
+            max_dimension: helper::DEFAULT_MAX_DIMENSION,
+            on_missing_layer: OnMissingLayer::default(),
+            chunk_size: None,
+            skip_empty: false,
+            output_color: OutputColor::default(),
+            bit_depth: BitDepth::default(),
+            dither: Dither::default(),
+            flatten: Flatten::Color((255, 255, 255)),
+            mirror: Mirror::default(),
+            watermark: None,
+            debug_labels: None,
+            vignette: None,
+            sharpen: None,
+            sharpen_threshold: 0,
+            seed: 0,
+            max_skips: None,
+            timeout: None,
+            scales: Vec::new(),
+            reproducible: false,
+            export_layers: None,
+            lint_order: None,
+            stack_cache: None,
+        }
+    }
+}
+
+/// Canvases with the layers under the item already stacked, shared by every task.
+/// Entries are never evicted, each distinct stack keeping one canvas in memory.
+#[derive(Default)]
+pub struct StackCache {
+    entries: Mutex<HashMap<String, (DynamicImage, Vec<LayerTrace>)>>,
+    hits: AtomicUsize,
+}
+
+impl StackCache {
+    /// Number of times a stack was reused instead of composited again
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The cached result for `key`, computing and storing it with `stack` when missing
+    fn get_or_stack(
+        &self,
+        key: String,
+        stack: impl FnOnce() -> (DynamicImage, Vec<LayerTrace>),
+    ) -> (DynamicImage, Vec<LayerTrace>) {
+        if let Some(cached) = self.entries.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return cached.clone();
+        }
+        // Stacked without holding the lock, tasks racing on the same key both compute it
+        let stacked = stack();
+        self.entries.lock().unwrap().insert(key, stacked.clone());
+        stacked
+    }
+}
+
+impl RenderOptions {
+    /// Scales every task is written at, 1 being the size given by `output_size`
+    pub fn output_scales(&self) -> Vec<f32> {
+        if self.scales.is_empty() {
+            vec![1.0]
+        } else {
+            self.scales.clone()
+        }
+    }
+
+    /// Scale outputs are composited at before being downsampled to the others
+    pub fn largest_scale(&self) -> f32 {
+        self.output_scales().into_iter().fold(0.0, f32::max)
+    }
+
+    /// Bits per channel of the canvas, 16 for 8-bit outputs reduced with dithering
+    pub fn canvas_depth(&self) -> BitDepth {
+        match self.dither {
+            Dither::FloydSteinberg => BitDepth::Sixteen,
+            Dither::None => self.bit_depth,
+        }
+    }
+
+    /// Random number generator of `key`, like `Perks/name` for a task or `atlas`,
+    /// the same for a given seed and key whatever order they are drawn in
+    pub fn rng(&self, key: &str) -> StdRng {
+        let mut hasher = helper::Fnv1a::default();
+        hasher.write(key.as_bytes());
+        StdRng::seed_from_u64(self.seed ^ hasher.finish())
+    }
+}
+
+/// Progress of a task, reported while rendering
+pub enum TaskEvent<'a> {
+    /// The task is about to be rendered
+    Started { task: &'a Task },
+    /// The output was written
+    Succeeded {
+        task: &'a Task,
+        output_path: &'a Path,
+        /// Size of the written file
+        bytes: u64,
+    },
+    /// The source image could not be opened, nothing was written
+    Skipped { task: &'a Task, reason: String },
+    /// The source image could not be opened and the placeholder was used instead
+    Placeholder { task: &'a Task, reason: String },
+    /// Some layers could not be found, grouped under the source image path
+    MissingLayer { task: &'a Task, report: String },
+    /// The output was fully transparent and not written
+    Empty { task: &'a Task },
+    /// The output could not be written, or was refused because of a missing layer
+    Failed {
+        task: &'a Task,
+        output_path: &'a Path,
+        error: String,
+    },
+}
+
+/// Receives task events, called from the rendering threads
+pub type EventCallback<'a> = &'a (dyn Fn(TaskEvent) + Sync);
+
+/// A rendered icon before it is written
+pub struct Composite {
+    pub image: DynamicImage,
+    pub source_path: PathBuf,
+    pub traces: Vec<LayerTrace>,
+    /// Why the placeholder replaced the source image, if it did
+    pub placeholder_reason: Option<String>,
+}
+
+/// Path of the source image of a task
+pub fn source_path(task: &Task, source_folder: &Path) -> PathBuf {
+    helper::force_png_path(&source_folder.join(&task.element_type), &task.filename)
+}
+
+/// How output paths are derived from the element type and entry name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputLayout {
+    /// `<output name or last folder of the element type>/<name>`
+    #[default]
+    Flat,
+    /// The path of the source image relative to the source folder
+    MirrorTree,
+}
+
+/// Path the output of a task is written to
+pub fn output_path(task: &Task, options: &RenderOptions) -> PathBuf {
+    let element_folder_name = match (options.layout, &task.output_name) {
+        (OutputLayout::MirrorTree, _) => std::ffi::OsStr::new(&task.element_type),
+        (OutputLayout::Flat, Some(name)) => std::ffi::OsStr::new(name),
+        (OutputLayout::Flat, None) => Path::new(&task.element_type)
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("Unknown")),
+    };
+    let output_name = if helper::is_url(&task.filename) {
+        helper::url_file_stem(&task.filename)
+    } else {
+        helper::strip_image_extension(&task.filename)
+    };
+    let output_name = match options.pad_numbers {
+        Some(width) => helper::pad_trailing_number(output_name, width),
+        None => output_name.to_string(),
+    };
+    options
+        .output_folder
+        .join(element_folder_name)
+        .join(format!("{output_name}.{}", options.format.extension()))
+}
+
+/// Path of the output at `scale`: `name@2x.png` next to `name.png`, which is
+/// the output at scale 1
+pub fn scaled_output_path(path: &Path, scale: f32) -> PathBuf {
+    if scale == 1.0 {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let mut name = format!("{stem}@{scale}x");
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        name = format!("{name}.{extension}");
+    }
+    path.with_file_name(name)
+}
+
+/// Stack the layers of a task under its item.
+/// Fails when the source image can't be opened.
+pub fn compose(task: &Task, options: &RenderOptions) -> Result<Composite, String> {
+    compose_frame(task, options, 0)
+}
+
+/// Same as [`compose`], layer sequences being resolved to their frame at `index`
+pub fn compose_frame(
+    task: &Task,
+    options: &RenderOptions,
+    index: usize,
+) -> Result<Composite, String> {
+    let item_img_path = source_path(task, &options.source_folder);
+    if let Some(rect) = task.item_inner
+        && (rect.w == 0 || rect.h == 0)
+    {
+        return Err(format!(
+            "the {}x{} inner rectangle is empty",
+            rect.w, rect.h
+        ));
+    }
+
+    // Fallbacks are picked before anything else is read from the descriptors
+    let layers = helper::resolve_fallbacks(&task.layers, &task.layer_folders);
+    let above_layers = helper::resolve_fallbacks(&task.above_layers, &task.layer_folders);
+
+    let mut placeholder_reason = None;
+    let mut item_img = match helper::open_image(&item_img_path, options.max_dimension) {
+        Ok(img) => img,
+        Err(e) => {
+            let reason = format!("could not open '{}': {}", item_img_path.display(), e);
+            let Some(placeholder) = &options.placeholder else {
+                return Err(reason);
+            };
+            placeholder_reason = Some(reason);
+
+            // Match the canvas the layers expect
+            let layers = [layers.as_slice(), &above_layers].concat();
+            match helper::layer_canvas_size(&task.layer_folders, &layers) {
+                Some((width, height)) => {
+                    placeholder.resize_exact(width, height, imageops::FilterType::Triangle)
+                }
+                None => placeholder.clone(),
+            }
+        }
+    };
+
+    // Dominant tints come from the item as it is in the source pack
+    let layers = helper::resolve_dominant_tints(
+        &layers,
+        &task.layer_folders,
+        &item_img,
+        options.max_dimension,
+    );
+    let above_layers = helper::resolve_dominant_tints(
+        &above_layers,
+        &task.layer_folders,
+        &item_img,
+        options.max_dimension,
+    );
+
+    let rarity = helper::rarity_color(&task.filename, &options.rarity_pattern);
+    let layers = helper::resolve_rarity_tints(&layers, rarity, &task.filename);
+    let above_layers = helper::resolve_rarity_tints(&above_layers, rarity, &task.filename);
+
+    // Conditions are about the item as it is in the source pack too
+    let variables = helper::ItemVariables::measure(&item_img);
+    let layers = helper::resolve_conditions(&layers, &variables);
+    let above_layers = helper::resolve_conditions(&above_layers, &variables);
+
+    // Keep the item at the depth of the canvas it is drawn on
+    if options.canvas_depth() == BitDepth::Sixteen {
+        item_img = DynamicImage::ImageRgba16(item_img.to_rgba16());
+    }
+
+    // Recolor the item if a HEX tint was given
+    if let Some(hex) = &task.item_tint {
+        match timings::record(Stage::Composite, || helper::tint_image(&item_img, hex)) {
+            Ok(tinted) => item_img = tinted,
+            Err(e) => log::warn!("Ignoring tint '{}' for '{}': {}", hex, task.filename, e),
+        }
+    }
+
+    let mut final_img = timings::record(Stage::Composite, || {
+        let (width, height) = (item_img.width(), item_img.height());
+        match (options.canvas_fill, options.canvas_depth()) {
+            (Some((r, g, b)), BitDepth::Eight) => {
+                DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255])))
+            }
+            (Some((r, g, b)), BitDepth::Sixteen) => {
+                let channel = |c: u8| c as u16 * 257;
+                DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(
+                    width,
+                    height,
+                    Rgba([channel(r), channel(g), channel(b), u16::MAX]),
+                ))
+            }
+            (None, BitDepth::Eight) => DynamicImage::new_rgba8(width, height),
+            (None, BitDepth::Sixteen) => DynamicImage::new_rgba16(width, height),
+        }
+    });
+    if let Some(background) = &options.global_background {
+        timings::record(Stage::Composite, || {
+            let (width, height) = (final_img.width(), final_img.height());
+            if background.width() == width && background.height() == height {
+                helper::overlay_image(&mut final_img, background, 0, 0);
+            } else {
+                let resized = background.resize_exact(width, height, options.filter.filter_type());
+                helper::overlay_image(&mut final_img, &resized, 0, 0);
+            }
+        });
+    }
+
+    let mut traces = Vec::new();
+    let stack_options = StackOptions {
+        jobs: options.jobs_per_image,
+        max_dimension: options.max_dimension,
+        on_missing: options.on_missing_layer,
+        lint_order: options.lint_order,
+        keep_images: options.export_layers.is_some(),
+    };
+    // Any layer hides a solid canvas fill or the background, which isn't a mistake
+    let below_options = StackOptions {
+        lint_order: options
+            .lint_order
+            .filter(|_| options.canvas_fill.is_none() && options.global_background.is_none()),
+        ..stack_options
+    };
+    let mut stack = |image: &mut DynamicImage,
+                     layers: &[String],
+                     stack_options: &StackOptions,
+                     cache: Option<&StackCache>| {
+        if task.layer_folders.is_empty() {
+            return;
+        }
+        let layers = animation::resolve_frame(layers, &task.layer_folders, index);
+        let stack_onto = |image: &mut DynamicImage| {
+            helper::stack_layers(
+                image,
+                &task.layer_folders,
+                &layers,
+                &task.layer_defaults,
+                stack_options,
+            )
+        };
+        match cache {
+            Some(cache) => {
+                // Everything the stacked canvas depends on, the canvas fill and background being global
+                let key = format!(
+                    "{:?}",
+                    (
+                        &task.layer_folders,
+                        &layers,
+                        &task.layer_defaults,
+                        (image.width(), image.height())
+                    )
+                );
+                let (stacked, stack_traces) = cache.get_or_stack(key, || {
+                    let mut canvas = image.clone();
+                    let stack_traces = stack_onto(&mut canvas);
+                    (canvas, stack_traces)
+                });
+                *image = stacked;
+                traces.extend(stack_traces);
+            }
+            None => traces.extend(stack_onto(image)),
+        }
+    };
+
+    stack(
+        &mut final_img,
+        &layers,
+        &below_options,
+        options.stack_cache.as_ref(),
+    );
+    timings::record(Stage::Composite, || match task.item_inner {
+        Some(rect) => {
+            let fitted = item_img.resize(rect.w, rect.h, options.filter.filter_type());
+            helper::overlay_image(
+                &mut final_img,
+                &fitted,
+                rect.x + (rect.w - fitted.width()) as i64 / 2 + task.item_offset.x,
+                rect.y + (rect.h - fitted.height()) as i64 / 2 + task.item_offset.y,
+            )
+        }
+        None => helper::overlay_image(
+            &mut final_img,
+            &item_img,
+            task.item_offset.x,
+            task.item_offset.y,
+        ),
+    });
+    stack(&mut final_img, &above_layers, &stack_options, None);
+
+    // Frames share their layers, reporting the first is enough
+    if index == 0 {
+        for trace in &traces {
+            if let Some(share) = trace.hides {
+                log::warn!(
+                    "Layer '{}' of '{}/{}' hides {:.0}% of what is drawn under it, it may be listed in the wrong order",
+                    trace.name,
+                    task.element_type,
+                    task.filename,
+                    share * 100.0
+                );
+            }
+        }
+    }
+
+    timings::record(Stage::Composite, || -> Result<(), String> {
+        if let Some(matrix) = &options.color_matrix {
+            helper::apply_color_matrix(&mut final_img, matrix);
+        }
+
+        if let Some(margin) = options.normalize_margin {
+            final_img = helper::normalize_margin(&final_img, margin)?;
+        }
+
+        let largest = options.largest_scale();
+        if let Some(size) = options.output_size {
+            let size = (size as f32 * largest).round() as u32;
+            final_img = final_img.resize(size, size, options.filter.filter_type());
+        } else if largest != 1.0 {
+            final_img = helper::resize_by(&final_img, largest, options.filter.filter_type());
+        }
+
+        if let Some(strength) = options.vignette {
+            helper::vignette(&mut final_img, strength);
+        }
+
+        if let Some(sigma) = options.sharpen {
+            helper::sharpen(&mut final_img, sigma, options.sharpen_threshold);
+        }
+
+        helper::scale_alpha(&mut final_img, task.opacity);
+
+        // The watermark and labels stay readable
+        options.mirror.apply(&mut final_img);
+
+        if let Some(watermark) = &options.watermark {
+            watermark.apply(&mut final_img);
+        }
+
+        if let Some(style) = &options.debug_labels {
+            let name = output_path(task, options);
+            let name = name
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            label::draw_label(&mut final_img, name, style);
+        }
+
+        helper::remap_colors(&mut final_img, &options.remap, options.remap_tolerance);
+
+        if options.bit_depth == BitDepth::Eight
+            && let DynamicImage::ImageRgba16(canvas) = &final_img
+        {
+            final_img = DynamicImage::ImageRgba8(helper::dither_floyd_steinberg(canvas));
+        }
+
+        if let Some((levels, alpha)) = options.posterize {
+            helper::posterize(&mut final_img, levels, alpha);
+        }
+
+        if options.output_color == OutputColor::Rgb || options.format == OutputFormat::Jpeg {
+            final_img = options.flatten.apply(&final_img);
+        }
+        Ok(())
+    })?;
+
+    Ok(Composite {
+        image: final_img,
+        source_path: item_img_path,
+        traces,
+        placeholder_reason,
+    })
+}
+
+/// Write every drawn layer of a task as `NN_name.png` in `folder/element/filename/`,
+/// numbered in stack order
+fn export_layers(folder: &Path, task: &Task, traces: &[LayerTrace]) -> Result<(), String> {
+    let task_folder = folder.join(&task.element_type).join(&task.filename);
+    std::fs::create_dir_all(&task_folder).map_err(|e| e.to_string())?;
+
+    let drawn = traces
+        .iter()
+        .filter_map(|trace| Some((&trace.name, trace.image.as_ref()?)));
+    for (index, (name, image)) in drawn.enumerate() {
+        // Layer names may be paths, URLs or generated layers
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = task_folder.join(format!("{:02}_{}.png", index + 1, name));
+        image
+            .save(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Order tasks round-robin across element types, one of each in turn, so every
+/// element type makes progress when they are rendered in parallel. Element types
+/// take turns in the order they first appear and keep the order of their tasks.
+pub fn interleave_element_types(tasks: Vec<Task>) -> Vec<Task> {
+    let mut groups: Vec<std::collections::VecDeque<Task>> = Vec::new();
+    for task in tasks {
+        match groups
+            .iter_mut()
+            .find(|group| group[0].element_type == task.element_type)
+        {
+            Some(group) => group.push_back(task),
+            None => groups.push([task].into()),
+        }
+    }
+
+    let mut interleaved = Vec::with_capacity(groups.iter().map(|group| group.len()).sum());
+    while !groups.is_empty() {
+        for group in &mut groups {
+            interleaved.extend(group.pop_front());
+        }
+        groups.retain(|group| !group.is_empty());
+    }
+    interleaved
+}
+
+/// Why [`process_tasks`] stopped before rendering every task
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stopped {
+    /// More than this many images were skipped
+    TooManySkips(usize),
+    /// Rendering ran for longer than this
+    TimedOut(Duration),
+}
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stopped::TooManySkips(max_skips) => {
+                write!(f, "Stopped after more than {} skipped image(s)", max_skips)
+            }
+            Stopped::TimedOut(timeout) => {
+                write!(f, "Stopped after the {}s timeout", timeout.as_secs_f64())
+            }
+        }
+    }
+}
+
+/// Render and write every task in parallel, reporting progress to `on_event`.
+/// Fails once more than `options.max_skips` images were skipped or once
+/// `options.timeout` elapsed, tasks being rendered finishing and the ones that
+/// didn't start yet being dropped.
+pub fn process_tasks(
+    tasks: &[Task],
+    options: &RenderOptions,
+    on_event: Option<EventCallback>,
+) -> Result<(), Stopped> {
+    let started = Instant::now();
+    let skipped = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    let timed_out = AtomicBool::new(false);
+    let emit = |event: TaskEvent| {
+        if matches!(event, TaskEvent::Skipped { .. })
+            && let Some(max_skips) = options.max_skips
+            && skipped.fetch_add(1, Ordering::Relaxed) + 1 > max_skips
+        {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+        if let Some(on_event) = on_event {
+            on_event(event);
+        }
+    };
+
+    let render_task = |task: &Task| {
+        if let Some(timeout) = options.timeout
+            && started.elapsed() > timeout
+        {
+            timed_out.store(true, Ordering::Relaxed);
+            cancelled.store(true, Ordering::Relaxed);
+        }
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+        crate::profile_span!("task", element = %task.element_type, name = %task.filename);
+        emit(TaskEvent::Started { task });
+
+        let composite = match compose(task, options) {
+            Ok(composite) => composite,
+            Err(reason) => {
+                emit(TaskEvent::Skipped { task, reason });
+                return;
+            }
+        };
+
+        if let Some(reason) = composite.placeholder_reason.clone() {
+            emit(TaskEvent::Placeholder { task, reason });
+        }
+
+        if let Some(report) =
+            helper::group_missing_layers(&composite.source_path, &composite.traces)
+        {
+            emit(TaskEvent::MissingLayer { task, report });
+        }
+
+        if options.skip_empty && is_fully_transparent(&composite.image) {
+            emit(TaskEvent::Empty { task });
+            return;
+        }
+
+        let output_path = output_path(task, options);
+
+        if options.on_missing_layer == OnMissingLayer::Error
+            && let Some(missing) = composite.traces.iter().find(|trace| !trace.found)
+        {
+            emit(TaskEvent::Failed {
+                task,
+                output_path: &output_path,
+                error: format!("missing layer '{}'", missing.path.display()),
+            });
+            return;
+        }
+
+        if let Some(parent) = output_path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        // Render the remaining frames of animated outputs, only PNG can hold them
+        let frame_count = match options.frame_delay_ms {
+            Some(_) if options.format == OutputFormat::Png => {
+                animation::frame_count(&task.layers, &task.layer_folders).max(
+                    animation::frame_count(&task.above_layers, &task.layer_folders),
+                )
+            }
+            _ => 1,
+        };
+        let frame_delay_ms = options.frame_delay_ms.filter(|_| frame_count > 1);
+        let mut frames = vec![composite.image.clone()];
+        for index in 1..frame_count {
+            match compose_frame(task, options, index) {
+                Ok(frame) => frames.push(frame.image),
+                Err(reason) => {
+                    emit(TaskEvent::Skipped { task, reason });
+                    return;
+                }
+            }
+        }
+
+        // Frames are composited at the largest scale, the others are downsampled from it
+        let largest = options.largest_scale();
+        for scale in options.output_scales() {
+            let output_path = scaled_output_path(&output_path, scale);
+            let frames: Vec<DynamicImage> = if scale == largest {
+                frames.clone()
+            } else {
+                timings::record(Stage::Composite, || {
+                    frames
+                        .iter()
+                        .map(|frame| {
+                            helper::resize_by(frame, scale / largest, options.filter.filter_type())
+                        })
+                        .collect()
+                })
+            };
+
+            let saved = timings::record(Stage::Save, || match frame_delay_ms {
+                Some(delay_ms) => {
+                    animation::save_apng(&output_path, &frames, delay_ms).and_then(|()| {
+                        std::fs::metadata(&output_path)
+                            .map(|metadata| metadata.len())
+                            .map_err(|e| e.to_string())
+                    })
+                }
+                None => save_image(
+                    &frames[0],
+                    &output_path,
+                    options.format,
+                    options.reproducible,
+                ),
+            });
+
+            match saved {
+                Ok(bytes) => emit(TaskEvent::Succeeded {
+                    task,
+                    output_path: &output_path,
+                    bytes,
+                }),
+                Err(error) => emit(TaskEvent::Failed {
+                    task,
+                    output_path: &output_path,
+                    error,
+                }),
+            }
+        }
+
+        if options.trace_layers
+            && let Err(e) = helper::write_layer_trace(&output_path, &task.meta, &composite.traces)
+        {
+            log::warn!(
+                "Failed to write layer trace for '{}': {}",
+                output_path.display(),
+                e
+            );
+        }
+
+        if let Some(folder) = &options.export_layers
+            && let Err(e) = export_layers(folder, task, &composite.traces)
+        {
+            log::warn!("Failed to export the layers of '{}': {}", task.filename, e);
+        }
+    };
+
+    // Chunks bound how many canvases are alive at once, each is waited for
+    // before the next starts
+    match options.chunk_size {
+        Some(size) if size > 0 => {
+            for chunk in tasks.chunks(size) {
+                if cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+                chunk.par_iter().for_each(render_task);
+            }
+        }
+        _ => tasks.par_iter().for_each(render_task),
+    }
+
+    match (options.max_skips, options.timeout) {
+        (_, Some(timeout)) if timed_out.load(Ordering::Relaxed) => Err(Stopped::TimedOut(timeout)),
+        (Some(max_skips), _) if cancelled.load(Ordering::Relaxed) => {
+            Err(Stopped::TooManySkips(max_skips))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether no pixel of an image has any opacity
+fn is_fully_transparent(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().all(|p| p[3] == 0)
+}
+
+/// Why [`render_task_to_bytes`] failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackError {
+    /// The task couldn't be composited, like when its source image can't be opened
+    Compose(String),
+    /// The composite couldn't be encoded in the requested format
+    Encode(String),
+}
+
+impl std::fmt::Display for PackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackError::Compose(reason) => write!(f, "Failed to compose: {}", reason),
+            PackError::Encode(reason) => write!(f, "Failed to encode: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+/// Render a task and encode it in `format` in memory instead of writing it, for
+/// embedding. `options.format` is ignored. With several `options.scales`, only the
+/// output at the largest scale is returned, the one the others are downsampled from.
+/// Animated outputs only get their first frame.
+pub fn render_task_to_bytes(
+    task: &Task,
+    options: &RenderOptions,
+    format: OutputFormat,
+) -> Result<Vec<u8>, PackError> {
+    let composite = compose(task, options).map_err(PackError::Compose)?;
+    encode_image(&composite.image, format, options.reproducible).map_err(PackError::Encode)
+}
+
+/// Encode an image in the given format, JPEG dropping the alpha channel it can't hold.
+/// With `reproducible`, PNGs are written with the best compression and the Paeth
+/// filter on every row instead of the adaptive choice of the encoder, whatever its
+/// defaults are, and fully transparent pixels are made transparent black, so the
+/// same visible pixels always give the same bytes.
+pub fn encode_image(
+    image: &DynamicImage,
+    format: OutputFormat,
+    reproducible: bool,
+) -> Result<Vec<u8>, String> {
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    let encoded = match format {
+        OutputFormat::Jpeg => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut bytes, format.image_format())
+        }
+        OutputFormat::Png if reproducible => {
+            let image = clear_transparent(image);
+            PngEncoder::new_with_quality(&mut bytes, CompressionType::Best, PngFilterType::Paeth)
+                .write_image(
+                    image.as_bytes(),
+                    image.width(),
+                    image.height(),
+                    image.color(),
+                )
+        }
+        _ => image.write_to(&mut bytes, format.image_format()),
+    };
+    encoded.map_err(|e| e.to_string())?;
+    Ok(bytes.into_inner())
+}
+
+/// Copy of an image whose fully transparent pixels are transparent black, the
+/// color hidden under them depending on how they were composited
+fn clear_transparent(image: &DynamicImage) -> DynamicImage {
+    let mut image = image.clone();
+    match &mut image {
+        DynamicImage::ImageRgba8(rgba) => {
+            for pixel in rgba.pixels_mut().filter(|pixel| pixel[3] == 0) {
+                *pixel = Rgba([0; 4]);
+            }
+        }
+        DynamicImage::ImageRgba16(rgba) => {
+            for pixel in rgba.pixels_mut().filter(|pixel| pixel[3] == 0) {
+                *pixel = Rgba([0; 4]);
+            }
+        }
+        _ => {}
+    }
+    image
+}
+
+/// Write an image in the given format, see [`encode_image`]. Returns the size of the file.
+fn save_image(
+    image: &DynamicImage,
+    path: &Path,
+    format: OutputFormat,
+    reproducible: bool,
+) -> Result<u64, String> {
+    let bytes = encode_image(image, format, reproducible)?;
+    std::fs::write(path, &bytes).map_err(|e| e.to_string())?;
+    Ok(bytes.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    /// Save an image of a single color, creating its folder
+    fn save_solid(path: &Path, (width, height): (u32, u32), color: [u8; 4]) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        RgbaImage::from_pixel(width, height, Rgba(color))
+            .save(path)
+            .unwrap();
+    }
+
+    /// Options reading sources from `dir/source` and writing to `dir/output`
+    fn options(dir: &Path) -> RenderOptions {
+        RenderOptions {
+            source_folder: dir.join("source"),
+            output_folder: dir.join("output"),
+            ..Default::default()
+        }
+    }
+
+    /// Task of the `Perks/a` source, its layers read from `dir/layers`
+    fn task(dir: &Path, layers: &[&str]) -> Task {
+        Task {
+            element_type: "Perks".to_string(),
+            filename: "a".to_string(),
+            layers: layers.iter().map(|layer| layer.to_string()).collect(),
+            layer_folders: vec![dir.join("layers")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tinted_item_is_colored() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (4, 4),
+            [128, 128, 128, 255],
+        );
+
+        let task = Task {
+            item_tint: Some("ff0000".to_string()),
+            ..task(dir.path(), &[])
+        };
+        let composite = compose(&task, &options(dir.path())).unwrap();
+        let image = composite.image.to_rgba8();
+        assert!(image.pixels().all(|pixel| pixel.0 == [128, 0, 0, 255]));
+    }
+
+    #[test]
+    fn trace_lists_the_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (4, 4), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("layers/bg.png"),
+            (4, 4),
+            [255, 255, 255, 255],
+        );
+        save_solid(
+            &dir.path().join("layers/frame.png"),
+            (2, 2),
+            [255, 255, 255, 255],
+        );
+
+        let task = Task {
+            meta: BTreeMap::from([("author".to_string(), "me".into())]),
+            ..task(
+                dir.path(),
+                &["bg#00ff00", "frame:scale(2):difference", "gone"],
+            )
+        };
+        let composite = compose(&task, &options(dir.path())).unwrap();
+        let output = dir.path().join("a.png");
+        helper::write_layer_trace(&output, &task.meta, &composite.traces).unwrap();
+
+        let trace = std::fs::read_to_string(dir.path().join("a.layers.txt")).unwrap();
+        let layers = dir.path().join("layers");
+        assert_eq!(
+            trace,
+            format!(
+                "# author: \"me\"\n\
+                 1. bg | path: {} | tint: 00ff00 | transforms: none | blend: normal | found\n\
+                 2. frame | path: {} | tint: none | transforms: :scale(2):difference | blend: difference | found\n\
+                 3. gone | path: {} | tint: none | transforms: none | blend: normal | missing\n",
+                layers.join("bg.png").display(),
+                layers.join("frame.png").display(),
+                layers.join("gone.png").display(),
+            )
+        );
+    }
+
+    /// Task of the `Perks/<filename>` source, its layers read from `dir/layers`
+    fn named_task(dir: &Path, filename: &str, layers: &[&str]) -> Task {
+        Task {
+            filename: filename.to_string(),
+            ..task(dir, layers)
+        }
+    }
+
+    #[test]
+    fn events_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "c"] {
+            let path = dir.path().join(format!("source/Perks/{name}.png"));
+            save_solid(&path, (2, 2), [0, 0, 0, 255]);
+        }
+        let tasks = [
+            named_task(dir.path(), "a", &[]),
+            named_task(dir.path(), "b", &[]),
+            named_task(dir.path(), "c", &["gone"]),
+        ];
+
+        let events = Mutex::new(Vec::new());
+        let on_event = |event: TaskEvent| {
+            let event = match event {
+                TaskEvent::Started { task } => format!("started {}", task.filename),
+                TaskEvent::Succeeded { task, bytes, .. } => {
+                    assert!(bytes > 0);
+                    format!("succeeded {}", task.filename)
+                }
+                TaskEvent::Skipped { task, .. } => format!("skipped {}", task.filename),
+                TaskEvent::MissingLayer { task, .. } => format!("missing layer {}", task.filename),
+                _ => "unexpected".to_string(),
+            };
+            events.lock().unwrap().push(event);
+        };
+        process_tasks(&tasks, &options(dir.path()), Some(&on_event)).unwrap();
+
+        let mut events = events.into_inner().unwrap();
+        events.sort();
+        assert_eq!(
+            events,
+            [
+                "missing layer c",
+                "skipped b",
+                "started a",
+                "started b",
+                "started c",
+                "succeeded a",
+                "succeeded c",
+            ]
+        );
+    }
+
+    #[test]
+    fn placeholder_stands_in_for_missing_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("layers/bg.png"), (4, 4), [0, 0, 0, 255]);
+        let options = RenderOptions {
+            placeholder: Some(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                2,
+                2,
+                Rgba([255, 0, 0, 255]),
+            ))),
+            ..options(dir.path())
+        };
+
+        let reasons = Mutex::new(Vec::new());
+        let on_event = |event: TaskEvent| {
+            if let TaskEvent::Placeholder { reason, .. } = event {
+                reasons.lock().unwrap().push(reason);
+            }
+        };
+        let tasks = [task(dir.path(), &["bg"])];
+        process_tasks(&tasks, &options, Some(&on_event)).unwrap();
+
+        let reasons = reasons.into_inner().unwrap();
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].contains("a.png"), "{}", reasons[0]);
+        let output = image::open(output_path(&tasks[0], &options)).unwrap();
+        assert_eq!(output.dimensions(), (4, 4));
+        assert!(
+            output
+                .to_rgba8()
+                .pixels()
+                .all(|pixel| pixel.0 == [255, 0, 0, 255])
+        );
+    }
+
+    #[test]
+    fn canvas_fill_shows_through_transparent_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (3, 3), [0, 0, 0, 0]);
+        save_solid(&dir.path().join("layers/clear.png"), (3, 3), [0, 255, 0, 0]);
+        let options = RenderOptions {
+            canvas_fill: Some((255, 0, 0)),
+            ..options(dir.path())
+        };
+
+        let composite = compose(&task(dir.path(), &["clear"]), &options).unwrap();
+        let image = composite.image.to_rgba8();
+        assert!(image.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn sequence_of_two_frames_is_animated() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (2, 2), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("layers/glow_01.png"),
+            (2, 2),
+            [255, 0, 0, 255],
+        );
+        save_solid(
+            &dir.path().join("layers/glow_02.png"),
+            (2, 2),
+            [0, 0, 255, 255],
+        );
+        let options = RenderOptions {
+            frame_delay_ms: Some(100),
+            ..options(dir.path())
+        };
+
+        let tasks = [task(dir.path(), &["glow_##:scale(1)"])];
+        process_tasks(&tasks, &options, None).unwrap();
+
+        let file = std::fs::File::open(output_path(&tasks[0], &options)).unwrap();
+        let mut reader = png::Decoder::new(file).read_info().unwrap();
+        let control = reader.info().animation_control.unwrap();
+        assert_eq!(control.num_frames, 2);
+        let mut frame = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut frame).unwrap();
+        assert_eq!(frame[..4], [255, 0, 0, 255]);
+        reader.next_frame(&mut frame).unwrap();
+        assert_eq!(frame[..4], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn oversized_sources_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (5, 3),
+            [0, 0, 0, 255],
+        );
+        let options = RenderOptions {
+            max_dimension: 4,
+            ..options(dir.path())
+        };
+
+        let reasons = Mutex::new(Vec::new());
+        let on_event = |event: TaskEvent| {
+            if let TaskEvent::Skipped { reason, .. } = event {
+                reasons.lock().unwrap().push(reason);
+            }
+        };
+        let tasks = [task(dir.path(), &[])];
+        process_tasks(&tasks, &options, Some(&on_event)).unwrap();
+
+        let reasons = reasons.into_inner().unwrap();
+        assert_eq!(reasons.len(), 1);
+        assert!(
+            reasons[0].ends_with("image is 5x3, larger than the 4 pixel limit"),
+            "{}",
+            reasons[0]
+        );
+        assert!(!output_path(&tasks[0], &options).exists());
+    }
+
+    #[test]
+    fn descriptor_tint_overrides_the_element_default() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (2, 2), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("layers/mask.png"),
+            (2, 2),
+            [255, 255, 255, 255],
+        );
+        let layer_defaults = LayerModifiers {
+            tint: Some("ff0000".to_string()),
+            opacity: 1.0,
+        };
+
+        let pixel = |layer: &str, layer_defaults: &LayerModifiers| {
+            let task = Task {
+                layer_defaults: layer_defaults.clone(),
+                ..task(dir.path(), &[layer])
+            };
+            let composite = compose(&task, &options(dir.path())).unwrap();
+            composite.image.to_rgba8().get_pixel(0, 0).0
+        };
+        assert_eq!(pixel("mask", &layer_defaults), [255, 0, 0, 255]);
+        assert_eq!(pixel("mask#0000ff", &layer_defaults), [0, 0, 255, 255]);
+
+        let translucent = LayerModifiers {
+            opacity: 0.5,
+            ..layer_defaults
+        };
+        assert_eq!(pixel("mask#0000ff", &translucent), [0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn chunks_render_the_same_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("layers/bg.png"), (3, 3), [0, 0, 255, 255]);
+        let names = ["a", "b", "c", "d", "e"];
+        for (index, name) in names.iter().enumerate() {
+            let path = dir.path().join(format!("source/Perks/{name}.png"));
+            save_solid(&path, (3, 3), [index as u8 * 50, 0, 0, 128]);
+        }
+        let tasks: Vec<Task> = names
+            .iter()
+            .map(|name| named_task(dir.path(), name, &["bg"]))
+            .collect();
+
+        let render = |output: &str, chunk_size| {
+            let options = RenderOptions {
+                output_folder: dir.path().join(output),
+                chunk_size,
+                ..options(dir.path())
+            };
+            process_tasks(&tasks, &options, None).unwrap();
+            tasks
+                .iter()
+                .map(|task| std::fs::read(output_path(task, &options)).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(render("all", None), render("chunked", Some(2)));
+    }
+
+    #[test]
+    fn indexed_layers_match_rgba_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (2, 2), [0, 0, 0, 0]);
+        let mut rgba = RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        rgba.put_pixel(1, 0, Rgba([0, 0, 255, 128]));
+        rgba.put_pixel(0, 1, Rgba([0, 0, 0, 0]));
+        std::fs::create_dir(dir.path().join("layers")).unwrap();
+        rgba.save(dir.path().join("layers/rgba.png")).unwrap();
+
+        let file = std::fs::File::create(dir.path().join("layers/indexed.png")).unwrap();
+        let mut encoder = png::Encoder::new(file, 2, 2);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(vec![255, 0, 0, 0, 0, 255, 0, 0, 0]);
+        encoder.set_trns(vec![255, 128, 0]);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 1, 2, 0]).unwrap();
+        writer.finish().unwrap();
+
+        let composite = |layer| {
+            let composite = compose(&task(dir.path(), &[layer]), &options(dir.path())).unwrap();
+            composite.image.to_rgba8()
+        };
+        assert_eq!(composite("indexed"), composite("rgba"));
+        assert_eq!(composite("indexed").get_pixel(1, 0).0, [0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn layers_below_and_above_the_item() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut item = RgbaImage::new(4, 4);
+        let mut top = RgbaImage::new(4, 4);
+        for x in 0..4 {
+            for y in 0..4 {
+                if x < 2 {
+                    item.put_pixel(x, y, Rgba([128, 128, 128, 255]));
+                }
+                if y == 3 {
+                    top.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+                }
+            }
+        }
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        item.save(dir.path().join("source/Perks/a.png")).unwrap();
+        save_solid(&dir.path().join("layers/bg.png"), (4, 4), [0, 0, 255, 255]);
+        top.save(dir.path().join("layers/top.png")).unwrap();
+
+        let task = Task {
+            above_layers: vec!["top".to_string()],
+            ..task(dir.path(), &["bg"])
+        };
+        let image = compose(&task, &options(dir.path()))
+            .unwrap()
+            .image
+            .to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [128, 128, 128, 255]);
+        assert_eq!(image.get_pixel(3, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(0, 3).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn empty_outputs_are_skipped_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (2, 2),
+            [255, 0, 0, 0],
+        );
+        let tasks = [task(dir.path(), &[])];
+
+        let written = |skip_empty| {
+            let options = RenderOptions {
+                output_folder: dir.path().join(format!("output_{skip_empty}")),
+                skip_empty,
+                ..options(dir.path())
+            };
+            let empty = AtomicUsize::new(0);
+            let on_event = |event: TaskEvent| {
+                if let TaskEvent::Empty { .. } = event {
+                    empty.fetch_add(1, Ordering::Relaxed);
+                }
+            };
+            process_tasks(&tasks, &options, Some(&on_event)).unwrap();
+            (
+                output_path(&tasks[0], &options).exists(),
+                empty.into_inner(),
+            )
+        };
+        assert_eq!(written(true), (false, 1));
+        assert_eq!(written(false), (true, 0));
+    }
+
+    #[test]
+    fn seed_makes_random_draws_reproducible() {
+        use rand::Rng;
+
+        let dir = tempfile::tempdir().unwrap();
+        let draws = |seed, filename: &str| {
+            let options = RenderOptions {
+                seed,
+                ..options(dir.path())
+            };
+            let mut rng = options.rng(&format!("Perks/{filename}"));
+            (0..8).map(|_| rng.r#gen::<u32>()).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(7, "a"), draws(7, "a"));
+        assert_ne!(draws(7, "a"), draws(8, "a"));
+        assert_ne!(draws(7, "a"), draws(7, "b"));
+    }
+
+    #[test]
+    fn element_opacity_scales_the_output_alpha() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (2, 2),
+            [10, 20, 30, 255],
+        );
+        let task = Task {
+            opacity: 0.5,
+            ..task(dir.path(), &[])
+        };
+        let image = compose(&task, &options(dir.path()))
+            .unwrap()
+            .image
+            .to_rgba8();
+        assert!(image.pixels().all(|pixel| pixel.0 == [10, 20, 30, 128]));
+    }
+
+    #[test]
+    fn stages_are_timed() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (64, 64),
+            [10, 20, 30, 255],
+        );
+        save_solid(&dir.path().join("layers/bg.png"), (64, 64), [255; 4]);
+        let before = timings::Totals::now();
+        process_tasks(
+            &[task(dir.path(), &["bg#ff0000"])],
+            &options(dir.path()),
+            None,
+        )
+        .unwrap();
+
+        let after = timings::Totals::now();
+        for stage in [Stage::Decode, Stage::Composite, Stage::Save] {
+            assert!(after.since(&before, stage) > Duration::ZERO, "{:?}", stage);
+        }
+    }
+
+    #[test]
+    fn rgb_outputs_are_flattened() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut item = RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255]));
+        item.put_pixel(1, 0, Rgba([0, 0, 0, 0]));
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        item.save(dir.path().join("source/Perks/a.png")).unwrap();
+        let options = RenderOptions {
+            output_color: OutputColor::Rgb,
+            flatten: Flatten::Color((0, 0, 255)),
+            ..options(dir.path())
+        };
+
+        let tasks = [task(dir.path(), &[])];
+        process_tasks(&tasks, &options, None).unwrap();
+        let output = image::open(output_path(&tasks[0], &options)).unwrap();
+        assert_eq!(output.color(), image::ColorType::Rgb8);
+        let output = output.to_rgb8();
+        assert_eq!(output.get_pixel(0, 0).0, [255, 0, 0]);
+        assert_eq!(output.get_pixel(1, 0).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn automatic_frame_contrasts_with_the_item() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("layers/light.png"),
+            (2, 2),
+            [230, 230, 230, 255],
+        );
+        save_solid(
+            &dir.path().join("layers/dark.png"),
+            (2, 2),
+            [20, 20, 20, 255],
+        );
+
+        let frame_over = |item: [u8; 4]| {
+            save_solid(&dir.path().join("source/Perks/a.png"), (2, 2), item);
+            let task = Task {
+                above_layers: vec!["frame:auto(light,dark)".to_string()],
+                ..task(dir.path(), &[])
+            };
+            let composite = compose(&task, &options(dir.path())).unwrap();
+            composite.image.to_rgba8().get_pixel(0, 0).0
+        };
+        assert_eq!(frame_over([10, 10, 10, 255]), [230, 230, 230, 255]);
+        assert_eq!(frame_over([250, 250, 250, 255]), [20, 20, 20, 255]);
+    }
+
+    #[test]
+    fn label_is_drawn_in_its_corner() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (32, 32),
+            [128, 128, 128, 255],
+        );
+        let task = task(dir.path(), &[]);
+        let plain = compose(&task, &options(dir.path()))
+            .unwrap()
+            .image
+            .to_rgba8();
+
+        let style = LabelStyle {
+            color: (255, 0, 0),
+            corner: label::Corner::BottomLeft,
+            scale: 2,
+            font: None,
+        };
+        let options = RenderOptions {
+            debug_labels: Some(style),
+            ..options(dir.path())
+        };
+        let labeled = compose(&task, &options).unwrap().image.to_rgba8();
+
+        // "a" is one glyph 3x5 font pixels wide, a font pixel away from the bottom left
+        let in_label = |x: u32, y: u32| (2..8).contains(&x) && (20..30).contains(&y);
+        let mut changed = 0;
+        for (x, y, pixel) in labeled.enumerate_pixels() {
+            if in_label(x, y) {
+                changed += usize::from(pixel != plain.get_pixel(x, y));
+            } else {
+                assert_eq!(pixel, plain.get_pixel(x, y), "({}, {})", x, y);
+            }
+        }
+        assert!(changed > 0);
+    }
+
+    #[test]
+    fn missing_layers_are_skipped_replaced_or_fail_the_image() {
+        let render = |mode: &str| {
+            let dir = tempfile::tempdir().unwrap();
+            save_solid(&dir.path().join("source/Perks/a.png"), (4, 4), [0, 0, 0, 0]);
+            save_solid(
+                &dir.path().join("layers/bg.png"),
+                (4, 4),
+                [255, 255, 255, 255],
+            );
+            let options = RenderOptions {
+                on_missing_layer: mode.parse().unwrap(),
+                ..options(dir.path())
+            };
+
+            let failed = AtomicBool::new(false);
+            let on_event = |event: TaskEvent| {
+                if let TaskEvent::Failed { error, .. } = event {
+                    assert!(error.contains("gone.png"), "{}", error);
+                    failed.store(true, Ordering::Relaxed);
+                }
+            };
+            let tasks = [task(dir.path(), &["bg", "gone"])];
+            process_tasks(&tasks, &options, Some(&on_event)).unwrap();
+
+            let output = image::open(dir.path().join("output/Perks/a.png")).ok();
+            (
+                failed.into_inner(),
+                output.map(|image| image.get_pixel(0, 0).0),
+            )
+        };
+
+        assert_eq!(render("skip"), (false, Some([255, 255, 255, 255])));
+        assert_eq!(render("placeholder"), (false, Some([255, 0, 255, 255])));
+        assert_eq!(render("error"), (true, None));
+    }
+
+    #[test]
+    fn cached_stacks_are_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bg = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]));
+        bg.put_pixel(3, 3, Rgba([0, 255, 0, 128]));
+        std::fs::create_dir_all(dir.path().join("layers")).unwrap();
+        bg.save(dir.path().join("layers/bg.png")).unwrap();
+        let mut tasks = Vec::new();
+        for (name, color) in [("a", [255, 0, 0, 255]), ("b", [255, 255, 0, 255])] {
+            let mut item = RgbaImage::new(4, 4);
+            item.put_pixel(0, 0, Rgba(color));
+            std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+            item.save(dir.path().join(format!("source/Perks/{name}.png")))
+                .unwrap();
+            tasks.push(named_task(dir.path(), name, &["bg"]));
+        }
+
+        let cached = RenderOptions {
+            stack_cache: Some(StackCache::default()),
+            ..options(dir.path())
+        };
+        let composites: Vec<RgbaImage> = tasks
+            .iter()
+            .map(|task| compose(task, &cached).unwrap().image.to_rgba8())
+            .collect();
+        assert_eq!(cached.stack_cache.as_ref().unwrap().hits(), 1);
+
+        let uncached = compose(&tasks[0], &options(dir.path())).unwrap();
+        assert_eq!(composites[0], uncached.image.to_rgba8());
+        for (x, y, pixel) in composites[0].enumerate_pixels() {
+            if (x, y) != (0, 0) {
+                assert_eq!(pixel, composites[1].get_pixel(x, y));
+            }
+        }
+        assert_ne!(composites[0].get_pixel(0, 0), composites[1].get_pixel(0, 0));
+    }
+
+    #[test]
+    fn reproducible_pngs_are_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let item = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, 0, 255])
+        });
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        item.save(dir.path().join("source/Perks/a.png")).unwrap();
+        let tasks = [task(dir.path(), &[])];
+
+        let render = |output: &str| {
+            let options = RenderOptions {
+                output_folder: dir.path().join(output),
+                reproducible: true,
+                ..options(dir.path())
+            };
+            process_tasks(&tasks, &options, None).unwrap();
+            std::fs::read(dir.path().join(output).join("Perks/a.png")).unwrap()
+        };
+        assert_eq!(render("first"), render("second"));
+
+        // Pixels hidden by their transparency don't change the file
+        let hidden = |color: u8| {
+            let mut image = RgbaImage::from_pixel(4, 4, Rgba([color, color, 0, 0]));
+            image.put_pixel(1, 1, Rgba([255, 0, 0, 255]));
+            DynamicImage::ImageRgba8(image)
+        };
+        let encode = |image: &DynamicImage, reproducible| {
+            encode_image(image, OutputFormat::Png, reproducible).unwrap()
+        };
+        assert_eq!(encode(&hidden(10), true), encode(&hidden(200), true));
+        assert_ne!(encode(&hidden(10), false), encode(&hidden(200), false));
+        let decoded = image::load_from_memory(&encode(&hidden(10), true)).unwrap();
+        assert_eq!(decoded.to_rgba8().get_pixel(1, 1).0, [255, 0, 0, 255]);
+
+        // The encoder settings are pinned, not the defaults of the encoder
+        let opaque = DynamicImage::ImageRgba8(item);
+        assert_ne!(encode(&opaque, true), encode(&opaque, false));
+    }
+
+    #[test]
+    fn every_scale_is_written_at_its_size() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (4, 4),
+            [128, 128, 128, 255],
+        );
+        let options = RenderOptions {
+            output_size: Some(8),
+            scales: vec![1.0, 2.0, 0.5],
+            ..options(dir.path())
+        };
+        process_tasks(&[task(dir.path(), &[])], &options, None).unwrap();
+
+        let size = |name: &str| {
+            let image = image::open(dir.path().join("output/Perks").join(name)).unwrap();
+            (image.width(), image.height())
+        };
+        assert_eq!(size("a.png"), (8, 8));
+        assert_eq!(size("a@2x.png"), (16, 16));
+        assert_eq!(size("a@0.5x.png"), (4, 4));
+    }
+
+    #[test]
+    fn too_many_skips_stop_the_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let tasks: Vec<Task> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| named_task(dir.path(), name, &[]))
+            .collect();
+        let started = |max_skips: usize| {
+            // One task at a time, so none starts after the run is stopped
+            let options = RenderOptions {
+                max_skips: Some(max_skips),
+                chunk_size: Some(1),
+                ..options(dir.path())
+            };
+            let count = AtomicUsize::new(0);
+            let on_event = |event: TaskEvent| {
+                if let TaskEvent::Started { .. } = event {
+                    count.fetch_add(1, Ordering::Relaxed);
+                }
+            };
+            let result = process_tasks(&tasks, &options, Some(&on_event));
+            (result, count.into_inner())
+        };
+
+        assert_eq!(started(1), (Err(Stopped::TooManySkips(1)), 2));
+        assert_eq!(started(4), (Ok(()), 4));
+    }
+
+    #[test]
+    fn bytes_decode_to_the_composite() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (4, 4), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("layers/bg.png"),
+            (4, 4),
+            [10, 200, 30, 255],
+        );
+        let task = task(dir.path(), &["bg"]);
+
+        let bytes = render_task_to_bytes(&task, &options(dir.path()), OutputFormat::Png).unwrap();
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png).unwrap();
+        assert_eq!(
+            decoded.to_rgba8(),
+            compose(&task, &options(dir.path()))
+                .unwrap()
+                .image
+                .to_rgba8()
+        );
+        assert!(!dir.path().join("output").exists());
+
+        // The format is the one asked for, the size the one of the largest scale
+        let options = RenderOptions {
+            scales: vec![1.0, 2.0],
+            ..options(dir.path())
+        };
+        let bytes = render_task_to_bytes(&task, &options, OutputFormat::Jpeg).unwrap();
+        assert_eq!(
+            image::guess_format(&bytes).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert_eq!(
+            image::load_from_memory(&bytes).unwrap().dimensions(),
+            (8, 8)
+        );
+
+        let missing = named_task(dir.path(), "missing", &["bg"]);
+        let error = render_task_to_bytes(&missing, &options, OutputFormat::Png).unwrap_err();
+        assert!(matches!(error, PackError::Compose(_)), "{}", error);
+    }
+
+    #[test]
+    fn flatten_backdrops() {
+        let mut img = RgbaImage::new(16, 16);
+        img.put_pixel(15, 15, Rgba([255, 0, 0, 255]));
+        let img = DynamicImage::ImageRgba8(img);
+        let flattened = |backdrop: &str| {
+            let flattened = backdrop.parse::<Flatten>().unwrap().apply(&img);
+            assert!(!flattened.color().has_alpha());
+            let flattened = flattened.to_rgb8();
+            assert_eq!(flattened.get_pixel(15, 15).0, [255, 0, 0]);
+            [(0, 0), (2, 0)].map(|(x, y)| flattened.get_pixel(x, y).0)
+        };
+
+        assert_eq!(flattened("white"), [[255, 255, 255]; 2]);
+        assert_eq!(flattened("black"), [[0, 0, 0]; 2]);
+        assert_eq!(flattened("color:#336699"), [[0x33, 0x66, 0x99]; 2]);
+        // Cells are an eighth of the image wide
+        assert_eq!(flattened("checker"), [[204, 204, 204], [255, 255, 255]]);
+        assert!("gray".parse::<Flatten>().is_err());
+    }
+
+    #[test]
+    fn item_is_moved_by_its_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut item = RgbaImage::new(40, 40);
+        item.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        item.save(dir.path().join("source/Perks/a.png")).unwrap();
+
+        let task = Task {
+            item_offset: Offset { x: 10, y: 20 },
+            ..task(dir.path(), &[])
+        };
+        let composite = compose(&task, &options(dir.path()))
+            .unwrap()
+            .image
+            .to_rgba8();
+        let visible: Vec<(u32, u32)> = composite
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] > 0)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(visible, [(10, 20)]);
+    }
+
+    #[test]
+    fn premultiplied_edges_are_not_darkened() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (2, 2), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("layers/bg.png"),
+            (2, 2),
+            [255, 255, 255, 255],
+        );
+        // Half transparent red, its color multiplied by its alpha
+        save_solid(
+            &dir.path().join("layers/edge.png"),
+            (2, 2),
+            [128, 0, 0, 128],
+        );
+
+        let edge = |descriptor: &str| {
+            let task = task(dir.path(), &["bg", descriptor]);
+            let composite = compose(&task, &options(dir.path())).unwrap();
+            let [r, g, b, _] = composite.image.get_pixel(0, 0).0;
+            [r, g, b]
+        };
+        assert_eq!(edge("edge:premul"), [255, 127, 127]);
+        // Read as straight alpha, the already darkened red is darkened again
+        assert_eq!(edge("edge:straight"), [191, 127, 127]);
+        assert_eq!(edge("edge"), edge("edge:straight"));
+    }
+
+    #[test]
+    fn mirrored_outputs_keep_readable_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut item = RgbaImage::new(32, 32);
+        item.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        item.save(dir.path().join("source/Perks/a.png")).unwrap();
+        let task = task(dir.path(), &[]);
+
+        let mirrored = |mirror: Mirror| {
+            let options = RenderOptions {
+                mirror,
+                debug_labels: Some(LabelStyle {
+                    color: (0, 0, 255),
+                    corner: label::Corner::BottomLeft,
+                    scale: 2,
+                    font: None,
+                }),
+                ..options(dir.path())
+            };
+            compose(&task, &options).unwrap().image.to_rgba8()
+        };
+        // Pixels of the item in red and of the label in blue
+        let colored = |image: &RgbaImage, channel: usize| -> Vec<(u32, u32)> {
+            let pixels = image.enumerate_pixels();
+            pixels
+                .filter(|(_, _, pixel)| pixel[channel] == 255)
+                .map(|(x, y, _)| (x, y))
+                .collect()
+        };
+        let red = |image: &RgbaImage| colored(image, 0);
+        let label = |image: &RgbaImage| colored(image, 2);
+
+        let (plain, horizontal, vertical) = (
+            mirrored(Mirror::None),
+            mirrored(Mirror::Horizontal),
+            mirrored(Mirror::Vertical),
+        );
+        assert_eq!(red(&plain), [(0, 0)]);
+        assert_eq!(red(&horizontal), [(31, 0)]);
+        assert_eq!(red(&vertical), [(0, 31)]);
+        assert!(!label(&plain).is_empty());
+        assert_eq!(label(&horizontal), label(&plain));
+        assert_eq!(label(&vertical), label(&plain));
+    }
+
+    #[test]
+    fn layers_hiding_what_is_under_them_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (4, 4), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("layers/glow.png"),
+            (4, 4),
+            [255, 0, 0, 128],
+        );
+        save_solid(
+            &dir.path().join("layers/bg.png"),
+            (4, 4),
+            [255, 255, 255, 255],
+        );
+        save_solid(
+            &dir.path().join("layers/corner.png"),
+            (1, 1),
+            [0, 0, 0, 255],
+        );
+
+        let hides = |options: &RenderOptions| {
+            let task = task(dir.path(), &["glow", "corner", "bg"]);
+            let composite = compose(&task, options).unwrap();
+            composite
+                .traces
+                .iter()
+                .map(|trace| trace.hides)
+                .collect::<Vec<_>>()
+        };
+        let linted = RenderOptions {
+            lint_order: Some(0.9),
+            ..options(dir.path())
+        };
+        // The opaque background hides the glow, the corner a sixteenth of it
+        assert_eq!(hides(&linted), [None, None, Some(1.0)]);
+        assert_eq!(hides(&options(dir.path())), [None, None, None]);
+        let filled = RenderOptions {
+            canvas_fill: Some((0, 0, 0)),
+            ..linted
+        };
+        assert_eq!(hides(&filled), [None, None, None]);
+    }
+
+    #[test]
+    fn every_drawn_layer_is_exported() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("source/Perks/a.png"), (2, 2), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("layers/bg.png"),
+            (2, 2),
+            [255, 255, 255, 255],
+        );
+        save_solid(
+            &dir.path().join("layers/frame.png"),
+            (2, 2),
+            [128, 128, 128, 255],
+        );
+        let export = dir.path().join("layers_export");
+        let options = RenderOptions {
+            export_layers: Some(export.clone()),
+            ..options(dir.path())
+        };
+        let tasks = [task(dir.path(), &["bg", "gone", "frame#ff0000"])];
+        process_tasks(&tasks, &options, None).unwrap();
+
+        let folder = export.join("Perks/a");
+        let mut exported: Vec<String> = std::fs::read_dir(&folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        exported.sort();
+        assert_eq!(exported, ["01_bg.png", "02_frame.png"]);
+        let frame = image::open(folder.join("02_frame.png")).unwrap();
+        assert_eq!(frame.get_pixel(0, 0).0, [128, 0, 0, 255]);
+    }
+
+    #[test]
+    fn mirror_tree_keeps_the_nested_path() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("source/Items/Addons/Flashlight/lens.png"),
+            (2, 2),
+            [0, 0, 0, 255],
+        );
+        let task = Task {
+            element_type: "Items/Addons".to_string(),
+            ..named_task(dir.path(), "Flashlight/lens", &[])
+        };
+        let mirrored = RenderOptions {
+            layout: OutputLayout::MirrorTree,
+            ..options(dir.path())
+        };
+        process_tasks(std::slice::from_ref(&task), &mirrored, None).unwrap();
+
+        let output = dir.path().join("output");
+        assert!(output.join("Items/Addons/Flashlight/lens.png").is_file());
+        assert_eq!(
+            output_path(&task, &options(dir.path())),
+            output.join("Addons").join("Flashlight/lens.png")
+        );
+    }
+
+    #[test]
+    fn item_is_fitted_into_the_inner_rect() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(&dir.path().join("layers/bg.png"), (40, 20), [0, 0, 0, 0]);
+        save_solid(
+            &dir.path().join("source/Perks/a.png"),
+            (40, 20),
+            [255, 0, 0, 255],
+        );
+
+        let task = Task {
+            item_inner: Some(Rect {
+                x: 10,
+                y: 0,
+                w: 10,
+                h: 20,
+            }),
+            ..task(dir.path(), &["bg"])
+        };
+        let composite = compose(&task, &options(dir.path()))
+            .unwrap()
+            .image
+            .to_rgba8();
+        let visible: Vec<(u32, u32)> = composite
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] > 0)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        // Shrunk to 10x5 keeping its aspect, then centered vertically
+        assert_eq!(visible.len(), 10 * 5);
+        assert_eq!(visible.first(), Some(&(10, 7)));
+        assert_eq!(visible.last(), Some(&(19, 11)));
+    }
+
+    #[test]
+    fn global_background_is_beneath_the_icon() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut item = RgbaImage::new(4, 4);
+        item.put_pixel(1, 1, Rgba([255, 0, 0, 255]));
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        item.save(dir.path().join("source/Perks/a.png")).unwrap();
+
+        // Resized to the canvas
+        let options = RenderOptions {
+            global_background: Some(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                2,
+                2,
+                Rgba([0, 0, 255, 255]),
+            ))),
+            ..options(dir.path())
+        };
+        let composite = compose(&task(dir.path(), &[]), &options)
+            .unwrap()
+            .image
+            .to_rgba8();
+        assert_eq!(composite.dimensions(), (4, 4));
+        assert_eq!(composite.get_pixel(1, 1).0, [255, 0, 0, 255]);
+        assert!(
+            composite
+                .enumerate_pixels()
+                .filter(|(x, y, _)| (*x, *y) != (1, 1))
+                .all(|(_, _, pixel)| pixel.0 == [0, 0, 255, 255])
+        );
+    }
+
+    #[test]
+    fn element_types_take_turns() {
+        let tasks = [
+            "Perks/a", "Perks/b", "Perks/c", "Items/d", "Addons/e", "Items/f",
+        ]
+        .map(|name| {
+            let (element_type, filename) = name.split_once('/').unwrap();
+            Task {
+                element_type: element_type.to_string(),
+                filename: filename.to_string(),
+                ..Default::default()
+            }
+        });
+        let order: Vec<String> = interleave_element_types(tasks.into())
+            .into_iter()
+            .map(|task| task.filename)
+            .collect();
+        assert_eq!(order, ["a", "d", "e", "b", "f", "c"]);
+    }
+
+    #[test]
+    fn rarity_in_the_name_tints_the_layer() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("layers/bg.png"),
+            (4, 4),
+            [255, 255, 255, 255],
+        );
+        for name in ["perk_rare", "perk"] {
+            let path = dir.path().join(format!("source/Perks/{name}.png"));
+            save_solid(&path, (4, 4), [0, 0, 0, 0]);
+        }
+        let corner = |filename: &str| {
+            let task = named_task(dir.path(), filename, &["bg#rarity"]);
+            let composite = compose(&task, &options(dir.path())).unwrap();
+            composite.image.to_rgba8().get_pixel(0, 0).0
+        };
+
+        let (r, g, b) = helper::PALETTE["rare"];
+        assert_eq!(corner("perk_rare"), [r, g, b, 255]);
+        // Without a rarity the layer is drawn untinted
+        assert_eq!(corner("perk"), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn sixteen_bit_composites_keep_their_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let color = [0x1234, 0xabcd, 0x0101, 0xffff];
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        image::ImageBuffer::<Rgba<u16>, _>::from_pixel(4, 4, Rgba(color))
+            .save(dir.path().join("source/Perks/a.png"))
+            .unwrap();
+
+        let options = RenderOptions {
+            bit_depth: BitDepth::Sixteen,
+            ..options(dir.path())
+        };
+        let tasks = [task(dir.path(), &[])];
+        let composite = compose(&tasks[0], &options).unwrap();
+        assert_eq!(composite.image.to_rgba16().get_pixel(0, 0).0, color);
+
+        process_tasks(&tasks, &options, None).unwrap();
+        let output = image::open(output_path(&tasks[0], &options)).unwrap();
+        assert!(matches!(output, DynamicImage::ImageRgba16(_)));
+        assert_eq!(output.to_rgba16().get_pixel(3, 3).0, color);
+    }
+
+    #[test]
+    fn masked_tint_stays_in_its_region() {
+        let dir = tempfile::tempdir().unwrap();
+        save_solid(
+            &dir.path().join("layers/bg.png"),
+            (4, 4),
+            [255, 255, 255, 255],
+        );
+        save_solid(&dir.path().join("source/Perks/a.png"), (4, 4), [0, 0, 0, 0]);
+        let mask = RgbaImage::from_fn(4, 4, |x, _| match x {
+            0 | 1 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+        mask.save(dir.path().join("layers/gem.png")).unwrap();
+
+        let task = task(dir.path(), &["bg#ff0000:mask=gem"]);
+        let composite = compose(&task, &options(dir.path()))
+            .unwrap()
+            .image
+            .to_rgba8();
+        for (x, y, pixel) in composite.enumerate_pixels() {
+            let expected = if x < 2 {
+                [255, 0, 0, 255]
+            } else {
+                [255, 255, 255, 255]
+            };
+            assert_eq!(pixel.0, expected, "at {},{}", x, y);
+        }
+    }
+
+    #[test]
+    fn slow_runs_stop_at_the_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let tasks: Vec<Task> = (0..8)
+            .map(|i| {
+                let name = format!("slow{i}");
+                let path = dir.path().join(format!("source/Perks/{name}.png"));
+                save_solid(&path, (2, 2), [0, 0, 0, 255]);
+                named_task(dir.path(), &name, &[])
+            })
+            .collect();
+        let timeout = Duration::from_millis(1);
+        let options = RenderOptions {
+            timeout: Some(timeout),
+            chunk_size: Some(1),
+            ..options(dir.path())
+        };
+
+        // Every task takes longer than the whole run may, one at a time
+        let count = AtomicUsize::new(0);
+        let on_event = |event: TaskEvent| {
+            if let TaskEvent::Started { .. } = event {
+                count.fetch_add(1, Ordering::Relaxed);
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        };
+        let result = process_tasks(&tasks, &options, Some(&on_event));
+        assert_eq!(result, Err(Stopped::TimedOut(timeout)));
+        assert!(count.into_inner() < tasks.len());
+    }
+}
@@ -0,0 +1,227 @@
+//! Manifest of rendered outputs with hashes, letting interrupted runs skip finished work
+
+use anyhow::Context;
+use pack_creator::helper::{self, Fnv1a};
+use pack_creator::render::{self, RenderOptions, Task};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Outputs written by previous runs, with what they were rendered from
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Hash of the options outputs are rendered with, every output is stale once it changes
+    options: String,
+    /// Keyed by `element_type/filename`
+    tasks: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Hash of the task and of every file it reads
+    inputs: String,
+    /// Hash of every output file
+    outputs: BTreeMap<PathBuf, String>,
+}
+
+fn hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Hash of the content of a file, `None` when it can't be read
+fn file_hash(path: &Path) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    let mut hasher = Fnv1a::default();
+    hasher.write(&bytes);
+    Some(hex(hasher.finish()))
+}
+
+/// Key of a task in the manifest
+pub fn task_key(task: &Task) -> String {
+    format!("{}/{}", task.element_type, task.filename)
+}
+
+/// Hash of every option in effect that changes what is written, whether it comes
+/// from the command line or settings.json, along with the pixels of the placeholder,
+/// background and watermark images. Options only changing how the work is spread,
+/// like threads or chunks, are left out.
+pub fn options_hash(options: &RenderOptions) -> String {
+    let mut hasher = Fnv1a::default();
+    let drawing = format!(
+        "{:?}",
+        (
+            &options.source_folder,
+            &options.output_folder,
+            options.color_matrix,
+            options.rarity_pattern.as_str(),
+            &options.remap,
+            options.remap_tolerance,
+            options.posterize,
+            options.canvas_fill,
+            options.frame_delay_ms,
+            options.normalize_margin,
+            options.output_size,
+            options.format,
+        )
+    );
+    let output = format!(
+        "{:?}",
+        (
+            options.layout,
+            options.pad_numbers,
+            options.filter,
+            options.max_dimension,
+            options.on_missing_layer,
+            options.skip_empty,
+            options.output_color,
+            options.bit_depth,
+            options.dither,
+            options.flatten,
+            options.mirror,
+            &options.debug_labels,
+        )
+    );
+    let effects = format!(
+        "{:?}",
+        (
+            options.vignette,
+            options.sharpen,
+            options.sharpen_threshold,
+            &options.scales,
+            options.reproducible,
+            options
+                .watermark
+                .as_ref()
+                .map(|watermark| (watermark.mode, watermark.fit)),
+        )
+    );
+    for part in [drawing, output, effects] {
+        hasher.write(part.as_bytes());
+        hasher.write_u8(0);
+    }
+
+    let images = [
+        options.global_background.as_ref(),
+        options.placeholder.as_ref(),
+        options.watermark.as_ref().map(|watermark| &watermark.image),
+    ];
+    for image in images {
+        match image {
+            Some(image) => {
+                hasher.write(&[1]);
+                hasher.write(
+                    format!("{:?}", (image.width(), image.height(), image.color())).as_bytes(),
+                );
+                hasher.write(image.as_bytes());
+            }
+            None => hasher.write_u8(0),
+        }
+    }
+    hex(hasher.finish())
+}
+
+/// Hash of everything a task is rendered from: its description, its source image
+/// and the layer files it uses
+pub fn input_hash(task: &Task, options: &RenderOptions) -> String {
+    let mut hasher = Fnv1a::default();
+    let description = format!(
+        "{:?}",
+        (
+            &task.item_tint,
+            &task.layers,
+            &task.above_layers,
+            &task.meta,
+            &task.layer_folders,
+            &task.layer_defaults,
+            task.opacity,
+            &task.output_name,
+            task.item_offset,
+            task.item_inner,
+        )
+    );
+    hasher.write(task_key(task).as_bytes());
+    hasher.write(description.as_bytes());
+
+    for path in input_files(task, options) {
+        if let Ok(bytes) = std::fs::read(&path) {
+            hasher.write(&bytes);
+        }
+    }
+    hex(hasher.finish())
+}
+
+/// The source image of a task and every layer and mask file it may use that exists
+pub fn input_files(task: &Task, options: &RenderOptions) -> Vec<PathBuf> {
+    let layer_files = task
+        .layers
+        .iter()
+        .chain(&task.above_layers)
+        .flat_map(|layer| helper::layer_choices(layer))
+        .flat_map(|layer| {
+            std::iter::once(helper::layer_base_name(layer))
+                .chain(helper::split_transforms(layer).1.mask)
+        })
+        .filter_map(|name| helper::find_layer_path(&task.layer_folders, name));
+    std::iter::once(render::source_path(task, &options.source_folder))
+        .chain(layer_files)
+        .collect()
+}
+
+/// Whether the source image or a layer file of a task was modified after `time`
+pub fn changed_since(task: &Task, options: &RenderOptions, time: SystemTime) -> bool {
+    input_files(task, options).iter().any(|path| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified > time)
+    })
+}
+
+impl Manifest {
+    /// Read a manifest, empty when the file doesn't exist yet
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid resume manifest {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Whether a task was rendered from the same inputs and options, and all of
+    /// its `expected` outputs are still the files that were written
+    pub fn is_current(&self, key: &str, inputs: &str, options: &str, expected: &[PathBuf]) -> bool {
+        let Some(entry) = self.tasks.get(key) else {
+            return false;
+        };
+        self.options == options
+            && entry.inputs == inputs
+            && expected.iter().all(|path| {
+                entry
+                    .outputs
+                    .get(path)
+                    .is_some_and(|hash| file_hash(path).as_ref() == Some(hash))
+            })
+    }
+
+    /// Remember the outputs of a task as they are on disk now
+    pub fn record(&mut self, key: String, inputs: String, options: &str, outputs: &[PathBuf]) {
+        if self.options != options {
+            // Entries of other options would never be current again
+            self.tasks.clear();
+            self.options = options.to_string();
+        }
+        let outputs = outputs
+            .iter()
+            .filter_map(|path| Some((path.clone(), file_hash(path)?)))
+            .collect();
+        self.tasks.insert(key, Entry { inputs, outputs });
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Context;
+use image::{DynamicImage, GenericImageView, imageops};
+use pack_creator::render::{self, RenderOptions, Task};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Paths of the strip image of an element type and of its sidecar JSON,
+/// `Perks.strip.png` and `Perks.strip.json` in the output folder
+pub fn paths(element_type: &str, output_folder: &Path) -> (PathBuf, PathBuf) {
+    let name = element_type.replace(['/', '\\'], "_");
+    (
+        output_folder.join(format!("{}.strip.png", name)),
+        output_folder.join(format!("{}.strip.json", name)),
+    )
+}
+
+/// Element type, name and output path of an icon of a strip
+pub struct Icon {
+    element_type: String,
+    name: String,
+    path: PathBuf,
+}
+
+impl Icon {
+    pub fn new(task: &Task, options: &RenderOptions) -> Self {
+        Icon {
+            element_type: task.element_type.clone(),
+            name: task.filename.clone(),
+            path: render::output_path(task, options),
+        }
+    }
+}
+
+/// Lay the written outputs of every element type out left to right in one image,
+/// each fitted and centered in a `cell` pixels square, sorted by name so the layout
+/// doesn't depend on the order the tasks were listed in.
+/// Outputs that weren't written are left out. Returns the number of strips written.
+pub fn write(icons: &[Icon], options: &RenderOptions, cell: u32) -> anyhow::Result<usize> {
+    let mut elements: BTreeMap<&str, Vec<(&str, DynamicImage)>> = BTreeMap::new();
+    for icon in icons {
+        let Ok(image) = image::open(&icon.path) else {
+            continue;
+        };
+        elements
+            .entry(&icon.element_type)
+            .or_default()
+            .push((&icon.name, image));
+    }
+
+    for icons in elements.values_mut() {
+        icons.sort_by_key(|(name, _)| *name);
+    }
+
+    for (element_type, icons) in &elements {
+        let mut strip = DynamicImage::new_rgba8(cell * icons.len() as u32, cell);
+        let mut offsets = Vec::with_capacity(icons.len());
+        for (index, (name, icon)) in icons.iter().enumerate() {
+            let x = index as u32 * cell;
+            let fitted = if icon.width() > cell || icon.height() > cell {
+                icon.resize(cell, cell, options.filter.filter_type())
+            } else {
+                icon.clone()
+            };
+            let (width, height) = fitted.dimensions();
+            imageops::overlay(
+                &mut strip,
+                &fitted,
+                (x + (cell - width) / 2) as i64,
+                ((cell - height) / 2) as i64,
+            );
+            offsets.push(serde_json::json!({ "name": name, "x": x }));
+        }
+
+        let (image_path, json_path) = paths(element_type, &options.output_folder);
+        strip
+            .save(&image_path)
+            .with_context(|| format!("Failed to write {}", image_path.display()))?;
+        let json = serde_json::json!({
+            "element": element_type,
+            "cell": cell,
+            "icons": offsets,
+        });
+        std::fs::write(&json_path, serde_json::to_string_pretty(&json)? + "\n")
+            .with_context(|| format!("Failed to write {}", json_path.display()))?;
+    }
+    Ok(elements.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn icon(element_type: &str, name: &str, path: PathBuf) -> Icon {
+        Icon {
+            element_type: element_type.to_string(),
+            name: name.to_string(),
+            path,
+        }
+    }
+
+    #[test]
+    fn icons_are_sorted_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let red = dir.path().join("red.png");
+        let blue = dir.path().join("blue.png");
+        RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]))
+            .save(&red)
+            .unwrap();
+        RgbaImage::from_pixel(2, 2, Rgba([0, 0, 255, 255]))
+            .save(&blue)
+            .unwrap();
+
+        let options = RenderOptions {
+            output_folder: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let icons = [
+            icon("Perks", "b", blue),
+            icon("Perks", "a", red),
+            icon("Perks", "missing", dir.path().join("missing.png")),
+        ];
+        assert_eq!(write(&icons, &options, 4).unwrap(), 1);
+
+        let (image_path, json_path) = paths("Perks", dir.path());
+        let strip = image::open(image_path).unwrap().to_rgba8();
+        assert_eq!(strip.dimensions(), (8, 4));
+        assert_eq!(strip.get_pixel(1, 1), &Rgba([255, 0, 0, 255]));
+        assert_eq!(strip.get_pixel(5, 1), &Rgba([0, 0, 255, 255]));
+        assert_eq!(strip.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(json_path).unwrap()).unwrap();
+        assert_eq!(json["icons"][0]["name"], "a");
+        assert_eq!(json["icons"][1]["x"], 4);
+    }
+
+    #[test]
+    fn strips_are_a_cell_per_icon() {
+        let dir = tempfile::tempdir().unwrap();
+        let save = |name: &str, (width, height): (u32, u32)| {
+            let path = dir.path().join(format!("{name}.png"));
+            RgbaImage::from_pixel(width, height, Rgba([0, 255, 0, 255]))
+                .save(&path)
+                .unwrap();
+            path
+        };
+        let icons = [
+            icon("Perks", "a", save("a", (4, 4))),
+            icon("Perks", "b", save("b", (2, 2))),
+            icon("Perks", "c", save("c", (8, 4))),
+            icon("Items", "d", save("d", (4, 4))),
+        ];
+        let options = RenderOptions {
+            output_folder: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        assert_eq!(write(&icons, &options, 4).unwrap(), 2);
+
+        let (perks, perks_json) = paths("Perks", dir.path());
+        let perks = image::open(perks).unwrap().to_rgba8();
+        assert_eq!(perks.dimensions(), (12, 4));
+        // The small icon is centered, the wide one fitted to 4x2 and centered
+        assert_eq!(perks.get_pixel(4, 0)[3], 0);
+        assert_eq!(perks.get_pixel(5, 1)[3], 255);
+        assert_eq!(perks.get_pixel(8, 0)[3], 0);
+        assert_eq!(perks.get_pixel(8, 1)[3], 255);
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(perks_json).unwrap()).unwrap();
+        let offsets: Vec<&serde_json::Value> = json["icons"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|icon| &icon["x"])
+            .collect();
+        assert_eq!(offsets, [0, 4, 8]);
+
+        let (items, _) = paths("Items", dir.path());
+        assert_eq!(image::open(items).unwrap().width(), 4);
+    }
+}
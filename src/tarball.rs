@@ -0,0 +1,96 @@
+use crate::{checksums, clean};
+use anyhow::Context;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::path::Path;
+
+/// Write every file of the output folder to a gzip-compressed tar at `path`, under
+/// its path relative to the output folder. Files are streamed one at a time and
+/// the archive itself is left out when it is written inside the output folder.
+/// Symlinked folders, like shared asset folders, are archived with their content
+/// under the link's name, a link back to a folder already archived being skipped.
+/// Returns the number of files archived.
+pub fn write(output_folder: &Path, path: &Path) -> anyhow::Result<usize> {
+    let files = clean::files_under_following_links(output_folder)
+        .with_context(|| format!("Failed to list {}", output_folder.display()))?;
+
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mut count = 0;
+    for file in files.iter().filter(|file| file.as_path() != path) {
+        archive
+            .append_path_with_name(file, checksums::relative_name(file, output_folder))
+            .with_context(|| format!("Failed to archive {}", file.display()))?;
+        count += 1;
+    }
+    archive
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn entries_keep_their_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+        for (name, content) in [("Perks/a.png", "a"), ("Items/Addons/b.png", "b")] {
+            let path = output.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        // Written inside the output folder, the archive leaves itself out
+        let path = output.join("pack.tar.gz");
+        assert_eq!(write(&output, &path).unwrap(), 2);
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(&path).unwrap()));
+        let mut entries: Vec<(String, String)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().display().to_string();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (name, content)
+            })
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("Items/Addons/b.png".to_string(), "b".to_string()),
+                ("Perks/a.png".to_string(), "a".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn symlinked_folders_are_archived_with_their_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+        let shared = dir.path().join("shared");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(shared.join("b.png"), "b").unwrap();
+        std::os::unix::fs::symlink(&shared, output.join("Shared")).unwrap();
+        std::os::unix::fs::symlink(&output, shared.join("loop")).unwrap();
+
+        let path = dir.path().join("pack.tar.gz");
+        assert_eq!(write(&output, &path).unwrap(), 1);
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(&path).unwrap()));
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().display().to_string(), "Shared/b.png");
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "b");
+    }
+}
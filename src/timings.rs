@@ -0,0 +1,150 @@
+//! Time spent decoding, compositing and saving images, summed over every thread,
+//! and spans of the `--profile` trace
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A stage of rendering
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    /// Reading and decoding source images and layers
+    Decode,
+    /// Tinting, stacking and post-processing
+    Composite,
+    /// Encoding and writing outputs
+    Save,
+}
+
+static TOTALS: [AtomicU64; 3] = [const { AtomicU64::new(0) }; 3];
+
+/// Run `f`, adding the time it took to `stage`
+pub fn record<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "profile")]
+    let _span = match stage {
+        Stage::Decode => tracing::info_span!("decode"),
+        Stage::Composite => tracing::info_span!("composite"),
+        Stage::Save => tracing::info_span!("save"),
+    }
+    .entered();
+    let start = Instant::now();
+    let result = f();
+    TOTALS[stage as usize].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+/// Time spent in every stage up to some point. Totals are shared by the whole
+/// process, a run measures its own time by difference with a snapshot taken
+/// when it started.
+#[derive(Debug, Clone, Copy)]
+pub struct Totals([u64; 3]);
+
+impl Totals {
+    /// Time spent in every stage so far
+    pub fn now() -> Self {
+        Totals(std::array::from_fn(|i| TOTALS[i].load(Ordering::Relaxed)))
+    }
+
+    /// Time spent in `stage` between `earlier` and this snapshot
+    pub fn since(&self, earlier: &Totals, stage: Stage) -> Duration {
+        let i = stage as usize;
+        Duration::from_nanos(self.0[i].saturating_sub(earlier.0[i]))
+    }
+}
+
+#[cfg(feature = "profile")]
+#[doc(hidden)]
+pub use tracing as __tracing;
+
+/// Enter a span of the `--profile` trace until the end of the current block.
+/// Expands to nothing without the `profile` feature.
+#[cfg(feature = "profile")]
+#[macro_export]
+macro_rules! profile_span {
+    ($($args:tt)*) => {
+        let _span = $crate::timings::__tracing::info_span!($($args)*).entered();
+    };
+}
+
+/// Enter a span of the `--profile` trace until the end of the current block.
+/// Expands to nothing without the `profile` feature.
+#[cfg(not(feature = "profile"))]
+#[macro_export]
+macro_rules! profile_span {
+    ($($args:tt)*) => {};
+}
+
+#[cfg(all(test, feature = "profile"))]
+mod tests {
+    use crate::render::{self, RenderOptions, Task};
+    use image::{Rgba, RgbaImage};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Name of a span and of its parent
+    type Span = (String, Option<String>);
+
+    /// Spans opened, in order
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<Span>>>);
+
+    impl<S> tracing_subscriber::Layer<S> for Spans
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            ctx: Context<'_, S>,
+        ) {
+            let parent = ctx
+                .span(id)
+                .and_then(|span| span.parent())
+                .map(|parent| parent.name().to_string());
+            let name = attrs.metadata().name().to_string();
+            self.0.lock().unwrap().push((name, parent));
+        }
+    }
+
+    #[test]
+    fn stages_are_traced_within_their_phase() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("source/Perks")).unwrap();
+        std::fs::create_dir_all(dir.path().join("layers")).unwrap();
+        let image = RgbaImage::from_pixel(2, 2, Rgba([255; 4]));
+        image.save(dir.path().join("source/Perks/a.png")).unwrap();
+        image.save(dir.path().join("layers/bg.png")).unwrap();
+        let task = Task {
+            element_type: "Perks".to_string(),
+            filename: "a".to_string(),
+            layers: vec!["bg".to_string()],
+            layer_folders: vec![dir.path().join("layers")],
+            ..Default::default()
+        };
+        let options = RenderOptions {
+            source_folder: dir.path().join("source"),
+            ..Default::default()
+        };
+
+        let spans = Spans::default();
+        let subscriber = tracing_subscriber::registry().with(spans.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            crate::profile_span!("render");
+            render::compose(&task, &options).unwrap();
+        });
+
+        let spans = spans.0.lock().unwrap();
+        let parent_of = |name: &str| {
+            spans
+                .iter()
+                .find(|(span, _)| span == name)
+                .map(|(_, parent)| parent.as_deref())
+        };
+        assert_eq!(parent_of("render"), Some(None));
+        assert_eq!(parent_of("stack_layers"), Some(Some("render")));
+        assert_eq!(parent_of("decode"), Some(Some("render")));
+        assert!(spans.contains(&("decode".to_string(), Some("stack_layers".to_string()))));
+        assert!(spans.iter().any(|(span, _)| span == "composite"));
+    }
+}
@@ -0,0 +1,304 @@
+use colored::Colorize;
+use pack_creator::animation::{self, LayerSequence};
+use pack_creator::config::{FolderSource, GameFolders, LayerFallback, Settings};
+use pack_creator::helper::{self, Platform};
+use std::path::Path;
+
+/// Element types without a settings entry and settings entries that can't be used
+pub fn check_settings(
+    settings: &Settings,
+    data: &GameFolders,
+    fallback: LayerFallback,
+    platform: Platform,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for element_type in data.keys() {
+        match settings.folders.get(element_type) {
+            None if settings.layer_folders(element_type, fallback, platform).1
+                != FolderSource::Missing => {}
+            None => problems.push(format!("'{}' has no entry in settings.json", element_type)),
+            Some(folder_config) => {
+                if let Some(hex) = &folder_config.layer_tint
+                    && let Err(e) = helper::parse_tint(hex)
+                {
+                    problems.push(format!("'{}' layer tint '{}': {}", element_type, hex, e));
+                }
+                let opacities = [
+                    ("layer opacity", folder_config.layer_opacity),
+                    ("opacity", folder_config.opacity),
+                ];
+                for (name, opacity) in opacities {
+                    if let Some(opacity) = opacity
+                        && !(0.0..=1.0).contains(&opacity)
+                    {
+                        problems.push(format!(
+                            "'{}' {} {} is outside of 0.0..=1.0",
+                            element_type, name, opacity
+                        ));
+                    }
+                }
+                for folder in folder_config.layers.0.iter().filter(|f| !f.is_empty()) {
+                    let p = helper::resolve_full_path(Path::new(folder));
+                    if !helper::is_path_compatible(&p, platform) {
+                        problems.push(format!(
+                            "'{}' layer folder is incompatible with {:?}: {}",
+                            element_type,
+                            platform,
+                            p.display()
+                        ));
+                    } else if !p.is_dir() {
+                        problems.push(format!(
+                            "'{}' layer folder does not exist: {}",
+                            element_type,
+                            p.display()
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    for element_type in settings.folders.keys() {
+        if !data.contains_key(element_type) {
+            problems.push(format!(
+                "'{}' is in settings.json but not in elements_layering.json",
+                element_type
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Source images referenced by elements_layering.json that don't exist
+pub fn check_sources(data: &GameFolders, source_folder: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (element_type, elements) in data {
+        for entry_name in elements.keys() {
+            let (filename, _) = helper::split_tint(entry_name);
+            if helper::is_url(filename) {
+                continue;
+            }
+            let path = helper::force_png_path(&source_folder.join(element_type), filename);
+            if !path.is_file() {
+                problems.push(path.display().to_string());
+            }
+        }
+    }
+
+    problems
+}
+
+/// Layer and mask files referenced by elements_layering.json that don't exist
+pub fn check_layers(
+    settings: &Settings,
+    data: &GameFolders,
+    fallback: LayerFallback,
+    platform: Platform,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (element_type, elements) in data {
+        let (layer_folders, _) = settings.layer_folders(element_type, fallback, platform);
+        if layer_folders.is_empty() {
+            continue;
+        }
+        for (entry_name, entry) in elements {
+            // Sequences are checked through their first frame
+            let (below, unmatched_below) =
+                helper::expand_layer_globs(&entry.layers, &layer_folders);
+            let (above, unmatched_above) = helper::expand_layer_globs(&entry.above, &layer_folders);
+            for pattern in unmatched_below.iter().chain(&unmatched_above) {
+                problems.push(format!(
+                    "{}/{}: nothing matches '{}'",
+                    element_type, entry_name, pattern
+                ));
+            }
+            let below = helper::resolve_fallbacks(&below, &layer_folders);
+            let above = helper::resolve_fallbacks(&above, &layer_folders);
+            let layers = [
+                animation::resolve_frame(&below, &layer_folders, 0),
+                animation::resolve_frame(&above, &layer_folders, 0),
+            ]
+            .concat();
+            for layer_name in layers.iter().flat_map(|layer| helper::layer_choices(layer)) {
+                if layer_name.is_empty() || layer_name == "none" {
+                    continue;
+                }
+                let base_name = helper::layer_base_name(layer_name);
+                let mask = helper::split_transforms(layer_name).1.mask;
+                let files = std::iter::once(base_name)
+                    .filter(|name| helper::parse_generated(name).is_none())
+                    .chain(mask);
+                for name in files {
+                    if helper::find_layer_path(&layer_folders, name).is_none() {
+                        let path = helper::force_png_path(&layer_folders[0], name);
+                        problems.push(format!(
+                            "{}/{}: {}",
+                            element_type,
+                            entry_name,
+                            path.display()
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// Item and layer tints that are not valid colors, layer scales that are not
+/// positive numbers, invalid conditions and generated layers that can't be drawn
+pub fn check_colors(data: &GameFolders) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (element_type, elements) in data {
+        for (entry_name, entry) in elements {
+            let item = std::iter::once(entry_name.clone());
+            let layers = entry
+                .layers
+                .iter()
+                .chain(&entry.above)
+                .flat_map(|layer| helper::layer_choices(layer))
+                .map(|layer| {
+                    LayerSequence::parse(layer).map_or_else(|| layer.to_string(), |s| s.frame(0))
+                });
+            for descriptor in item.chain(layers) {
+                let (rest, transforms) = helper::split_transforms(&descriptor);
+                if let Some(factor) = transforms.scale
+                    && let Err(e) = helper::parse_scale(factor)
+                {
+                    problems.push(format!(
+                        "{}/{}: '{}': {}",
+                        element_type, entry_name, descriptor, e
+                    ));
+                }
+                if let Some(condition) = transforms.condition
+                    && let Err(e) = helper::parse_condition(condition)
+                {
+                    problems.push(format!(
+                        "{}/{}: '{}': {}",
+                        element_type, entry_name, descriptor, e
+                    ));
+                }
+                let (name, tint) = helper::split_tint(rest);
+                if let Some(Err(e)) = helper::parse_generated(name) {
+                    problems.push(format!(
+                        "{}/{}: '{}': {}",
+                        element_type, entry_name, descriptor, e
+                    ));
+                }
+                if let Some(hex) = tint.filter(|&tint| tint != helper::RARITY_TINT)
+                    && let Err(e) = helper::parse_dominant_tint(hex)
+                        .map_or_else(|| helper::parse_tint(hex).map(|_| ()), |d| d.map(|_| ()))
+                {
+                    problems.push(format!(
+                        "{}/{}: '{}': {}",
+                        element_type, entry_name, descriptor, e
+                    ));
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// Run every check, print a report and fail if anything was found
+pub fn run(
+    settings: &Settings,
+    data: &GameFolders,
+    source_folder: &Path,
+    fallback: LayerFallback,
+    platform: Platform,
+) -> anyhow::Result<()> {
+    let mut categories = [
+        (
+            "Settings",
+            check_settings(settings, data, fallback, platform),
+        ),
+        ("Missing source images", check_sources(data, source_folder)),
+        (
+            "Missing layers",
+            check_layers(settings, data, fallback, platform),
+        ),
+        ("Invalid colors, scales or conditions", check_colors(data)),
+    ];
+
+    let mut total = 0;
+    for (name, problems) in &mut categories {
+        problems.sort();
+        if problems.is_empty() {
+            println!("{}", format!("{}: OK", name).green());
+            continue;
+        }
+        total += problems.len();
+        println!("{}", format!("{} ({}):", name, problems.len()).red());
+        for problem in problems.iter() {
+            println!(" - {}", problem);
+        }
+    }
+
+    anyhow::ensure!(total == 0, "Validation failed with {} problem(s)", total);
+    println!("\n{}", "Configuration is valid!".green());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    /// Settings with the `Perks` layers in `dir/layers`, and the layering of
+    /// `Perks/a` drawn over `layer`, with every file but `missing.png`
+    fn config(dir: &Path, layer: &str) -> (Settings, GameFolders) {
+        for path in ["layers/bg.png", "source/Perks/a.png"] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            RgbaImage::new(2, 2).save(path).unwrap();
+        }
+        let settings = serde_json::json!({ "layers_location": { "Perks": dir.join("layers") } });
+        let settings = serde_json::from_value(settings).unwrap();
+        let data = serde_json::json!({ "Perks": { "a": [layer] } });
+        (settings, serde_json::from_value(data).unwrap())
+    }
+
+    fn validate(dir: &Path, layer: &str) -> anyhow::Result<()> {
+        let (settings, data) = config(dir, layer);
+        let platform = helper::detect_platform();
+        run(
+            &settings,
+            &data,
+            &dir.join("source"),
+            LayerFallback::default(),
+            platform,
+        )
+    }
+
+    #[test]
+    fn broken_config_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(validate(dir.path(), "bg#ff0000").is_ok());
+        assert!(validate(dir.path(), "missing").is_err());
+        assert!(validate(dir.path(), "bg#nothex").is_err());
+    }
+
+    #[test]
+    fn element_types_outside_the_layer_root_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("Perks")).unwrap();
+        let settings: Settings = serde_json::from_str("{}").unwrap();
+        let data = serde_json::json!({ "Perks": { "a": [] }, "Items": { "b": [] } });
+        let data: GameFolders = serde_json::from_value(data).unwrap();
+        let fallback = LayerFallback {
+            layer_root: Some(dir.path()),
+            default_folder: None,
+        };
+
+        let problems = check_settings(&settings, &data, fallback, helper::detect_platform());
+        assert_eq!(problems, ["'Items' has no entry in settings.json"]);
+    }
+}
@@ -0,0 +1,130 @@
+use colored::Colorize;
+use image::codecs::{jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder};
+use image::{ExtendedColorType, ImageDecoder, ImageFormat};
+use pack_creator::helper;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Bits per channel of a color type, `None` when the decoder doesn't know it
+fn bit_depth(color: ExtendedColorType) -> Option<u8> {
+    use ExtendedColorType::*;
+    match color {
+        L1 | La1 | Rgb1 | Rgba1 => Some(1),
+        L2 | La2 | Rgb2 | Rgba2 => Some(2),
+        L4 | La4 | Rgb4 | Rgba4 => Some(4),
+        A8 | L8 | La8 | Rgb8 | Rgba8 | Bgr8 | Bgra8 | Cmyk8 => Some(8),
+        L16 | La16 | Rgb16 | Rgba16 => Some(16),
+        Rgb32F | Rgba32F => Some(32),
+        _ => None,
+    }
+}
+
+/// Why a color type may not survive the conversion to 8-bit RGBA unchanged
+fn unusual(color: ExtendedColorType) -> Option<&'static str> {
+    match (color, bit_depth(color)) {
+        (ExtendedColorType::Cmyk8, _) => Some("CMYK is converted to RGB"),
+        (_, Some(16)) => Some("16-bit channels are truncated to 8 bits"),
+        (_, Some(32)) => Some("floating point channels are clamped to 8 bits"),
+        (_, None) => Some("unknown color type"),
+        _ => None,
+    }
+}
+
+/// Color type of an image file as stored, before the decoder converts it
+fn original_color_type(path: &Path) -> Result<ExtendedColorType, String> {
+    let open = || {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| e.to_string())
+    };
+    let format = image::io::Reader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| e.to_string())?
+        .format()
+        .ok_or("unrecognized image format")?;
+    match format {
+        ImageFormat::Png => PngDecoder::new(open()?).map(|d| d.original_color_type()),
+        ImageFormat::Jpeg => JpegDecoder::new(open()?).map(|d| d.original_color_type()),
+        ImageFormat::Tiff => TiffDecoder::new(open()?).map(|d| d.original_color_type()),
+        _ => return Err(format!("unsupported image format {:?}", format)),
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// Decode a file fully and describe its color type, `Err` if it can't be used as is
+fn inspect(path: &Path) -> Result<String, String> {
+    let color = original_color_type(path)?;
+    image::open(path).map_err(|e| e.to_string())?;
+    let description = match bit_depth(color) {
+        Some(bits) => format!("{:?}, {}-bit", color, bits),
+        None => format!("{:?}", color),
+    };
+    match unusual(color) {
+        Some(reason) => Err(format!("{} ({})", description, reason)),
+        None => Ok(description),
+    }
+}
+
+/// Open every existing asset, given with its kind, and report the ones that can't
+/// be decoded or whose color type is unusual. Missing files and URLs are skipped,
+/// rendering already reports them.
+pub fn run<'a>(assets: impl Iterator<Item = (&'a Path, &'a str)>) -> anyhow::Result<()> {
+    let mut checked = 0;
+    let mut problems = Vec::new();
+    for (path, kind) in assets {
+        let is_url = path.to_str().is_some_and(helper::is_url);
+        if is_url || !path.exists() {
+            continue;
+        }
+        checked += 1;
+        match inspect(path) {
+            Ok(description) => log::info!("{} {}: {}", kind, path.display(), description),
+            Err(e) => problems.push(format!("{} {}: {}", kind, path.display(), e)),
+        }
+    }
+
+    if problems.is_empty() {
+        println!("{}", format!("Verified {} input(s): OK", checked).green());
+        return Ok(());
+    }
+    println!(
+        "{}",
+        format!("Unusual or unreadable inputs ({}):", problems.len()).red()
+    );
+    for problem in &problems {
+        println!(" - {}", problem);
+    }
+    anyhow::bail!(
+        "{} of {} input(s) failed verification",
+        problems.len(),
+        checked
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn sixteen_bit_pngs_are_flagged() {
+        let dir = tempfile::tempdir().unwrap();
+        let deep = dir.path().join("deep.png");
+        ImageBuffer::<Rgba<u16>, _>::from_pixel(2, 2, Rgba([65535, 0, 0, 65535]))
+            .save(&deep)
+            .unwrap();
+        let plain = dir.path().join("plain.png");
+        image::RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]))
+            .save(&plain)
+            .unwrap();
+
+        let error = inspect(&deep).unwrap_err();
+        assert!(error.contains("16-bit"), "{}", error);
+        assert_eq!(inspect(&plain).unwrap(), "Rgba8, 8-bit");
+
+        let assets = [(deep.as_path(), "source"), (plain.as_path(), "layer")];
+        assert!(run(assets.into_iter()).is_err());
+        assert!(run([(plain.as_path(), "layer")].into_iter()).is_ok());
+    }
+}
@@ -0,0 +1,110 @@
+//! Watermark composited over every output, e.g. to mark preview releases
+
+use crate::helper;
+use image::{DynamicImage, GenericImageView, imageops, imageops::overlay};
+use std::str::FromStr;
+
+/// How the watermark is laid out on an output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WatermarkMode {
+    #[default]
+    Centered,
+    /// Repeated from the top-left corner until the output is covered
+    Tiled,
+}
+
+impl FromStr for WatermarkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "centered" | "center" => Ok(WatermarkMode::Centered),
+            "tiled" | "tile" => Ok(WatermarkMode::Tiled),
+            _ => Err(format!(
+                "Unknown watermark mode '{}', expected centered or tiled",
+                s
+            )),
+        }
+    }
+}
+
+/// A watermark decoded once and drawn over every output
+pub struct Watermark {
+    /// Already multiplied by the watermark opacity
+    pub image: DynamicImage,
+    pub mode: WatermarkMode,
+    /// Resize the watermark to fit each output, keeping its aspect ratio
+    pub fit: bool,
+}
+
+impl Watermark {
+    pub fn new(mut image: DynamicImage, mode: WatermarkMode, opacity: f32, fit: bool) -> Self {
+        helper::scale_alpha(&mut image, opacity);
+        Watermark { image, mode, fit }
+    }
+
+    /// Draw the watermark over an image
+    pub fn apply(&self, img: &mut DynamicImage) {
+        let (width, height) = img.dimensions();
+        let mark = if self.fit {
+            self.image
+                .resize(width, height, imageops::FilterType::Triangle)
+        } else {
+            self.image.clone()
+        };
+        let (mark_width, mark_height) = mark.dimensions();
+        if mark_width == 0 || mark_height == 0 {
+            return;
+        }
+
+        match self.mode {
+            WatermarkMode::Centered => {
+                let x = (width as i64 - mark_width as i64) / 2;
+                let y = (height as i64 - mark_height as i64) / 2;
+                overlay(img, &mark, x, y);
+            }
+            WatermarkMode::Tiled => {
+                for y in (0..height).step_by(mark_height as usize) {
+                    for x in (0..width).step_by(mark_width as usize) {
+                        overlay(img, &mark, x as i64, y as i64);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    /// Pixels of a 6x6 gray image where a 2x2 watermark, opaque red in its
+    /// top-left corner only, left its mark
+    fn marked(mode: WatermarkMode) -> Vec<(u32, u32)> {
+        let mut mark = RgbaImage::new(2, 2);
+        mark.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let watermark = Watermark::new(DynamicImage::ImageRgba8(mark), mode, 1.0, false);
+
+        let gray = Rgba([128, 128, 128, 255]);
+        let mut img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(6, 6, gray));
+        watermark.apply(&mut img);
+        img.pixels()
+            .filter(|(_, _, pixel)| *pixel != gray)
+            .map(|(x, y, pixel)| {
+                assert_eq!(pixel, Rgba([255, 0, 0, 255]));
+                (x, y)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn opaque_pixels_of_the_watermark_are_drawn() {
+        assert_eq!(marked(WatermarkMode::Centered), [(2, 2)]);
+        let tiled: Vec<(u32, u32)> = (0..6)
+            .step_by(2)
+            .flat_map(|y| (0..6).step_by(2).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(marked(WatermarkMode::Tiled), tiled);
+    }
+}